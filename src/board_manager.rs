@@ -0,0 +1,198 @@
+use std::net::IpAddr;
+use std::time::Duration;
+use serde_json::Value;
+use tracing::info;
+use crate::models::*;
+use crate::compiler::run_arduino_command;
+use crate::config::{ lock_config, save_setting };
+
+const ADDITIONAL_URLS_KEY: &str = "board_manager.additional_urls";
+// Largest package index downloaded to validate a URL
+const MAX_INDEX_SIZE: usize = 16 * 1024 * 1024;
+const INDEX_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_REDIRECTS: usize = 5;
+
+fn config_command(args: &[&str]) -> ArduinoCommand {
+    ArduinoCommand {
+        command: "config".to_string(),
+        args: args
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect(),
+    }
+}
+
+// List the third-party package index URLs arduino-cli knows about
+pub async fn list_additional_urls() -> Result<Vec<String>, String> {
+    let response = run_arduino_command(
+        &config_command(&["get", ADDITIONAL_URLS_KEY, "--format", "json"])
+    ).await;
+    if !response.success {
+        return Err(
+            response.error.unwrap_or_else(|| "Failed to read board manager URLs".to_string())
+        );
+    }
+    Ok(
+        match response.output_json {
+            Some(Value::Array(urls)) =>
+                urls
+                    .iter()
+                    .filter_map(|url| url.as_str().map(|url| url.to_string()))
+                    .collect(),
+            _ => vec![],
+        }
+    )
+}
+
+// Whether a host names this machine or the local network. URLs come from web pages,
+// which must not use the agent to reach services that are not public.
+fn is_local_host(host: &str) -> bool {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host.eq_ignore_ascii_case("localhost") || host.ends_with(".localhost") {
+        return true;
+    }
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) =>
+            ip.is_loopback() || ip.is_private() || ip.is_link_local() || ip.is_unspecified(),
+        Ok(IpAddr::V6(ip)) =>
+            ip.is_loopback() ||
+                ip.is_unspecified() ||
+                ip.is_unique_local() ||
+                ip.is_unicast_link_local() ||
+                ip.to_ipv4_mapped().is_some_and(|ip| is_local_host(&ip.to_string())),
+        Err(_) => false,
+    }
+}
+
+fn check_public_https(url: &reqwest::Url) -> Result<(), String> {
+    if url.scheme() != "https" {
+        return Err(format!("Invalid URL {}: only https is supported", url));
+    }
+    match url.host_str() {
+        Some(host) if !is_local_host(host) => Ok(()),
+        _ => Err(format!("Invalid URL {}: not a public host", url)),
+    }
+}
+
+// Check that a URL points to a package index such as
+// https://espressif.github.io/arduino-esp32/package_esp32_index.json
+pub async fn validate_index_url(url: &str) -> Result<(), String> {
+    let parsed = reqwest::Url::parse(url).map_err(|e| format!("Invalid URL {}: {}", url, e))?;
+    check_public_https(&parsed)?;
+    if !parsed.path().ends_with(".json") {
+        return Err(format!("Invalid URL {}: a package index is a .json file", url));
+    }
+
+    // Redirects have to stay on public https hosts as well
+    let client = reqwest::Client
+        ::builder()
+        .timeout(INDEX_TIMEOUT)
+        .redirect(
+            reqwest::redirect::Policy::custom(|attempt| {
+                let allowed = check_public_https(attempt.url()).is_ok();
+                if attempt.previous().len() >= MAX_REDIRECTS || !allowed {
+                    attempt.stop()
+                } else {
+                    attempt.follow()
+                }
+            })
+        )
+        .build()
+        .map_err(|e| format!("Failed to download {}: {}", url, e))?;
+    let mut response = client
+        .get(parsed)
+        .send().await
+        .map_err(|e| format!("Failed to download {}: {}", url, e))?;
+    if !response.status().is_success() {
+        return Err(format!("Failed to download {}: HTTP {}", url, response.status()));
+    }
+    let too_large = || format!("{} is larger than {} bytes", url, MAX_INDEX_SIZE);
+    if response.content_length().is_some_and(|length| length > (MAX_INDEX_SIZE as u64)) {
+        return Err(too_large());
+    }
+    let mut body = Vec::new();
+    while
+        let Some(chunk) = response
+            .chunk().await
+            .map_err(|e| format!("Failed to download {}: {}", url, e))?
+    {
+        if body.len() + chunk.len() > MAX_INDEX_SIZE {
+            return Err(too_large());
+        }
+        body.extend_from_slice(&chunk);
+    }
+    let index = serde_json
+        ::from_slice::<Value>(&body)
+        .map_err(|e| format!("{} is not a package index: {}", url, e))?;
+    if !index.get("packages").is_some_and(|packages| packages.is_array()) {
+        return Err(format!("{} is not a package index: missing packages", url));
+    }
+    Ok(())
+}
+
+// Add a validated package index URL and return the new list
+pub async fn add_additional_url(url: &str) -> Result<Vec<String>, String> {
+    let urls = list_additional_urls().await?;
+    if urls.iter().any(|existing| existing == url) {
+        return Ok(urls);
+    }
+    validate_index_url(url).await?;
+
+    // Not held while downloading, so other settings can change in the meantime
+    let _guard = lock_config().await;
+
+    let response = run_arduino_command(&config_command(&["add", ADDITIONAL_URLS_KEY, url])).await;
+    if !response.success {
+        return Err(response.error.unwrap_or_else(|| format!("Failed to add {}", url)));
+    }
+    info!("Added board manager URL {}", url);
+    let urls = list_additional_urls().await?;
+    save_setting(ADDITIONAL_URLS_KEY, serde_json::json!(urls))?;
+    Ok(urls)
+}
+
+// Remove a package index URL and return the new list
+pub async fn remove_additional_url(url: &str) -> Result<Vec<String>, String> {
+    let _guard = lock_config().await;
+    let urls = list_additional_urls().await?;
+    if !urls.iter().any(|existing| existing == url) {
+        return Err(format!("{} is not a board manager URL", url));
+    }
+
+    let response = run_arduino_command(
+        &config_command(&["remove", ADDITIONAL_URLS_KEY, url])
+    ).await;
+    if !response.success {
+        return Err(response.error.unwrap_or_else(|| format!("Failed to remove {}", url)));
+    }
+    info!("Removed board manager URL {}", url);
+    let urls = list_additional_urls().await?;
+    save_setting(ADDITIONAL_URLS_KEY, serde_json::json!(urls))?;
+    Ok(urls)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_local_hosts() {
+        for host in [
+            "localhost",
+            "api.localhost",
+            "127.0.0.1",
+            "10.0.0.8",
+            "192.168.1.1",
+            "169.254.169.254",
+            "0.0.0.0",
+            "[::1]",
+            "[fd00::1]",
+            "[::ffff:192.168.1.1]",
+        ] {
+            assert!(is_local_host(host), "{} should be local", host);
+        }
+        for host in ["espressif.github.io", "8.8.8.8", "[2606:4700::1111]"] {
+            assert!(!is_local_host(host), "{} should be public", host);
+        }
+    }
+}
@@ -0,0 +1,360 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use serialport::SerialPortType;
+use tracing::{ info, error };
+use crate::models::*;
+use crate::compiler::{ json_list, run_arduino_command };
+use crate::environment::get_agent_home;
+
+const BOARD_IDS_FILE: &str = "board_ids.json";
+// USB ids of the Plode boards, shipped with the agent
+static BUILTIN_BOARD_IDS: &str = include_str!("../resource/board_ids.json");
+
+// Outcome of working out which board is connected to a port
+pub enum FqbnResolution {
+    Resolved(String),
+    // Several boards match and none of them is the sketch's default
+    Ambiguous(Vec<BoardCandidate>),
+    NotFound,
+}
+
+// Path of the user's board ids, which extend the built-in table
+pub fn get_board_ids_path() -> PathBuf {
+    get_agent_home().join(BOARD_IDS_FILE)
+}
+
+fn builtin_board_ids() -> Vec<BoardId> {
+    let mut board_ids: Vec<BoardId> = serde_json
+        ::from_str(BUILTIN_BOARD_IDS)
+        .unwrap_or_else(|e| {
+            error!(?e, "Invalid built-in board ids");
+            vec![]
+        });
+    for board_id in &mut board_ids {
+        board_id.builtin = true;
+    }
+    board_ids
+}
+
+fn read_user_board_ids() -> Vec<BoardId> {
+    let path = get_board_ids_path();
+    let mut board_ids: Vec<BoardId> = match fs::read_to_string(&path) {
+        Ok(content) =>
+            serde_json::from_str(&content).unwrap_or_else(|e| {
+                info!("Ignoring invalid {}: {}", path.display(), e);
+                vec![]
+            }),
+        Err(_) => vec![],
+    };
+    for board_id in &mut board_ids {
+        board_id.builtin = false;
+    }
+    board_ids
+}
+
+fn same_usb_ids(a: &BoardId, b: &BoardId) -> bool {
+    parse_usb_id(&a.vid) == parse_usb_id(&b.vid) &&
+        a.pid.as_deref().and_then(parse_usb_id) == b.pid.as_deref().and_then(parse_usb_id)
+}
+
+// Put entries on top of a table, replacing the entries for the same USB ids
+fn merge_board_ids(mut board_ids: Vec<BoardId>, entries: Vec<BoardId>) -> Vec<BoardId> {
+    for entry in entries {
+        board_ids.retain(|existing| !same_usb_ids(existing, &entry));
+        board_ids.push(entry);
+    }
+    board_ids
+}
+
+// The built-in board ids with the user's on top
+pub fn read_board_ids() -> Vec<BoardId> {
+    merge_board_ids(builtin_board_ids(), read_user_board_ids())
+}
+
+// Add a board to the user's table, replacing an entry for the same USB ids
+pub fn add_board_id(mut board_id: BoardId) -> Result<Vec<BoardId>, String> {
+    if parse_usb_id(&board_id.vid).is_none() {
+        return Err(format!("Invalid vid {}", board_id.vid));
+    }
+    if let Some(pid) = &board_id.pid && parse_usb_id(pid).is_none() {
+        return Err(format!("Invalid pid {}", pid));
+    }
+    board_id.builtin = false;
+    let board_ids = merge_board_ids(read_user_board_ids(), vec![board_id]);
+    let content = serde_json::to_string_pretty(&board_ids).map_err(|e| e.to_string())?;
+    fs::write(get_board_ids_path(), content).map_err(|e|
+        format!("Failed to write board ids: {}", e)
+    )?;
+    Ok(read_board_ids())
+}
+
+// The `vendor:arch:board` part of an FQBN, without board options
+pub fn fqbn_base(fqbn: &str) -> &str {
+    match fqbn.match_indices(':').nth(2) {
+        Some((index, _)) => &fqbn[..index],
+        None => fqbn,
+    }
+}
+
+// Build a full FQBN such as `esp32:esp32:esp32:PartitionScheme=huge_app` from a
+// board and the selected menu options. Options already in `fqbn` are kept unless
+// they are selected again.
+pub fn build_fqbn(fqbn: &str, board_options: &BTreeMap<String, String>) -> Result<String, String> {
+    let base = fqbn_base(fqbn);
+    if base.split(':').count() != 3 || base.split(':').any(|part| part.is_empty()) {
+        return Err(format!("Invalid fqbn {}", fqbn));
+    }
+    if board_options.is_empty() {
+        return Ok(fqbn.to_string());
+    }
+
+    let mut options = BTreeMap::new();
+    if let Some(existing) = fqbn.strip_prefix(base).and_then(|rest| rest.strip_prefix(':')) {
+        for option in existing.split(',').filter(|option| !option.is_empty()) {
+            match option.split_once('=') {
+                Some((key, value)) => {
+                    options.insert(key.to_string(), value.to_string());
+                }
+                None => {
+                    return Err(format!("Invalid board option {} in fqbn {}", option, fqbn));
+                }
+            }
+        }
+    }
+    for (key, value) in board_options {
+        let valid = |text: &str| !text.is_empty() && !text.contains([':', ',', '=']);
+        if !valid(key) || !valid(value) {
+            return Err(format!("Invalid board option {}={}", key, value));
+        }
+        options.insert(key.clone(), value.clone());
+    }
+
+    let options = options
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect::<Vec<_>>()
+        .join(",");
+    Ok(format!("{}:{}", base, options))
+}
+
+// Get the typed `board details` of a board
+pub async fn get_board_details(fqbn: &str) -> Result<BoardDetails, String> {
+    let command = ArduinoCommand {
+        command: "board".to_string(),
+        args: vec![
+            "details".to_string(),
+            "--fqbn".to_string(),
+            fqbn.to_string(),
+            "--format".to_string(),
+            "json".to_string()
+        ],
+    };
+    let response = run_arduino_command(&command).await;
+    if !response.success {
+        return Err(response.error.unwrap_or_else(|| format!("Failed to get details of {}", fqbn)));
+    }
+    let json = response.output_json.ok_or_else(|| "Invalid board details output".to_string())?;
+    serde_json::from_value(json).map_err(|e| format!("Invalid board details output: {}", e))
+}
+
+// Parse a USB id such as "0xB1B0" or "b1b0"
+fn parse_usb_id(id: &str) -> Option<u16> {
+    let id = id.trim();
+    let hex = id.strip_prefix("0x").or_else(|| id.strip_prefix("0X")).unwrap_or(id);
+    u16::from_str_radix(hex, 16).ok()
+}
+
+// Look up the USB vendor and product id of a serial port
+fn port_usb_ids(port: &str, board_list_port: Option<&serde_json::Value>) -> Option<(u16, u16)> {
+    // arduino-cli reports them as port properties
+    if let Some(properties) = board_list_port.and_then(|p| p.get("properties")) {
+        let vid = properties
+            .get("vid")
+            .and_then(|v| v.as_str())
+            .and_then(parse_usb_id);
+        let pid = properties
+            .get("pid")
+            .and_then(|v| v.as_str())
+            .and_then(parse_usb_id);
+        if let (Some(vid), Some(pid)) = (vid, pid) {
+            return Some((vid, pid));
+        }
+    }
+    // Fall back to the operating system's view of the port
+    serialport
+        ::available_ports()
+        .unwrap_or_default()
+        .into_iter()
+        .find(|available_port| available_port.port_name == port)
+        .and_then(|available_port| {
+            match available_port.port_type {
+                SerialPortType::UsbPort(usb) => Some((usb.vid, usb.pid)),
+                _ => None,
+            }
+        })
+}
+
+// Work out the FQBN of the board on `port` from `board list` matching and the
+// board id table, using the sketch's remembered default to settle ties
+pub async fn resolve_fqbn(port: &str, default_fqbn: Option<&str>) -> FqbnResolution {
+    let command = ArduinoCommand {
+        command: "board".to_string(),
+        args: vec!["list".to_string(), "--format".to_string(), "json".to_string()],
+    };
+    let board_list = run_arduino_command(&command).await.output_json.unwrap_or_default();
+    let detected_port = json_list(&board_list, "detected_ports")
+        .into_iter()
+        .find(|detected| {
+            detected
+                .get("port")
+                .and_then(|p| p.get("address"))
+                .and_then(|a| a.as_str()) == Some(port)
+        });
+
+    let mut candidates: Vec<BoardCandidate> = vec![];
+    let mut add_candidate = |fqbn: &str, name: &str, source: &str| {
+        if !fqbn.is_empty() && !candidates.iter().any(|c| c.fqbn == fqbn) {
+            candidates.push(BoardCandidate {
+                fqbn: fqbn.to_string(),
+                name: name.to_string(),
+                source: source.to_string(),
+            });
+        }
+    };
+
+    if let Some(detected_port) = detected_port {
+        let boards = detected_port
+            .get("matching_boards")
+            .and_then(|b| b.as_array())
+            .cloned()
+            .unwrap_or_default();
+        for board in boards {
+            let fqbn = board
+                .get("fqbn")
+                .and_then(|f| f.as_str())
+                .unwrap_or_default();
+            let name = board
+                .get("name")
+                .and_then(|n| n.as_str())
+                .unwrap_or_default();
+            add_candidate(fqbn, name, "board-list");
+        }
+    }
+
+    if let Some((vid, pid)) = port_usb_ids(port, detected_port.and_then(|d| d.get("port"))) {
+        for board_id in read_board_ids() {
+            let vid_matches = parse_usb_id(&board_id.vid) == Some(vid);
+            let pid_matches = match &board_id.pid {
+                Some(board_pid) => parse_usb_id(board_pid) == Some(pid),
+                None => true,
+            };
+            if vid_matches && pid_matches {
+                add_candidate(&board_id.fqbn, &board_id.name, "board-ids");
+            }
+        }
+    }
+
+    info!(?port, candidates = candidates.len(), "Resolved board candidates");
+    match (candidates.len(), default_fqbn) {
+        (1, _) => FqbnResolution::Resolved(candidates.remove(0).fqbn),
+        (0, Some(default_fqbn)) => FqbnResolution::Resolved(default_fqbn.to_string()),
+        (0, None) => FqbnResolution::NotFound,
+        (_, Some(default_fqbn)) if
+            candidates.iter().any(|c| c.fqbn == fqbn_base(default_fqbn))
+        => {
+            FqbnResolution::Resolved(default_fqbn.to_string())
+        }
+        _ => FqbnResolution::Ambiguous(candidates),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn appends_sorted_board_options() {
+        let selected = options(&[("UploadSpeed", "115200"), ("PartitionScheme", "huge_app")]);
+        assert_eq!(
+            build_fqbn("esp32:esp32:esp32", &selected).unwrap(),
+            "esp32:esp32:esp32:PartitionScheme=huge_app,UploadSpeed=115200"
+        );
+        assert_eq!(build_fqbn("arduino:avr:uno", &options(&[])).unwrap(), "arduino:avr:uno");
+    }
+
+    #[test]
+    fn replaces_options_already_in_the_fqbn() {
+        let selected = options(&[("cpu", "atmega328old")]);
+        assert_eq!(
+            build_fqbn("arduino:avr:nano:cpu=atmega328,clock=16", &selected).unwrap(),
+            "arduino:avr:nano:clock=16,cpu=atmega328old"
+        );
+        assert_eq!(
+            build_fqbn("arduino:avr:nano:", &selected).unwrap(),
+            "arduino:avr:nano:cpu=atmega328old"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_fqbns_and_options() {
+        let selected = options(&[("cpu", "atmega328")]);
+        assert!(build_fqbn("arduino:avr", &selected).is_err());
+        assert!(build_fqbn("arduino::nano", &selected).is_err());
+        assert!(build_fqbn("arduino:avr:nano:cpu", &selected).is_err());
+        assert!(build_fqbn("arduino:avr:nano", &options(&[("cpu", "a,b=c")])).is_err());
+        assert!(build_fqbn("arduino:avr:nano", &options(&[("", "atmega328")])).is_err());
+    }
+
+    fn board_id(vid: &str, pid: Option<&str>, fqbn: &str) -> BoardId {
+        BoardId {
+            vid: vid.to_string(),
+            pid: pid.map(|pid| pid.to_string()),
+            fqbn: fqbn.to_string(),
+            name: fqbn.to_string(),
+            builtin: false,
+        }
+    }
+
+    #[test]
+    fn ships_valid_plode_board_ids() {
+        let board_ids = builtin_board_ids();
+        assert!(!board_ids.is_empty());
+        for board_id in board_ids {
+            assert!(board_id.builtin);
+            assert!(parse_usb_id(&board_id.vid).is_some());
+            assert!(board_id.pid.as_deref().is_none_or(|pid| parse_usb_id(pid).is_some()));
+            assert!(build_fqbn(&board_id.fqbn, &BTreeMap::new()).is_ok());
+        }
+    }
+
+    #[test]
+    fn user_board_ids_replace_entries_for_the_same_usb_ids() {
+        let builtin = vec![
+            board_id("0xB1B0", None, "plode:a:any"),
+            board_id("0xB1B0", Some("0x0001"), "plode:a:one")
+        ];
+        let merged = merge_board_ids(
+            builtin,
+            vec![board_id("b1b0", Some("0001"), "user:a:one"), board_id("0x1A86", None, "user:b:any")]
+        );
+        let fqbns = merged
+            .iter()
+            .map(|board_id| board_id.fqbn.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(fqbns, vec!["plode:a:any", "user:a:one", "user:b:any"]);
+    }
+
+    #[test]
+    fn strips_board_options_from_the_base() {
+        assert_eq!(fqbn_base("esp32:esp32:esp32:PartitionScheme=huge_app"), "esp32:esp32:esp32");
+        assert_eq!(fqbn_base("arduino:avr:uno"), "arduino:avr:uno");
+    }
+}
@@ -0,0 +1,350 @@
+use std::path::{ Path, PathBuf };
+use std::fs;
+use std::time::SystemTime;
+use chrono::{ DateTime, Utc };
+use tracing::{ info, error };
+use axum::{
+    extract::{ Multipart, Path as UrlPath },
+    http::{ header, StatusCode },
+    response::{ IntoResponse, Json as ResponseJson },
+};
+use crate::models::*;
+use crate::sketch_paths::existing_sketch_dir;
+use crate::socketio::get_build_directory;
+
+const BUILD_INFO_FILE: &str = "build_info.json";
+const WARNING_LEVELS: [&str; 4] = ["none", "default", "more", "all"];
+// Build properties a client may set. Any other property, such as a recipe or tool
+// command, could make the next compile run arbitrary commands.
+const BUILD_PROPERTY_KEYS: [&str; 6] = [
+    "build.extra_flags",
+    "build.defines",
+    "compiler.c.extra_flags",
+    "compiler.cpp.extra_flags",
+    "compiler.S.extra_flags",
+    "compiler.c.elf.extra_flags",
+];
+// Compiler flags that load or run other programs, refused in property values
+const UNSAFE_FLAG_PREFIXES: [&str; 5] = ["-wrapper", "-fplugin", "-specs", "-B", "@"];
+
+// Directory arduino-cli keeps its intermediate build files in
+pub fn get_build_path(sketch_name: &str) -> PathBuf {
+    get_build_directory(sketch_name).join("build")
+}
+
+// Directory the stored build artifacts (hex/bin/elf/map) of a sketch are exported to
+pub fn get_output_path(sketch_name: &str) -> PathBuf {
+    get_build_directory(sketch_name).join("output")
+}
+
+// Directory binaries uploaded over HTTP are kept in until they are flashed
+pub fn get_binaries_directory() -> PathBuf {
+    let current_dir = std::env::current_dir().unwrap_or_else(|_| Path::new(".").to_path_buf());
+    current_dir.join("binaries")
+}
+
+// A `key=value` build property with an allowed key and no flags that run other programs
+fn check_build_property(property: &str) -> Result<(), String> {
+    let Some((key, value)) = property.split_once('=') else {
+        return Err(format!("Invalid build property {}, expected key=value", property));
+    };
+    if !BUILD_PROPERTY_KEYS.contains(&key) {
+        return Err(
+            format!("Build property {} is not allowed, allowed are {:?}", key, BUILD_PROPERTY_KEYS)
+        );
+    }
+    let unsafe_flag = value
+        .split_whitespace()
+        .find(|flag| UNSAFE_FLAG_PREFIXES.iter().any(|prefix| flag.starts_with(prefix)));
+    if let Some(flag) = unsafe_flag {
+        return Err(format!("Flag {} is not allowed in build property {}", flag, key));
+    }
+    Ok(())
+}
+
+// Build the arduino-cli compile flags for the given options
+pub fn compile_args(
+    fqbn: Option<&str>,
+    options: &CompileOptions,
+    build_path: &Path,
+    output_path: &Path
+) -> Result<Vec<String>, String> {
+    let mut args = vec![];
+
+    if let Some(fqbn) = fqbn {
+        args.push("--fqbn".to_string());
+        args.push(fqbn.to_string());
+    }
+    for property in &options.build_properties {
+        check_build_property(property)?;
+        args.push("--build-property".to_string());
+        args.push(property.clone());
+    }
+    if let Some(warnings) = &options.warnings {
+        if !WARNING_LEVELS.contains(&warnings.as_str()) {
+            return Err(
+                format!("Invalid warning level {}, expected one of {:?}", warnings, WARNING_LEVELS)
+            );
+        }
+        args.push("--warnings".to_string());
+        args.push(warnings.clone());
+    }
+    if options.optimize_for_debug {
+        args.push("--optimize-for-debug".to_string());
+    }
+    if options.verbose {
+        args.push("--verbose".to_string());
+    }
+    if let Some(jobs) = options.jobs {
+        args.push("--jobs".to_string());
+        args.push(jobs.to_string());
+    }
+    if options.export_binaries {
+        args.push("--export-binaries".to_string());
+    }
+
+    // Build into directories the agent owns so a cancelled build can be removed
+    // and the artifacts of the last build can be downloaded or uploaded again
+    args.push("--build-path".to_string());
+    args.push(build_path.to_string_lossy().to_string());
+    args.push("--output-dir".to_string());
+    args.push(output_path.to_string_lossy().to_string());
+
+    Ok(args)
+}
+
+// Empty a directory so it only holds the output of the next job
+pub fn reset_dir(path: &Path) {
+    if path.exists() && let Err(e) = fs::remove_dir_all(path) {
+        error!(?e, ?path, "Failed to clear directory");
+    }
+    if let Err(e) = fs::create_dir_all(path) {
+        error!(?e, ?path, "Failed to create directory");
+    }
+}
+
+// List the artifacts of the last build of a sketch
+pub fn list_artifacts(sketch_name: &str) -> Vec<FileResponse> {
+    let output_path = get_output_path(sketch_name);
+    match crate::usb::list_directory_recursive(&output_path.to_string_lossy()) {
+        Ok(files) =>
+            files
+                .into_iter()
+                .filter(|file| file.is_file)
+                .collect(),
+        Err(e) => {
+            info!(?e, "Failed to list build artifacts");
+            vec![]
+        }
+    }
+}
+
+// Read what is known about the last build of a sketch
+pub fn read_build_info(sketch_name: &str) -> Option<BuildInfo> {
+    let path = get_build_directory(sketch_name).join(BUILD_INFO_FILE);
+    let content = fs::read_to_string(path).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+// Newest modification time of the files below a directory
+fn newest_modification(dir: &Path) -> Option<SystemTime> {
+    let mut newest = None;
+    for entry in fs::read_dir(dir).ok()?.flatten() {
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        let modified = if metadata.is_dir() {
+            newest_modification(&entry.path())
+        } else {
+            metadata.modified().ok()
+        };
+        newest = newest.max(modified);
+    }
+    newest
+}
+
+// Whether the last build of a sketch can be uploaded as it is: it succeeded, was built
+// for the same board and profile without extra build properties, and no file of the
+// sketch changed after it finished
+pub fn is_build_current(sketch_name: &str, fqbn: &str, profile: Option<&str>) -> bool {
+    let Some(build_info) = read_build_info(sketch_name) else {
+        return false;
+    };
+    if
+        !build_info.success ||
+        build_info.fqbn.as_deref() != Some(fqbn) ||
+        build_info.profile.as_deref() != profile ||
+        !build_info.build_properties.is_empty() ||
+        !get_output_path(sketch_name).is_dir()
+    {
+        return false;
+    }
+    let Ok(finished_at) = DateTime::parse_from_rfc3339(&build_info.finished_at) else {
+        return false;
+    };
+    let Ok(sketch_dir) = existing_sketch_dir(sketch_name) else {
+        return false;
+    };
+    match newest_modification(&sketch_dir) {
+        Some(modified) => DateTime::<Utc>::from(modified) < finished_at,
+        None => false,
+    }
+}
+
+pub fn write_build_info(sketch_name: &str, build_info: &BuildInfo) {
+    let build_dir = get_build_directory(sketch_name);
+    let result = fs
+        ::create_dir_all(&build_dir)
+        .and_then(|_| {
+            let content = serde_json::to_string_pretty(build_info).unwrap_or_default();
+            fs::write(build_dir.join(BUILD_INFO_FILE), content)
+        });
+    if let Err(e) = result {
+        error!(?e, "Failed to write build info");
+    }
+}
+
+// Record the result of a compile and attach the produced artifacts to the response
+pub fn finish_compile(
+    sketch_name: &str,
+    fqbn: Option<String>,
+    profile: Option<String>,
+    build_properties: Vec<String>,
+    response: CommandResponse
+) -> CommandResponse {
+    let artifacts = if response.success { list_artifacts(sketch_name) } else { vec![] };
+
+    let build_info = BuildInfo {
+        sketch_name: sketch_name.to_string(),
+        fqbn,
+        profile,
+        build_properties,
+        success: response.success,
+        finished_at: chrono::Utc::now().to_rfc3339(),
+        artifacts: artifacts
+            .iter()
+            .map(|artifact| artifact.filename.clone())
+            .collect(),
+    };
+    write_build_info(sketch_name, &build_info);
+
+    CommandResponse {
+        files: Some(artifacts),
+        ..response
+    }
+}
+
+// A single path segment that cannot be used to leave its parent directory
+pub fn is_plain_name(name: &str) -> bool {
+    !name.is_empty() &&
+        name != "." &&
+        name != ".." &&
+        !name.contains(['/', '\\', ':'])
+}
+
+// Handler for downloading a build artifact, e.g. GET /artifacts/MySketch/MySketch.ino.hex
+pub async fn download_artifact(
+    UrlPath((sketch_name, file_name)): UrlPath<(String, String)>
+) -> Result<impl IntoResponse, StatusCode> {
+    if !is_plain_name(&sketch_name) || !is_plain_name(&file_name) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let artifact_path = get_output_path(&sketch_name).join(&file_name);
+    let data = tokio::fs::read(&artifact_path).await.map_err(|_| StatusCode::NOT_FOUND)?;
+    info!("Serving build artifact {}", artifact_path.display());
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/octet-stream".to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", file_name)),
+        ],
+        data,
+    ))
+}
+
+// Handler for uploading a prebuilt binary (hex/bin/uf2/...) that can then be
+// flashed with the upload-binary command without compiling anything
+pub async fn upload_binary(
+    mut multipart: Multipart
+) -> Result<ResponseJson<BinaryUploadResponse>, StatusCode> {
+    let mut file_name = String::new();
+    let mut file_path: Option<String> = None;
+
+    while let Some(field) = multipart.next_field().await.map_err(|_| StatusCode::BAD_REQUEST)? {
+        // Prefer the uploaded file name, fall back to the form field name
+        let name = field
+            .file_name()
+            .or(field.name())
+            .unwrap_or("")
+            .to_string();
+        if !is_plain_name(&name) {
+            info!("Rejecting binary with invalid file name: {}", name);
+            return Err(StatusCode::BAD_REQUEST);
+        }
+
+        let data = field.bytes().await.map_err(|e| {
+            info!("Error reading bytes from field: {:?}", e);
+            StatusCode::BAD_REQUEST
+        })?;
+        info!("Successfully read {} bytes for binary '{}'", data.len(), name);
+
+        let binaries_dir = get_binaries_directory();
+        tokio::fs
+            ::create_dir_all(&binaries_dir).await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        let path = binaries_dir.join(&name);
+        tokio::fs::write(&path, &data).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+        file_name = name;
+        file_path = Some(path.to_string_lossy().to_string());
+    }
+
+    if file_name.is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    Ok(
+        ResponseJson(BinaryUploadResponse {
+            success: true,
+            message: format!("Binary '{}' uploaded successfully", file_name),
+            file_name,
+            file_path,
+        })
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args_with(properties: &[&str]) -> Result<Vec<String>, String> {
+        let options = CompileOptions {
+            build_properties: properties.iter().map(|property| property.to_string()).collect(),
+            ..Default::default()
+        };
+        compile_args(None, &options, Path::new("build"), Path::new("output"))
+    }
+
+    #[test]
+    fn passes_allowed_build_properties() {
+        let args = args_with(&["build.extra_flags=-DDEBUG -DLEVEL=2"]).unwrap();
+        assert_eq!(args[..2], ["--build-property", "build.extra_flags=-DDEBUG -DLEVEL=2"]);
+        assert!(args_with(&["compiler.cpp.extra_flags="]).is_ok());
+    }
+
+    #[test]
+    fn refuses_other_build_properties() {
+        assert!(args_with(&["recipe.hooks.prebuild.1.pattern=touch /tmp/x"]).is_err());
+        assert!(args_with(&["tools.avrdude.cmd=sh"]).is_err());
+        assert!(args_with(&["compiler.path=/tmp/"]).is_err());
+        assert!(args_with(&["build.extra_flags"]).is_err());
+    }
+
+    #[test]
+    fn refuses_flags_that_run_programs() {
+        assert!(args_with(&["build.extra_flags=-DX -wrapper sh,-c,id"]).is_err());
+        assert!(args_with(&["compiler.c.extra_flags=-fplugin=/tmp/p.so"]).is_err());
+        assert!(args_with(&["compiler.cpp.extra_flags=@/tmp/flags"]).is_err());
+        assert!(args_with(&["build.defines=-B/tmp/bin"]).is_err());
+    }
+}
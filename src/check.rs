@@ -0,0 +1,359 @@
+use std::collections::{ BTreeMap, HashMap };
+use std::fs;
+use std::path::{ Path, PathBuf };
+use std::process::Stdio;
+use std::sync::{ Arc, Mutex, OnceLock };
+use serde_json::Value;
+use tokio::process::Command as TokioCommand;
+use tokio::sync::watch;
+use tokio::time::{ sleep, Duration };
+use tracing::{ info, error };
+use crate::models::*;
+use crate::compiler::run_arduino_command;
+use crate::compilation_database::ensure_compilation_database;
+use crate::examples::copy_dir_recursive;
+use crate::sketch_paths::existing_sketch_dir;
+use crate::socketio::get_build_directory;
+
+// How long to wait for more edits before checking
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_millis(300);
+
+// Per sketch: the newest check request, and a lock so checks do not share the overlay
+struct CheckState {
+    generation: watch::Sender<u64>,
+    lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+static CHECKS: OnceLock<Mutex<HashMap<String, CheckState>>> = OnceLock::new();
+
+// Outcome of a check request
+pub enum CheckResult {
+    Checked(Vec<Diagnostic>),
+    // A newer check of the same sketch came in first
+    Superseded,
+}
+
+// Register a new check of a sketch, superseding the running and pending ones
+fn start_check(sketch_name: &str) -> (u64, watch::Receiver<u64>, Arc<tokio::sync::Mutex<()>>) {
+    let checks = CHECKS.get_or_init(|| Mutex::new(HashMap::new()));
+    let mut checks = match checks.lock() {
+        Ok(checks) => checks,
+        Err(e) => {
+            error!(?e, "Failed to lock checks");
+            let (_, receiver) = watch::channel(0);
+            return (0, receiver, Arc::new(tokio::sync::Mutex::new(())));
+        }
+    };
+    let state = checks.entry(sketch_name.to_string()).or_insert_with(|| CheckState {
+        generation: watch::channel(0).0,
+        lock: Arc::new(tokio::sync::Mutex::new(())),
+    });
+    let generation = *state.generation.borrow() + 1;
+    state.generation.send_replace(generation);
+    (generation, state.generation.subscribe(), Arc::clone(&state.lock))
+}
+
+// Resolves once a newer check of the sketch was requested
+async fn superseded(generation: u64, mut receiver: watch::Receiver<u64>) {
+    let _ = receiver.wait_for(|current| *current != generation).await;
+}
+
+// Check the in-editor content of a sketch for syntax errors. Only the sketch itself
+// is preprocessed and parsed with `-fsyntax-only`: the core and libraries are
+// neither compiled nor linked. Edits arriving within `debounce` replace this check.
+pub async fn check_sketch(
+    sketch_name: &str,
+    fqbn: &str,
+    files: BTreeMap<String, String>,
+    debounce: Duration
+) -> Result<CheckResult, String> {
+    let (generation, receiver, lock) = start_check(sketch_name);
+    let _guard = tokio::select! {
+        _ = superseded(generation, receiver.clone()) => {
+            return Ok(CheckResult::Superseded);
+        }
+        guard = async {
+            sleep(debounce).await;
+            lock.lock().await
+        } => guard,
+    };
+    if *receiver.borrow() != generation {
+        return Ok(CheckResult::Superseded);
+    }
+    // A started check is not dropped halfway, as it may be building the compilation
+    // database as a job. A newer check waits for it and then reports its own result.
+    let diagnostics = run_check(sketch_name, fqbn, files).await?;
+    if *receiver.borrow() != generation {
+        return Ok(CheckResult::Superseded);
+    }
+    Ok(CheckResult::Checked(diagnostics))
+}
+
+// Copy the sketch to `overlay_dir` with the editor content written over its files
+fn write_overlay(
+    sketch_dir: &Path,
+    overlay_dir: &Path,
+    files: &BTreeMap<String, String>
+) -> Result<(), String> {
+    if overlay_dir.exists() {
+        fs::remove_dir_all(overlay_dir).map_err(|e| format!("Failed to clear check: {}", e))?;
+    }
+    copy_dir_recursive(sketch_dir, overlay_dir).map_err(|e| {
+        format!("Failed to copy sketch {}: {}", sketch_dir.display(), e)
+    })?;
+    for (file_name, content) in files {
+        fs::write(overlay_dir.join(file_name), content).map_err(|e| {
+            format!("Failed to write {}: {}", file_name, e)
+        })?;
+    }
+    Ok(())
+}
+
+async fn run_check(
+    sketch_name: &str,
+    fqbn: &str,
+    files: BTreeMap<String, String>
+) -> Result<Vec<Diagnostic>, String> {
+    // The handler already resolved the sketch, this only fails if it was removed since
+    let sketch_dir = existing_sketch_dir(sketch_name).map_err(|error| error.message)?;
+
+    // Overlay the editor content on a copy of the sketch
+    let check_dir = get_build_directory(sketch_name).join("check");
+    let overlay_dir = check_dir.join(sketch_name);
+    let copy_dir = overlay_dir.clone();
+    tokio::task
+        ::spawn_blocking(move || write_overlay(&sketch_dir, &copy_dir, &files)).await
+        .unwrap_or_else(|e| Err(format!("Check failed: {}", e)))?;
+
+    // Built from the overlay, so headers the editor content includes before it is
+    // saved are on the include path
+    let database = ensure_compilation_database(
+        sketch_name,
+        &overlay_dir,
+        &check_dir.join("compdb"),
+        fqbn
+    ).await?;
+
+    // Merge the .ino files and generate prototypes like a real build does
+    let preprocess = ArduinoCommand {
+        command: "compile".to_string(),
+        args: vec![
+            "--fqbn".to_string(),
+            fqbn.to_string(),
+            "--preprocess".to_string(),
+            "--build-path".to_string(),
+            check_dir.join("build").to_string_lossy().to_string(),
+            overlay_dir.to_string_lossy().to_string()
+        ],
+    };
+    let response = run_arduino_command(&preprocess).await;
+    if !response.success {
+        // Preprocessing fails on broken includes, report that as the diagnostic
+        let output = response.error.unwrap_or_default();
+        let diagnostics = parse_diagnostics(&output, &overlay_dir);
+        return if diagnostics.is_empty() { Err(output) } else { Ok(diagnostics) };
+    }
+    let preprocessed = check_dir.join(format!("{}.ino.cpp", sketch_name));
+    fs::write(&preprocessed, &response.output).map_err(|e| {
+        format!("Failed to write {}: {}", preprocessed.display(), e)
+    })?;
+
+    let (directory, arguments) = syntax_check_command(&database, &preprocessed, &overlay_dir)?;
+    let Some((program, arguments)) = arguments.split_first() else {
+        return Err("Empty compile command".to_string());
+    };
+    let output = TokioCommand::new(program)
+        .args(arguments)
+        .current_dir(directory)
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output().await
+        .map_err(|e| format!("Failed to run {}: {}", program, e))?;
+    let diagnostics = parse_diagnostics(&String::from_utf8_lossy(&output.stderr), &overlay_dir);
+    info!("Checked {}: {} diagnostics", sketch_name, diagnostics.len());
+    Ok(diagnostics)
+}
+
+// Turn the compile command of the sketch's .ino.cpp into a syntax-only check of `source`
+fn syntax_check_command(
+    database: &Value,
+    source: &Path,
+    include_dir: &Path
+) -> Result<(PathBuf, Vec<String>), String> {
+    let entry = database
+        .get("compile_commands")
+        .and_then(|commands| commands.as_array())
+        .and_then(|commands| {
+            commands.iter().find(|entry| {
+                entry
+                    .get("file")
+                    .and_then(|file| file.as_str())
+                    .is_some_and(|file| file.ends_with(".ino.cpp"))
+            })
+        })
+        .ok_or_else(|| "The compilation database has no entry for the sketch".to_string())?;
+    let directory = entry
+        .get("directory")
+        .and_then(|directory| directory.as_str())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("."));
+    let file = entry
+        .get("file")
+        .and_then(|file| file.as_str())
+        .unwrap_or_default();
+    let original = entry
+        .get("arguments")
+        .and_then(|arguments| arguments.as_array())
+        .ok_or_else(|| "The compile command has no arguments".to_string())?;
+
+    let mut arguments = vec![];
+    let mut original = original.iter().filter_map(|argument| argument.as_str());
+    while let Some(argument) = original.next() {
+        match argument {
+            // No object file and no dependency file, just the diagnostics
+            "-o" | "-MF" => {
+                original.next();
+            }
+            "-MMD" | "-MD" | "-c" => {}
+            _ if argument == file => arguments.push(source.to_string_lossy().to_string()),
+            _ => arguments.push(argument.to_string()),
+        }
+    }
+    // Headers next to the sketch are found relative to the edited copy
+    arguments.insert(1, format!("-I{}", include_dir.display()));
+    arguments.insert(1, "-fsyntax-only".to_string());
+    Ok((directory, arguments))
+}
+
+// Parse GCC output such as `/path/Blink.ino:12:5: error: 'foo' was not declared`
+pub fn parse_diagnostics(output: &str, sketch_dir: &Path) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    for line in output.lines() {
+        let Some((location, severity, message)) = [
+            ": fatal error: ",
+            ": error: ",
+            ": warning: ",
+            ": note: ",
+        ]
+            .iter()
+            .find_map(|marker| {
+                let (location, message) = line.split_once(marker)?;
+                let severity = marker.trim_matches([':', ' ']).trim_start_matches("fatal ");
+                Some((location, severity, message))
+            }) else {
+            continue;
+        };
+
+        // The location is `file:line:column` or `file:line`, and the file may contain colons
+        let mut parts = location.rsplitn(3, ':');
+        let (file, line, column) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(column), Some(line), Some(file)) if line.parse::<u32>().is_ok() => {
+                (file, line.parse().ok(), column.parse().ok())
+            }
+            (Some(line), Some(file), _) => {
+                let file = location.strip_suffix(&format!(":{}", line)).unwrap_or(file);
+                (file, line.parse().ok(), None)
+            }
+            _ => {
+                continue;
+            }
+        };
+        let Some(line) = line else {
+            continue;
+        };
+        let file = Path::new(file)
+            .strip_prefix(sketch_dir)
+            .map(|relative| relative.to_string_lossy().to_string())
+            .unwrap_or_else(|_| file.to_string());
+        diagnostics.push(Diagnostic {
+            file,
+            line,
+            column,
+            severity: severity.to_string(),
+            message: message.trim().to_string(),
+        });
+    }
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overlays_editor_content_on_a_fresh_copy() {
+        let dir = tempfile::tempdir().unwrap();
+        let sketch_dir = dir.path().join("Blink");
+        let overlay_dir = dir.path().join("check").join("Blink");
+        fs::create_dir_all(&sketch_dir).unwrap();
+        fs::write(sketch_dir.join("Blink.ino"), "void setup() {}\n").unwrap();
+        fs::write(sketch_dir.join("motor.h"), "int speed;\n").unwrap();
+        fs::create_dir_all(&overlay_dir).unwrap();
+        fs::write(overlay_dir.join("stale.h"), "").unwrap();
+
+        let files = BTreeMap::from([
+            ("Blink.ino".to_string(), "#include <Servo.h>\n".to_string()),
+        ]);
+        write_overlay(&sketch_dir, &overlay_dir, &files).unwrap();
+        let read = |name: &str| fs::read_to_string(overlay_dir.join(name)).unwrap();
+        assert_eq!(read("Blink.ino"), "#include <Servo.h>\n");
+        assert_eq!(read("motor.h"), "int speed;\n");
+        assert!(!overlay_dir.join("stale.h").exists());
+        assert_eq!(fs::read_to_string(sketch_dir.join("Blink.ino")).unwrap(), "void setup() {}\n");
+    }
+
+    #[test]
+    fn parses_errors_relative_to_the_sketch() {
+        let output = "/tmp/sketch/Blink.ino: In function 'void loop()':\n\
+            /tmp/sketch/Blink.ino:12:5: error: 'foo' was not declared in this scope\n\
+            /tmp/sketch/src/motor.h:3:1: warning: unused variable 'speed'\n";
+        let diagnostics = parse_diagnostics(output, Path::new("/tmp/sketch"));
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].file, "Blink.ino");
+        assert_eq!(diagnostics[0].line, 12);
+        assert_eq!(diagnostics[0].column, Some(5));
+        assert_eq!(diagnostics[0].severity, "error");
+        assert_eq!(diagnostics[0].message, "'foo' was not declared in this scope");
+        assert_eq!(diagnostics[1].file, Path::new("src").join("motor.h").to_string_lossy());
+        assert_eq!(diagnostics[1].severity, "warning");
+    }
+
+    #[test]
+    fn reports_fatal_errors_as_errors() {
+        let output = "/tmp/sketch/Blink.ino:1:10: fatal error: Servo.h: No such file or directory";
+        let diagnostics = parse_diagnostics(output, Path::new("/tmp/sketch"));
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, "error");
+        assert_eq!(diagnostics[0].message, "Servo.h: No such file or directory");
+    }
+
+    #[test]
+    fn parses_locations_without_a_column() {
+        let output = "/tmp/sketch/Blink.ino:7: note: declared here";
+        let diagnostics = parse_diagnostics(output, Path::new("/tmp/sketch"));
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].line, 7);
+        assert_eq!(diagnostics[0].column, None);
+        assert_eq!(diagnostics[0].severity, "note");
+    }
+
+    #[test]
+    fn keeps_colons_in_file_names() {
+        let output = "C:\\Users\\me\\Blink\\Blink.ino:4:2: error: expected ';'\n\
+            C:\\Users\\me\\Blink\\Blink.ino:9: error: expected '}'";
+        let diagnostics = parse_diagnostics(output, Path::new("/tmp/sketch"));
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].file, "C:\\Users\\me\\Blink\\Blink.ino");
+        assert_eq!((diagnostics[0].line, diagnostics[0].column), (4, Some(2)));
+        assert_eq!(diagnostics[1].file, "C:\\Users\\me\\Blink\\Blink.ino");
+        assert_eq!((diagnostics[1].line, diagnostics[1].column), (9, None));
+    }
+
+    #[test]
+    fn ignores_lines_without_a_location() {
+        let output = "In file included from /tmp/sketch/Blink.ino:1:\n\
+            compilation terminated.\n\
+            error: no line here";
+        assert!(parse_diagnostics(output, Path::new("/tmp/sketch")).is_empty());
+    }
+}
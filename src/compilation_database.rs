@@ -0,0 +1,201 @@
+use std::fs;
+use std::path::{ Path, PathBuf };
+use serde_json::Value;
+use sha2::{ Digest, Sha256 };
+use tracing::error;
+use crate::models::*;
+use crate::compiler::run_arduino_command;
+use crate::jobs::{ new_job_id, run_job, JobCleanup, JobRequest };
+use crate::socketio::get_build_directory;
+use crate::usb::list_directory_recursive;
+
+const FINGERPRINT_FILE: &str = "fingerprint";
+const SKETCH_SOURCE_EXTENSIONS: [&str; 6] = ["ino", "pde", "h", "hpp", "c", "cpp"];
+
+// Build directory of the compilation database, kept apart from the real build
+pub fn get_compilation_database_path(sketch_name: &str) -> PathBuf {
+    get_build_directory(sketch_name).join("compdb")
+}
+
+// Fingerprint of everything the compilation database of the sketch in `sketch_dir`
+// depends on: the folder, the board, the installed cores and libraries, and the
+// sketch's #include lines
+pub async fn compilation_database_fingerprint(sketch_dir: &Path, fqbn: &str) -> String {
+    // A stable digest, so a cached database survives an agent update. Every part ends
+    // with a NUL so two parts cannot run into each other.
+    let mut hasher = Sha256::new();
+    let mut add = |part: &str| {
+        hasher.update(part.as_bytes());
+        hasher.update([0]);
+    };
+    add(&sketch_dir.to_string_lossy());
+    add(fqbn);
+    for command in ["core", "lib"] {
+        let response = run_arduino_command(
+            &(ArduinoCommand {
+                command: command.to_string(),
+                args: vec!["list".to_string(), "--format".to_string(), "json".to_string()],
+            })
+        ).await;
+        add(&response.output);
+    }
+
+    if let Ok(files) = list_directory_recursive(&sketch_dir.to_string_lossy()) {
+        let mut sources = files
+            .into_iter()
+            .filter(|file| {
+                file.is_file && SKETCH_SOURCE_EXTENSIONS.contains(&file.filetype.as_str())
+            })
+            .map(|file| file.path)
+            .collect::<Vec<_>>();
+        sources.sort();
+        for source in sources {
+            let content = fs::read_to_string(&source).unwrap_or_default();
+            for line in content.lines().filter(|line| line.trim_start().starts_with("#include")) {
+                add(line.trim());
+            }
+        }
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+// Include paths passed with -I (or -isystem / -iquote) in the compile commands
+fn include_paths(compile_commands: &Value) -> Vec<String> {
+    let mut paths = vec![];
+    for entry in compile_commands.as_array().into_iter().flatten() {
+        let arguments = match entry.get("arguments").and_then(|v| v.as_array()) {
+            Some(arguments) =>
+                arguments
+                    .iter()
+                    .filter_map(|argument| argument.as_str().map(|a| a.to_string()))
+                    .collect::<Vec<_>>(),
+            None =>
+                entry
+                    .get("command")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or_default()
+                    .split_whitespace()
+                    .map(|a| a.to_string())
+                    .collect(),
+        };
+        let mut arguments = arguments.iter();
+        while let Some(argument) = arguments.next() {
+            let path = match argument.as_str() {
+                "-I" | "-isystem" | "-iquote" => arguments.next().cloned(),
+                _ => argument.strip_prefix("-I").map(|path| path.to_string()),
+            };
+            if let Some(path) = path.map(|path| path.trim_matches('"').to_string()) &&
+                !path.is_empty() &&
+                !paths.contains(&path)
+            {
+                paths.push(path);
+            }
+        }
+    }
+    paths
+}
+
+// Read the compilation database of a sketch from the build directory it was built in
+pub fn read_compilation_database(
+    build_path: &Path,
+    sketch_name: &str,
+    cached: bool
+) -> Result<Value, String> {
+    let database_path = build_path.join("compile_commands.json");
+    let content = fs
+        ::read_to_string(&database_path)
+        .map_err(|e| format!("Failed to read {}: {}", database_path.display(), e))?;
+    let compile_commands = serde_json
+        ::from_str::<Value>(&content)
+        .map_err(|e| format!("Invalid {}: {}", database_path.display(), e))?;
+    let preprocessed_sketch = build_path.join("sketch").join(format!("{}.ino.cpp", sketch_name));
+
+    Ok(
+        serde_json::json!({
+            "compile_commands_path": database_path,
+            "include_paths": include_paths(&compile_commands),
+            "preprocessed_sketch": preprocessed_sketch,
+            "compile_commands": compile_commands,
+            "cached": cached,
+        })
+    )
+}
+
+// The cached compilation database, if it was built with the same fingerprint
+pub fn cached_compilation_database(
+    build_path: &Path,
+    sketch_name: &str,
+    fingerprint: &str
+) -> Option<Value> {
+    let stored = fs::read_to_string(build_path.join(FINGERPRINT_FILE)).ok()?;
+    if stored.trim() != fingerprint {
+        return None;
+    }
+    read_compilation_database(build_path, sketch_name, true).ok()
+}
+
+// Remember which fingerprint the compilation database was built with
+pub fn store_fingerprint(build_path: &Path, fingerprint: &str) {
+    let path = build_path.join(FINGERPRINT_FILE);
+    if let Err(e) = fs::write(&path, fingerprint) {
+        error!(?e, ?path, "Failed to store compilation database fingerprint");
+    }
+}
+
+// Forget the cached compilation database so the next request rebuilds it
+pub fn clear_fingerprint(build_path: &Path) {
+    let _ = fs::remove_file(build_path.join(FINGERPRINT_FILE));
+}
+
+// Get the compilation database of the sketch in `sketch_dir`, building it into
+// `build_path` when the cached one is out of date. The build runs as a job, so it
+// waits for the sketch and a compile slot like any other compile.
+pub async fn ensure_compilation_database(
+    sketch_name: &str,
+    sketch_dir: &Path,
+    build_path: &Path,
+    fqbn: &str
+) -> Result<Value, String> {
+    let fingerprint = compilation_database_fingerprint(sketch_dir, fqbn).await;
+    if let Some(database) = cached_compilation_database(build_path, sketch_name, &fingerprint) {
+        return Ok(database);
+    }
+
+    let prepare_path = build_path.to_path_buf();
+    let finish_path = build_path.to_path_buf();
+    let request = JobRequest {
+        job_id: new_job_id(),
+        kind: "check-sketch".to_string(),
+        sketch: Some(sketch_name.to_string()),
+        port: None,
+        command: ArduinoCommand {
+            command: "compile".to_string(),
+            args: vec![
+                "--fqbn".to_string(),
+                fqbn.to_string(),
+                "--only-compilation-database".to_string(),
+                "--build-path".to_string(),
+                build_path.to_string_lossy().to_string(),
+                sketch_dir.to_string_lossy().to_string()
+            ],
+        },
+        cleanup: vec![JobCleanup::RemoveDir(build_path.to_path_buf())],
+        check: None,
+        prepare: Some(Box::new(move || clear_fingerprint(&prepare_path))),
+        finish: Some(
+            Box::new(move |response| {
+                if response.success {
+                    store_fingerprint(&finish_path, &fingerprint);
+                }
+                response
+            })
+        ),
+    };
+    let response = run_job(request, |_, _| {}).await;
+    if !response.success {
+        return Err(
+            response.error.unwrap_or_else(|| "Failed to build the compilation database".to_string())
+        );
+    }
+    read_compilation_database(build_path, sketch_name, false)
+}
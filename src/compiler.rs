@@ -1,11 +1,16 @@
 use std::path::PathBuf;
 use socketioxide::socket;
 use tracing::info;
-use tokio::process::Command as TokioCommand;
+use tokio::process::{ Child, Command as TokioCommand };
+use tokio::sync::watch;
+use tokio::time::{ sleep_until, Duration, Instant };
+use tokio::io::{ AsyncRead, AsyncReadExt };
+use std::process::Stdio;
 use axum::{ extract::{ Multipart, Query }, http::StatusCode, response::Json as ResponseJson };
 use std::collections::HashMap;
 use tokio::fs;
 use crate::{ models::*, socketio::get_sketch_directory };
+use crate::environment::get_cli_config_path;
 // Path to the arduino-cli binary
 #[cfg(target_os = "linux")]
 static ARDUINO_CLI_BINARY: &[u8] = include_bytes!("../resource/linux/arduino-cli"); // Change this if needed
@@ -14,6 +19,8 @@ static ARDUINO_CLI_BINARY: &[u8] = include_bytes!("../resource/windows/arduino-c
 #[cfg(target_os = "macos")]
 static ARDUINO_CLI_BINARY: &[u8] = include_bytes!("../resource/macOS_x86_64/arduino-cli"); // Change this if needed
 static ARDUINO_CLI_PATH: std::sync::OnceLock<PathBuf> = std::sync::OnceLock::new();
+// How long a cancelled command gets to exit before it is killed
+const KILL_GRACE_PERIOD: Duration = Duration::from_secs(3);
 
 // Function to initialize the arduino-cli binary
 fn initialize_arduino_cli() -> PathBuf {
@@ -45,7 +52,12 @@ pub fn get_arduino_cli_path() -> &'static PathBuf {
 }
 pub fn health_check() -> bool {
     let arduino_cli_path = get_arduino_cli_path();
-    let test_result = std::process::Command::new(arduino_cli_path).arg("version").output();
+    let test_result = std::process::Command
+        ::new(arduino_cli_path)
+        .arg("version")
+        .arg("--config-file")
+        .arg(get_cli_config_path())
+        .output();
     match test_result {
         Ok(output) => {
             if output.status.success() {
@@ -64,6 +76,18 @@ pub fn health_check() -> bool {
 }
 // Helper function to run Arduino CLI commands
 pub async fn run_arduino_command(command: &ArduinoCommand) -> CommandResponse {
+    run_arduino_command_streaming(command, None, |_, _| {}).await
+}
+
+// Run an Arduino CLI command, reporting every stdout/stderr line as it is produced.
+// When `cancel` flips to true the whole process tree is stopped.
+pub async fn run_arduino_command_streaming<F>(
+    command: &ArduinoCommand,
+    mut cancel: Option<watch::Receiver<bool>>,
+    mut on_output: F
+) -> CommandResponse
+    where F: FnMut(&str, &str)
+{
     let arduino_cli_path = get_arduino_cli_path();
 
     let cmd_name = &command.command;
@@ -71,13 +95,95 @@ pub async fn run_arduino_command(command: &ArduinoCommand) -> CommandResponse {
 
     info!("Running Arduino CLI command: {} {:?}", cmd_name, args);
 
-    let output = TokioCommand::new(arduino_cli_path).arg(cmd_name).args(args).output().await;
+    let mut process = TokioCommand::new(arduino_cli_path);
+    process
+        .arg(cmd_name)
+        .args(args)
+        // Use the agent's own config and directories, never the user's global setup
+        .arg("--config-file")
+        .arg(get_cli_config_path())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    // Run in a separate process group so a cancel can stop gcc/avrdude as well
+    #[cfg(unix)]
+    process.process_group(0);
+    let child = process.spawn();
 
-    match output {
-        Ok(output) => {
-            let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-            let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            return CommandResponse {
+                success: false,
+                output: String::new(),
+                output_json: None,
+                files: None,
+                error: Some(format!("Failed to execute command: {}", e)),
+                command: cmd_name.clone(),
+                args: args.clone(),
+                job_id: None,
+                error_code: None,
+            };
+        }
+    };
+
+    let mut stdout_lines = child.stdout.take().map(OutputLines::new);
+    let mut stderr_lines = child.stderr.take().map(OutputLines::new);
+    let mut stdout = String::new();
+    let mut stderr = String::new();
+    let mut cancelled = false;
+    let mut kill_deadline: Option<Instant> = None;
 
+    // Read both pipes until they are closed so neither one can fill up and block the child
+    while stdout_lines.is_some() || stderr_lines.is_some() {
+        tokio::select! {
+            _ = wait_for_cancel(&mut cancel), if !cancelled => {
+                info!("Cancelling Arduino CLI command: {} {:?}", cmd_name, args);
+                kill_process_tree(&mut child, false);
+                cancelled = true;
+                kill_deadline = Some(Instant::now() + KILL_GRACE_PERIOD);
+            }
+            _ = sleep_until(kill_deadline.unwrap_or_else(Instant::now)), if kill_deadline.is_some() => {
+                info!("Arduino CLI command did not stop in time, killing it");
+                kill_process_tree(&mut child, true);
+                kill_deadline = None;
+            }
+            line = next_output_line(&mut stdout_lines), if stdout_lines.is_some() => {
+                match line {
+                    Some((line, redraw)) => {
+                        on_output("stdout", &line);
+                        // Progress bar redraws are streamed but not kept in the final output
+                        if !redraw {
+                            stdout.push_str(&line);
+                            stdout.push('\n');
+                        }
+                    }
+                    None => {
+                        stdout_lines = None;
+                    }
+                }
+            }
+            line = next_output_line(&mut stderr_lines), if stderr_lines.is_some() => {
+                match line {
+                    Some((line, redraw)) => {
+                        on_output("stderr", &line);
+                        // Progress bar redraws are streamed but not kept in the final output
+                        if !redraw {
+                            stderr.push_str(&line);
+                            stderr.push('\n');
+                        }
+                    }
+                    None => {
+                        stderr_lines = None;
+                    }
+                }
+            }
+        }
+    }
+
+    match child.wait().await {
+        Ok(status) => {
             // Try to parse stdout as JSON, fallback to string if parsing fails
             let parsed_output = match serde_json::from_str::<serde_json::Value>(&stdout) {
                 Ok(json) => Some(json),
@@ -85,7 +191,7 @@ pub async fn run_arduino_command(command: &ArduinoCommand) -> CommandResponse {
             };
 
             CommandResponse {
-                success: output.status.success(),
+                success: status.success(),
                 output: stdout,
                 output_json: parsed_output,
                 files: None,
@@ -96,21 +202,182 @@ pub async fn run_arduino_command(command: &ArduinoCommand) -> CommandResponse {
                 },
                 command: cmd_name.clone(),
                 args: args.clone(),
+                job_id: None,
+                error_code: None,
             }
         }
         Err(e) =>
             CommandResponse {
                 success: false,
-                output: String::new(),
+                output: stdout,
                 output_json: None,
                 files: None,
-                error: Some(format!("Failed to execute command: {}", e)),
+                error: Some(format!("Failed to wait for command: {}", e)),
                 command: cmd_name.clone(),
                 args: args.clone(),
+                job_id: None,
+                error_code: None,
             },
     }
 }
 
+// Get the entries of an arduino-cli JSON list, which is either a bare array
+// or wrapped in an object under `key` depending on the arduino-cli version
+pub fn json_list<'a>(value: &'a serde_json::Value, key: &str) -> Vec<&'a serde_json::Value> {
+    match value {
+        serde_json::Value::Array(items) => items.iter().collect(),
+        serde_json::Value::Object(map) =>
+            map
+                .get(key)
+                .and_then(|v| v.as_array())
+                .map(|items| items.iter().collect())
+                .unwrap_or_default(),
+        _ => vec![],
+    }
+}
+
+// Check whether a core (e.g. `arduino:avr`) is installed
+pub async fn is_core_installed(core: &str) -> bool {
+    let core_id = core.split('@').next().unwrap_or(core);
+    let command = ArduinoCommand {
+        command: "core".to_string(),
+        args: vec!["list".to_string(), "--format".to_string(), "json".to_string()],
+    };
+    match run_arduino_command(&command).await.output_json {
+        Some(json) =>
+            json_list(&json, "platforms")
+                .iter()
+                .any(|platform| platform.get("id").and_then(|id| id.as_str()) == Some(core_id)),
+        None => false,
+    }
+}
+
+// Check whether a library is installed
+pub async fn is_library_installed(library: &str) -> bool {
+    let library_name = library.split('@').next().unwrap_or(library);
+    let command = ArduinoCommand {
+        command: "lib".to_string(),
+        args: vec!["list".to_string(), "--format".to_string(), "json".to_string()],
+    };
+    match run_arduino_command(&command).await.output_json {
+        Some(json) =>
+            json_list(&json, "installed_libraries")
+                .iter()
+                .any(|entry| {
+                    entry
+                        .get("library")
+                        .and_then(|lib| lib.get("name"))
+                        .and_then(|name| name.as_str()) == Some(library_name)
+                }),
+        None => false,
+    }
+}
+
+// Wait until the job is cancelled; never resolves when there is nothing to cancel
+async fn wait_for_cancel(cancel: &mut Option<watch::Receiver<bool>>) {
+    match cancel {
+        Some(cancel) => {
+            if cancel.wait_for(|cancelled| *cancelled).await.is_err() {
+                std::future::pending::<()>().await;
+            }
+        }
+        None => std::future::pending::<()>().await,
+    }
+}
+
+// Stop a child process together with every process it spawned
+fn kill_process_tree(child: &mut Child, force: bool) {
+    let Some(pid) = child.id() else {
+        return;
+    };
+
+    #[cfg(unix)]
+    {
+        // The child leads its own process group, so signal the whole group
+        let signal = if force { "-KILL" } else { "-TERM" };
+        if let Err(e) = std::process::Command::new("kill")
+            .args([signal, "--", &format!("-{}", pid)])
+            .status()
+        {
+            info!("Failed to signal process group {}: {}", pid, e);
+        }
+    }
+    #[cfg(target_os = "windows")]
+    {
+        if let Err(e) = std::process::Command::new("taskkill")
+            .args(["/PID", &pid.to_string(), "/T", "/F"])
+            .output()
+        {
+            info!("Failed to kill process tree {}: {}", pid, e);
+        }
+    }
+
+    if force {
+        child.start_kill().ok();
+    }
+}
+
+// Splits child output into lines on '\n', '\r\n' and a lone '\r', so progress
+// bars that redraw themselves with '\r' are reported while they update
+struct OutputLines<R> {
+    reader: R,
+    buffer: Vec<u8>,
+    closed: bool,
+}
+
+impl<R> OutputLines<R> where R: AsyncRead + Unpin {
+    fn new(reader: R) -> Self {
+        OutputLines {
+            reader,
+            buffer: Vec::new(),
+            closed: false,
+        }
+    }
+
+    // Returns the next line and whether it was a redraw ended by a lone '\r', or None once closed
+    async fn next_line(&mut self) -> Option<(String, bool)> {
+        loop {
+            if let Some(pos) = self.buffer.iter().position(|b| *b == b'\n' || *b == b'\r') {
+                let carriage_return = self.buffer[pos] == b'\r';
+                // A trailing '\r' may be the start of "\r\n", so wait for the next byte
+                let undecided = carriage_return && pos + 1 == self.buffer.len() && !self.closed;
+                if !undecided {
+                    let crlf = carriage_return && self.buffer.get(pos + 1) == Some(&b'\n');
+                    let end = if crlf { pos + 1 } else { pos };
+                    let line = self.buffer.drain(..=end).collect::<Vec<u8>>();
+                    let text = String::from_utf8_lossy(&line[..pos]).to_string();
+                    return Some((text, carriage_return && !crlf));
+                }
+            } else if self.closed {
+                if self.buffer.is_empty() {
+                    return None;
+                }
+                let line = std::mem::take(&mut self.buffer);
+                return Some((String::from_utf8_lossy(&line).to_string(), false));
+            }
+
+            let mut chunk = [0u8; 4096];
+            match self.reader.read(&mut chunk).await {
+                Ok(0) => {
+                    self.closed = true;
+                }
+                Ok(n) => self.buffer.extend_from_slice(&chunk[..n]),
+                Err(e) => {
+                    info!("Failed to read command output: {}", e);
+                    self.closed = true;
+                }
+            }
+        }
+    }
+}
+
+// Read the next line from a child pipe, returning None once the pipe is closed
+async fn next_output_line<R>(lines: &mut Option<OutputLines<R>>) -> Option<(String, bool)>
+    where R: AsyncRead + Unpin
+{
+    lines.as_mut()?.next_line().await
+}
+
 // Handler for library upload endpoint
 pub async fn upload_library(
     Query(params): Query<HashMap<String, String>>,
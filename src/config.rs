@@ -0,0 +1,285 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{ Path, PathBuf };
+use std::sync::OnceLock;
+use serde_json::Value;
+use tracing::{ info, error };
+use crate::models::*;
+use crate::compiler::run_arduino_command;
+
+const AGENT_CONFIG_FILE: &str = "agent_config.json";
+const LOG_LEVELS: &[&str] = &["trace", "debug", "info", "warn", "error", "fatal", "panic"];
+
+// Serialises changes to the agent config so concurrent sets do not lose each other
+static CONFIG_LOCK: OnceLock<tokio::sync::Mutex<()>> = OnceLock::new();
+
+// The type of value an arduino-cli setting takes
+#[derive(Clone, Copy)]
+pub enum SettingKind {
+    Bool,
+    Integer,
+    Text,
+    TextList,
+    Choice(&'static [&'static str]),
+}
+
+// arduino-cli settings the UI may read and change
+pub const ALLOWED_SETTINGS: &[(&str, SettingKind)] = &[
+    ("build_cache.compilations_before_purge", SettingKind::Integer),
+    ("build_cache.path", SettingKind::Text),
+    ("build_cache.ttl", SettingKind::Text),
+    ("directories.builtin.libraries", SettingKind::Text),
+    ("directories.data", SettingKind::Text),
+    ("directories.downloads", SettingKind::Text),
+    ("directories.user", SettingKind::Text),
+    ("library.enable_unsafe_install", SettingKind::Bool),
+    ("locale", SettingKind::Text),
+    ("logging.level", SettingKind::Choice(LOG_LEVELS)),
+    ("network.proxy", SettingKind::Text),
+    ("network.user_agent_ext", SettingKind::Text),
+    ("sketch.always_export_binaries", SettingKind::Bool),
+    ("updater.enable_notification", SettingKind::Bool),
+];
+
+// Settings the UI may read and reset but only change through their own commands,
+// e.g. board manager URLs, which add-board-url validates first
+pub const MANAGED_SETTINGS: &[(&str, SettingKind)] = &[
+    ("board_manager.additional_urls", SettingKind::TextList),
+];
+
+// Settings the agent needs no matter what the user configured before
+fn default_settings() -> BTreeMap<String, Value> {
+    // Local ZIP libraries from /upload-library can only be installed with unsafe install
+    BTreeMap::from([("library.enable_unsafe_install".to_string(), Value::Bool(true))])
+}
+
+// Path of the agent's own config, holding the arduino-cli settings changed through the agent
+pub fn get_agent_config_path() -> PathBuf {
+    let current_dir = std::env::current_dir().unwrap_or_else(|_| Path::new(".").to_path_buf());
+    current_dir.join(AGENT_CONFIG_FILE)
+}
+
+fn read_agent_config() -> BTreeMap<String, Value> {
+    let path = get_agent_config_path();
+    match fs::read_to_string(&path) {
+        Ok(content) =>
+            serde_json::from_str(&content).unwrap_or_else(|e| {
+                error!(?e, ?path, "Invalid agent config, ignoring it");
+                BTreeMap::new()
+            }),
+        Err(_) => BTreeMap::new(),
+    }
+}
+
+fn write_agent_config(settings: &BTreeMap<String, Value>) -> Result<(), String> {
+    let path = get_agent_config_path();
+    let content = serde_json::to_string_pretty(settings).unwrap_or_default();
+    fs::write(&path, content).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+// Lock held while changing settings, also by the commands of managed settings
+pub async fn lock_config() -> tokio::sync::MutexGuard<'static, ()> {
+    CONFIG_LOCK.get_or_init(|| tokio::sync::Mutex::new(())).lock().await
+}
+
+fn find_setting(settings: &[(&str, SettingKind)], key: &str) -> Option<SettingKind> {
+    settings
+        .iter()
+        .find(|(allowed, _)| *allowed == key)
+        .map(|(_, kind)| *kind)
+}
+
+// The type of a setting config-set may change
+fn setting_kind(key: &str) -> Result<SettingKind, String> {
+    find_setting(ALLOWED_SETTINGS, key).ok_or_else(|| {
+        format!("Setting {} cannot be changed through the agent", key)
+    })
+}
+
+// The type of a setting the agent reads, saves and resets, managed ones included
+fn known_setting_kind(key: &str) -> Result<SettingKind, String> {
+    find_setting(ALLOWED_SETTINGS, key)
+        .or_else(|| find_setting(MANAGED_SETTINGS, key))
+        .ok_or_else(|| format!("Setting {} is not managed by the agent", key))
+}
+
+// Check a value against the setting's type and turn it into `config set` arguments
+fn setting_args(key: &str, kind: SettingKind, value: &Value) -> Result<Vec<String>, String> {
+    let invalid = |expected: &str| {
+        format!("Invalid value {} for {}, expected {}", value, key, expected)
+    };
+    match (kind, value) {
+        (SettingKind::Bool, Value::Bool(value)) => Ok(vec![value.to_string()]),
+        (SettingKind::Bool, _) => Err(invalid("true or false")),
+        (SettingKind::Integer, Value::Number(number)) if number.is_u64() => {
+            Ok(vec![number.to_string()])
+        }
+        (SettingKind::Integer, _) => Err(invalid("a positive integer")),
+        (SettingKind::Text, Value::String(text)) => Ok(vec![text.clone()]),
+        (SettingKind::Text, _) => Err(invalid("a string")),
+        (SettingKind::TextList, Value::Array(items)) => {
+            items
+                .iter()
+                .map(|item| {
+                    item.as_str()
+                        .map(|item| item.to_string())
+                        .ok_or_else(|| invalid("a list of strings"))
+                })
+                .collect()
+        }
+        (SettingKind::TextList, _) => Err(invalid("a list of strings")),
+        (SettingKind::Choice(choices), Value::String(choice)) if
+            choices.contains(&choice.as_str())
+        => {
+            Ok(vec![choice.clone()])
+        }
+        (SettingKind::Choice(choices), _) => Err(invalid(&format!("one of {:?}", choices))),
+    }
+}
+
+async fn run_config_command(args: Vec<String>) -> Result<CommandResponse, String> {
+    let command = ArduinoCommand {
+        command: "config".to_string(),
+        args,
+    };
+    let response = run_arduino_command(&command).await;
+    if response.success {
+        Ok(response)
+    } else {
+        Err(response.error.clone().unwrap_or_else(|| "arduino-cli config failed".to_string()))
+    }
+}
+
+// Apply a setting to arduino-cli. An empty list is removed as `config set` needs a value.
+async fn apply_setting(key: &str, kind: SettingKind, value: &Value) -> Result<(), String> {
+    let values = setting_args(key, kind, value)?;
+    let mut args = if values.is_empty() {
+        vec!["delete".to_string(), key.to_string()]
+    } else {
+        vec!["set".to_string(), key.to_string()]
+    };
+    args.extend(values);
+    run_config_command(args).await.map(|_| ())
+}
+
+// Get the current value of a setting from arduino-cli
+pub async fn get_setting(key: &str) -> Result<Value, String> {
+    known_setting_kind(key)?;
+    let response = run_config_command(
+        vec!["get".to_string(), key.to_string(), "--format".to_string(), "json".to_string()]
+    ).await?;
+    Ok(response.output_json.unwrap_or(Value::Null))
+}
+
+// Change a setting and keep it in the agent config so it survives restarts
+pub async fn set_setting(key: &str, value: Value) -> Result<(), String> {
+    let kind = setting_kind(key)?;
+    let _guard = lock_config().await;
+    apply_setting(key, kind, &value).await?;
+    save_setting(key, value)
+}
+
+// Keep a setting that was already applied to arduino-cli in the agent config.
+// Callers hold the lock from `lock_config`.
+pub fn save_setting(key: &str, value: Value) -> Result<(), String> {
+    let mut settings = read_agent_config();
+    settings.insert(key.to_string(), value);
+    write_agent_config(&settings)
+}
+
+// The allowlisted settings as arduino-cli currently has them, e.g. {"locale": "en"}
+pub async fn dump_settings() -> Result<BTreeMap<String, Value>, String> {
+    let response = run_config_command(
+        vec!["dump".to_string(), "--format".to_string(), "json".to_string()]
+    ).await?;
+    let dump = response.output_json.unwrap_or(Value::Null);
+    // Newer arduino-cli versions wrap the settings in a `config` object
+    let dump = dump.get("config").cloned().unwrap_or(dump);
+
+    Ok(
+        ALLOWED_SETTINGS.iter()
+            .chain(MANAGED_SETTINGS)
+            .map(|(key, _)| {
+                let value = key
+                    .split('.')
+                    .try_fold(&dump, |value, part| value.get(part))
+                    .cloned()
+                    .unwrap_or(Value::Null);
+                (key.to_string(), value)
+            })
+            .collect()
+    )
+}
+
+// Forget a setting changed through the agent, or all of them when no key is given.
+// The setting goes back to the agent default, or to the arduino-cli default.
+pub async fn reset_settings(key: Option<&str>) -> Result<Vec<String>, String> {
+    let _guard = lock_config().await;
+    let mut settings = read_agent_config();
+    let keys = match key {
+        Some(key) => {
+            known_setting_kind(key)?;
+            vec![key.to_string()]
+        }
+        None => settings.keys().cloned().collect(),
+    };
+
+    let defaults = default_settings();
+    for key in &keys {
+        match defaults.get(key) {
+            Some(value) => apply_setting(key, known_setting_kind(key)?, value).await?,
+            None => {
+                // Nothing to delete when arduino-cli never had the setting
+                if let Err(e) = run_config_command(vec!["delete".to_string(), key.clone()]).await {
+                    info!("Could not delete arduino-cli setting {}: {}", key, e);
+                }
+            }
+        }
+        settings.remove(key);
+    }
+    write_agent_config(&settings)?;
+    Ok(keys)
+}
+
+// Apply the agent defaults and the saved settings to arduino-cli, once at startup
+pub async fn apply_agent_config() {
+    let mut settings = default_settings();
+    settings.extend(read_agent_config());
+    for (key, value) in &settings {
+        let applied = match known_setting_kind(key) {
+            Ok(kind) => apply_setting(key, kind, value).await,
+            Err(e) => Err(e),
+        };
+        match applied {
+            Ok(()) => info!("Applied arduino-cli setting {} = {}", key, value),
+            Err(e) => error!("Failed to apply arduino-cli setting {}: {}", key, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn board_manager_urls_cannot_be_set_directly() {
+        assert!(setting_kind("board_manager.additional_urls").is_err());
+        assert!(known_setting_kind("board_manager.additional_urls").is_ok());
+        assert!(known_setting_kind("directories.unknown").is_err());
+    }
+
+    #[test]
+    fn checks_values_against_the_setting_type() {
+        let args = |key: &str, value: Value| setting_args(key, known_setting_kind(key)?, &value);
+        assert_eq!(args("locale", Value::from("de")).unwrap(), ["de"]);
+        assert_eq!(args("sketch.always_export_binaries", Value::Bool(true)).unwrap(), ["true"]);
+        assert!(args("sketch.always_export_binaries", Value::from("yes")).is_err());
+        assert!(args("build_cache.compilations_before_purge", Value::from(-1)).is_err());
+        assert!(args("logging.level", Value::from("loud")).is_err());
+        assert_eq!(
+            args("board_manager.additional_urls", serde_json::json!(["https://a", "https://b"]))
+                .unwrap(),
+            ["https://a", "https://b"]
+        );
+    }
+}
@@ -0,0 +1,241 @@
+use std::iter::repeat_n;
+
+// Unchanged lines shown around each change
+const CONTEXT: usize = 3;
+// Above this many line pairs a file is shown as replaced instead of diffed line by line
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Edit {
+    Equal,
+    Delete,
+    Insert,
+}
+
+// The line operations turning `old` into `new`, from their longest common subsequence
+fn line_edits(old: &[&str], new: &[&str]) -> Vec<Edit> {
+    let prefix = old
+        .iter()
+        .zip(new)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old = &old[prefix..old.len() - suffix];
+    let new = &new[prefix..new.len() - suffix];
+
+    let mut edits = vec![Edit::Equal; prefix];
+    if old.len() * new.len() > MAX_DIFF_CELLS {
+        edits.extend(repeat_n(Edit::Delete, old.len()));
+        edits.extend(repeat_n(Edit::Insert, new.len()));
+    } else {
+        // lengths[i * width + j] is the common subsequence length of old[i..] and new[j..]
+        let width = new.len() + 1;
+        let mut lengths = vec![0u32; (old.len() + 1) * width];
+        for i in (0..old.len()).rev() {
+            for j in (0..new.len()).rev() {
+                lengths[i * width + j] = if old[i] == new[j] {
+                    lengths[(i + 1) * width + j + 1] + 1
+                } else {
+                    lengths[(i + 1) * width + j].max(lengths[i * width + j + 1])
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < old.len() && j < new.len() {
+            if old[i] == new[j] {
+                edits.push(Edit::Equal);
+                i += 1;
+                j += 1;
+            } else if lengths[(i + 1) * width + j] >= lengths[i * width + j + 1] {
+                edits.push(Edit::Delete);
+                i += 1;
+            } else {
+                edits.push(Edit::Insert);
+                j += 1;
+            }
+        }
+        edits.extend(repeat_n(Edit::Delete, old.len() - i));
+        edits.extend(repeat_n(Edit::Insert, new.len() - j));
+    }
+    edits.extend(repeat_n(Edit::Equal, suffix));
+    edits
+}
+
+// Line range of a hunk as written in its header, e.g. "12,4"
+fn hunk_range(start: usize, count: usize) -> String {
+    // An empty range names the line before it
+    let start = if count == 0 { start } else { start + 1 };
+    format!("{},{}", start, count)
+}
+
+// A diff line for `line`, which keeps its line break. A last line without one is
+// marked the way diff and git mark it.
+fn diff_line(prefix: char, line: &str) -> String {
+    match line.strip_suffix('\n') {
+        Some(line) => format!("{}{}\n", prefix, line),
+        None => format!("{}{}\n\\ No newline at end of file\n", prefix, line),
+    }
+}
+
+// A unified diff of two texts, empty when they are the same
+pub fn unified_diff(old: &str, new: &str, old_label: &str, new_label: &str) -> String {
+    // Lines are compared with their line breaks, so a missing last one is a change too
+    let old_lines = old.split_inclusive('\n').collect::<Vec<_>>();
+    let new_lines = new.split_inclusive('\n').collect::<Vec<_>>();
+    let edits = line_edits(&old_lines, &new_lines);
+
+    // Line in both texts at which each edit starts
+    let mut positions = Vec::with_capacity(edits.len());
+    let (mut i, mut j) = (0, 0);
+    for edit in &edits {
+        positions.push((i, j));
+        match edit {
+            Edit::Equal => {
+                i += 1;
+                j += 1;
+            }
+            Edit::Delete => {
+                i += 1;
+            }
+            Edit::Insert => {
+                j += 1;
+            }
+        }
+    }
+    let changes = edits
+        .iter()
+        .enumerate()
+        .filter(|(_, edit)| **edit != Edit::Equal)
+        .map(|(index, _)| index)
+        .collect::<Vec<_>>();
+    if changes.is_empty() {
+        return String::new();
+    }
+
+    let mut output = format!("--- {}\n+++ {}\n", old_label, new_label);
+    let mut index = 0;
+    while index < changes.len() {
+        // Changes whose context would touch end up in the same hunk
+        let start = changes[index].saturating_sub(CONTEXT);
+        let mut last = changes[index];
+        index += 1;
+        while index < changes.len() && changes[index] - last <= 2 * CONTEXT + 1 {
+            last = changes[index];
+            index += 1;
+        }
+        let end = (last + CONTEXT + 1).min(edits.len());
+
+        let hunk = &edits[start..end];
+        let old_count = hunk
+            .iter()
+            .filter(|edit| **edit != Edit::Insert)
+            .count();
+        let new_count = hunk
+            .iter()
+            .filter(|edit| **edit != Edit::Delete)
+            .count();
+        let (old_start, new_start) = positions[start];
+        output.push_str(
+            &format!(
+                "@@ -{} +{} @@\n",
+                hunk_range(old_start, old_count),
+                hunk_range(new_start, new_count)
+            )
+        );
+        for (edit, (i, j)) in hunk.iter().zip(&positions[start..end]) {
+            let line = match edit {
+                Edit::Equal => diff_line(' ', old_lines[*i]),
+                Edit::Delete => diff_line('-', old_lines[*i]),
+                Edit::Insert => diff_line('+', new_lines[*j]),
+            };
+            output.push_str(&line);
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbered_lines(count: usize) -> String {
+        (1..=count).map(|n| format!("line {}\n", n)).collect()
+    }
+
+    #[test]
+    fn same_texts_have_no_diff() {
+        assert_eq!(unified_diff("a\nb\n", "a\nb\n", "old", "new"), "");
+        assert_eq!(unified_diff("", "", "old", "new"), "");
+    }
+
+    #[test]
+    fn diffs_inserted_lines() {
+        assert_eq!(
+            unified_diff("a\nb\nc\n", "a\nb\nx\nc\n", "old", "new"),
+            "--- old\n+++ new\n@@ -1,3 +1,4 @@\n a\n b\n+x\n c\n"
+        );
+        assert_eq!(
+            unified_diff("", "a\nb\n", "old", "new"),
+            "--- old\n+++ new\n@@ -0,0 +1,2 @@\n+a\n+b\n"
+        );
+    }
+
+    #[test]
+    fn diffs_deleted_lines() {
+        assert_eq!(
+            unified_diff("a\nb\nc\n", "a\nc\n", "old", "new"),
+            "--- old\n+++ new\n@@ -1,3 +1,2 @@\n a\n-b\n c\n"
+        );
+        assert_eq!(
+            unified_diff("a\nb\n", "", "old", "new"),
+            "--- old\n+++ new\n@@ -1,2 +0,0 @@\n-a\n-b\n"
+        );
+    }
+
+    #[test]
+    fn merges_changes_whose_context_touches() {
+        let old = numbered_lines(20);
+        // Six unchanged lines apart: one hunk
+        let near = old.replace("line 5\n", "five\n").replace("line 12\n", "twelve\n");
+        let diff = unified_diff(&old, &near, "old", "new");
+        assert_eq!(diff.matches("@@ -").count(), 1);
+        assert!(diff.contains("@@ -2,14 +2,14 @@\n"));
+
+        // Eight unchanged lines apart: two hunks
+        let far = old.replace("line 5\n", "five\n").replace("line 14\n", "fourteen\n");
+        let diff = unified_diff(&old, &far, "old", "new");
+        assert!(diff.contains("@@ -2,7 +2,7 @@\n"));
+        assert!(diff.contains("@@ -11,7 +11,7 @@\n"));
+    }
+
+    #[test]
+    fn marks_missing_newline_at_end() {
+        assert_eq!(
+            unified_diff("a\nb\n", "a\nb", "old", "new"),
+            "--- old\n+++ new\n@@ -1,2 +1,2 @@\n a\n-b\n+b\n\\ No newline at end of file\n"
+        );
+        assert_eq!(
+            unified_diff("a\nb", "a\nb\nc\n", "old", "new"),
+            "--- old\n+++ new\n@@ -1,2 +1,3 @@\n a\n-b\n\\ No newline at end of file\n+b\n+c\n"
+        );
+    }
+
+    #[test]
+    fn replaces_large_files_as_a_whole() {
+        // Over MAX_DIFF_CELLS once the common start and end are left out
+        let old = format!("same\n{}", numbered_lines(2001));
+        let new = format!("same\n{}", numbered_lines(2001).replace("line", "row"));
+        let diff = unified_diff(&old, &new, "old", "new");
+        assert!(diff.contains("@@ -1,2002 +1,2002 @@\n same\n-line 1\n"));
+        // Every line is removed and added again, counting the --- and +++ headers
+        assert_eq!(diff.lines().filter(|line| line.starts_with('-')).count(), 2001 + 1);
+        assert_eq!(diff.lines().filter(|line| line.starts_with('+')).count(), 2001 + 1);
+        let first_insert = diff.find("\n+row 1\n").unwrap();
+        assert!(diff.find("\n-line 2001\n").unwrap() < first_insert);
+    }
+}
@@ -0,0 +1,229 @@
+use std::fs;
+use std::path::{ Path, PathBuf };
+use std::sync::OnceLock;
+use serde_json::Value;
+use tracing::info;
+use crate::examples::copy_dir_recursive;
+use crate::socketio::get_sketch_directory;
+
+const AGENT_DIR_NAME: &str = "plode-web-agent";
+const CLI_CONFIG_FILE: &str = "arduino-cli.yaml";
+const IDE_IMPORT_FILE: &str = "ide_import.json";
+
+static AGENT_HOME: OnceLock<PathBuf> = OnceLock::new();
+
+fn home_dir() -> PathBuf {
+    std::env
+        ::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+// Per-user directory of the agent's own arduino-cli setup, so it never touches
+// the config, cores and libraries of the user's Arduino IDE.
+// Can be moved with the PLODE_AGENT_HOME environment variable.
+pub fn get_agent_home() -> &'static PathBuf {
+    AGENT_HOME.get_or_init(|| {
+        if let Some(home) = std::env::var_os("PLODE_AGENT_HOME") {
+            return PathBuf::from(home);
+        }
+        #[cfg(target_os = "windows")]
+        let base = std::env
+            ::var_os("LOCALAPPDATA")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| home_dir().join("AppData").join("Local"));
+        #[cfg(target_os = "macos")]
+        let base = home_dir().join("Library").join("Application Support");
+        #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+        let base = std::env
+            ::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| home_dir().join(".local").join("share"));
+        base.join(AGENT_DIR_NAME)
+    })
+}
+
+// The config file passed to every arduino-cli command with --config-file
+pub fn get_cli_config_path() -> PathBuf {
+    get_agent_home().join(CLI_CONFIG_FILE)
+}
+
+// The arduino-cli user directory, which holds the libraries the agent installs
+pub fn get_cli_user_directory() -> PathBuf {
+    get_agent_home().join("user")
+}
+
+// Create the agent's arduino-cli directories and config file on first start
+pub fn ensure_cli_environment() -> Result<(), String> {
+    let home = get_agent_home();
+    let directories = [
+        ("data", home.join("data")),
+        ("downloads", home.join("downloads")),
+        ("user", get_cli_user_directory()),
+    ];
+    for (_, path) in &directories {
+        fs
+            ::create_dir_all(path)
+            .map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+    }
+
+    // Settings changed later with config-set live in this file, only write it once
+    let config_path = get_cli_config_path();
+    if !config_path.exists() {
+        let mut config = serde_yaml::Mapping::new();
+        let mut dirs = serde_yaml::Mapping::new();
+        for (key, path) in &directories {
+            dirs.insert(
+                serde_yaml::Value::from(*key),
+                serde_yaml::Value::from(path.to_string_lossy().to_string())
+            );
+        }
+        config.insert(serde_yaml::Value::from("directories"), serde_yaml::Value::Mapping(dirs));
+        let content = serde_yaml::to_string(&config).map_err(|e| e.to_string())?;
+        fs
+            ::write(&config_path, content)
+            .map_err(|e| format!("Failed to write {}: {}", config_path.display(), e))?;
+        info!("Created arduino-cli config {}", config_path.display());
+    }
+    Ok(())
+}
+
+// The sketchbook of the user's Arduino IDE: the directories.user setting of
+// Arduino IDE 2, or the default sketchbook location
+pub fn find_ide_sketchbook() -> Option<PathBuf> {
+    let ide_config = home_dir().join(".arduinoIDE").join(CLI_CONFIG_FILE);
+    let configured = fs
+        ::read_to_string(ide_config)
+        .ok()
+        .and_then(|content| serde_yaml::from_str::<serde_yaml::Value>(&content).ok())
+        .and_then(|config| {
+            config.get("directories")?.get("user")?.as_str().map(PathBuf::from)
+        });
+    let defaults = [home_dir().join("Documents").join("Arduino"), home_dir().join("Arduino")];
+    configured
+        .into_iter()
+        .chain(defaults)
+        .find(|path| path.is_dir())
+}
+
+// Sketch folders of a sketchbook, i.e. folders holding a .ino named after them.
+// Symlinked folders are left out, like symlinks inside the folders.
+fn sketchbook_sketches(sketchbook: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(sketchbook) else {
+        return vec![];
+    };
+    let mut sketches = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_ok_and(|file_type| file_type.is_dir()))
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name().is_some_and(|name| {
+                path.join(format!("{}.ino", name.to_string_lossy())).is_file()
+            })
+        })
+        .collect::<Vec<_>>();
+    sketches.sort();
+    sketches
+}
+
+fn sketchbook_libraries(sketchbook: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(sketchbook.join("libraries")) else {
+        return vec![];
+    };
+    let mut libraries = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_ok_and(|file_type| file_type.is_dir()))
+        .map(|entry| entry.path())
+        .collect::<Vec<_>>();
+    libraries.sort();
+    libraries
+}
+
+fn names(paths: &[PathBuf]) -> Vec<String> {
+    paths
+        .iter()
+        .filter_map(|path| path.file_name().map(|name| name.to_string_lossy().to_string()))
+        .collect()
+}
+
+// When the IDE sketchbook was imported, if it was
+fn read_ide_import() -> Option<Value> {
+    let content = fs::read_to_string(get_agent_home().join(IDE_IMPORT_FILE)).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+// What an import of the Arduino IDE sketchbook would bring in. Only the sketchbook
+// the IDE is configured with, or its default location, is ever read: the path does
+// not come from clients, so a web page cannot list or import any other folder.
+pub fn describe_ide_import() -> Value {
+    let sketchbook = find_ide_sketchbook();
+    let (sketches, libraries) = match &sketchbook {
+        Some(sketchbook) => (sketchbook_sketches(sketchbook), sketchbook_libraries(sketchbook)),
+        None => (vec![], vec![]),
+    };
+    serde_json::json!({
+        "sketchbook": sketchbook,
+        "sketches": names(&sketches),
+        "libraries": names(&libraries),
+        "last_import": read_ide_import(),
+    })
+}
+
+// Copy the sketches and libraries of the Arduino IDE sketchbook into the agent,
+// once. Sketches and libraries the agent already has are skipped.
+pub fn import_ide_sketchbook(
+    import_sketches: bool,
+    import_libraries: bool,
+    force: bool
+) -> Result<Value, String> {
+    if let (Some(last_import), false) = (read_ide_import(), force) {
+        return Err(format!("The Arduino IDE sketchbook was already imported: {}", last_import));
+    }
+    let sketchbook = find_ide_sketchbook().ok_or_else(|| {
+        "No Arduino IDE sketchbook found".to_string()
+    })?;
+
+    let mut imported = vec![];
+    let mut skipped = vec![];
+    let mut copy_all = |sources: Vec<PathBuf>, target_dir: PathBuf| -> Result<(), String> {
+        fs
+            ::create_dir_all(&target_dir)
+            .map_err(|e| format!("Failed to create {}: {}", target_dir.display(), e))?;
+        for source in sources {
+            let Some(name) = source.file_name() else {
+                continue;
+            };
+            let target = target_dir.join(name);
+            let name = name.to_string_lossy().to_string();
+            if target.exists() {
+                skipped.push(name);
+                continue;
+            }
+            copy_dir_recursive(&source, &target).map_err(|e| {
+                format!("Failed to import {}: {}", source.display(), e)
+            })?;
+            imported.push(name);
+        }
+        Ok(())
+    };
+    if import_sketches {
+        copy_all(sketchbook_sketches(&sketchbook), get_sketch_directory()?)?;
+    }
+    if import_libraries {
+        copy_all(sketchbook_libraries(&sketchbook), get_cli_user_directory().join("libraries"))?;
+    }
+
+    let result = serde_json::json!({
+        "sketchbook": sketchbook,
+        "imported": imported,
+        "skipped": skipped,
+        "imported_at": chrono::Utc::now().to_rfc3339(),
+    });
+    let marker = get_agent_home().join(IDE_IMPORT_FILE);
+    fs
+        ::write(&marker, serde_json::to_string_pretty(&result).unwrap_or_default())
+        .map_err(|e| format!("Failed to write {}: {}", marker.display(), e))?;
+    info!("Imported Arduino IDE sketchbook {}", sketchbook.display());
+    Ok(result)
+}
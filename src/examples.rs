@@ -0,0 +1,174 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{ Path, PathBuf };
+use serde_json::Value;
+use tracing::info;
+use crate::models::*;
+use crate::compiler::{ json_list, run_arduino_command };
+use crate::sketch_paths::{ resolve_sketch_dir, PathError, ALREADY_EXISTS, IO_ERROR, NOT_FOUND };
+use crate::sketches::rename_main_file;
+
+fn json_str(value: &Value, key: &str) -> Option<String> {
+    value
+        .get(key)
+        .and_then(|v| v.as_str())
+        .filter(|v| !v.is_empty())
+        .map(|v| v.to_string())
+}
+
+// List the examples of the installed libraries. With an fqbn the libraries
+// bundled with that board's core are included as well.
+pub async fn list_examples(fqbn: Option<&str>) -> Result<ExampleGroups, String> {
+    let mut args = vec!["examples".to_string(), "--format".to_string(), "json".to_string()];
+    if let Some(fqbn) = fqbn {
+        args.push("--fqbn".to_string());
+        args.push(fqbn.to_string());
+    }
+    let response = run_arduino_command(
+        &(ArduinoCommand {
+            command: "lib".to_string(),
+            args,
+        })
+    ).await;
+    if !response.success {
+        return Err(response.error.unwrap_or_else(|| "Failed to list examples".to_string()));
+    }
+    let json = response.output_json.unwrap_or(Value::Null);
+
+    let mut groups = ExampleGroups::default();
+    let mut cores: BTreeMap<String, Vec<LibraryExamples>> = BTreeMap::new();
+    for entry in json_list(&json, "examples") {
+        let Some(library) = entry.get("library") else {
+            continue;
+        };
+        let Some(name) = json_str(library, "name") else {
+            continue;
+        };
+        let examples_dir = json_str(library, "install_dir").map(|dir| {
+            Path::new(&dir).join("examples")
+        });
+        let examples = json_list(entry, "examples")
+            .into_iter()
+            .filter_map(|path| path.as_str())
+            .map(|path| {
+                let example_path = Path::new(path);
+                let relative = examples_dir
+                    .as_ref()
+                    .and_then(|dir| example_path.strip_prefix(dir).ok())
+                    .map(|relative| relative.to_path_buf())
+                    .or_else(|| example_path.file_name().map(PathBuf::from))
+                    .unwrap_or_default();
+                SketchExample {
+                    name: relative
+                        .components()
+                        .map(|part| part.as_os_str().to_string_lossy().to_string())
+                        .collect::<Vec<_>>()
+                        .join("/"),
+                    path: path.to_string(),
+                }
+            })
+            .collect::<Vec<_>>();
+        if examples.is_empty() {
+            continue;
+        }
+
+        let library_examples = LibraryExamples {
+            library: name,
+            version: json_str(library, "version"),
+            examples,
+        };
+        // Libraries bundled with a core are listed under the core
+        let location = json_str(library, "location").unwrap_or_default();
+        if location.to_lowercase().contains("platform") {
+            let core = json_str(library, "container_platform")
+                .or_else(|| fqbn.map(|fqbn| fqbn.split(':').take(2).collect::<Vec<_>>().join(":")))
+                .unwrap_or_default();
+            cores.entry(core).or_default().push(library_examples);
+        } else {
+            groups.libraries.push(library_examples);
+        }
+    }
+    groups.cores = cores
+        .into_iter()
+        .map(|(core, libraries)| CoreExamples { core, libraries })
+        .collect();
+    Ok(groups)
+}
+
+// Find the folder of an example by library and example name
+pub async fn find_example(
+    library: &str,
+    example: &str,
+    fqbn: Option<&str>
+) -> Result<PathBuf, String> {
+    let groups = list_examples(fqbn).await?;
+    groups.libraries
+        .iter()
+        .chain(groups.cores.iter().flat_map(|core| core.libraries.iter()))
+        .filter(|examples| examples.library == library)
+        .flat_map(|examples| examples.examples.iter())
+        .find(|candidate| candidate.name == example)
+        .map(|candidate| PathBuf::from(&candidate.path))
+        .ok_or_else(|| format!("Library {} has no example {}", library, example))
+}
+
+// Copy a directory with everything in it. Symlinks are skipped so a link inside a
+// sketch or example cannot pull in a file from elsewhere.
+pub fn copy_dir_recursive(from: &Path, to: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            copy_dir_recursive(&entry.path(), &target)?;
+        } else if file_type.is_file() {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+// Copy an example with its extra files into the sketches directory as a new sketch.
+// The main .ino file is renamed after the sketch, as arduino-cli requires.
+pub fn create_sketch_from_example(
+    example_path: &Path,
+    sketch_name: &str
+) -> Result<PathBuf, PathError> {
+    let sketch_path = resolve_sketch_dir(sketch_name)?;
+    if fs::symlink_metadata(&sketch_path).is_ok() {
+        return Err(
+            PathError::new(ALREADY_EXISTS, format!("Sketch {} already exists", sketch_name))
+        );
+    }
+    let example_name = example_path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .ok_or_else(|| {
+            PathError::new(NOT_FOUND, format!("Invalid example {}", example_path.display()))
+        })?;
+
+    // Copy next to the sketchbook first so a failed copy leaves no half sketch behind
+    let staging = sketch_path.with_file_name(format!(".{}.creating", sketch_name));
+    let _ = fs::remove_dir_all(&staging);
+    copy_dir_recursive(example_path, &staging)
+        .map_err(|e| {
+            PathError::new(IO_ERROR, format!("Failed to copy example {}: {}", example_name, e))
+        })
+        .and_then(|_| {
+            if example_name == sketch_name {
+                return Ok(());
+            }
+            rename_main_file(&staging, &example_name, sketch_name)
+        })
+        .and_then(|_| {
+            fs::rename(&staging, &sketch_path).map_err(|e| {
+                PathError::new(IO_ERROR, format!("Failed to create sketch {}: {}", sketch_name, e))
+            })
+        })
+        .inspect_err(|_| {
+            let _ = fs::remove_dir_all(&staging);
+        })?;
+    info!("Created sketch {} from example {}", sketch_name, example_path.display());
+    Ok(sketch_path)
+}
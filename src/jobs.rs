@@ -0,0 +1,389 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{ AtomicU64, Ordering };
+use std::sync::{ Arc, Mutex, OnceLock };
+use futures::future::BoxFuture;
+use tokio::sync::{ broadcast, watch, OwnedMutexGuard, OwnedSemaphorePermit, Semaphore };
+use tracing::{ info, error };
+use crate::models::*;
+use crate::compiler::{ run_arduino_command, run_arduino_command_streaming };
+use crate::logs::{ forget_job_log, log_args, tail_job_log };
+
+// How many compiles may run at the same time
+const MAX_CONCURRENT_COMPILES: usize = 2;
+// How many finished jobs are kept around for list-jobs
+const MAX_FINISHED_JOBS: usize = 50;
+
+static JOB_COUNTER: AtomicU64 = AtomicU64::new(0);
+static JOBS: OnceLock<Mutex<HashMap<String, JobEntry>>> = OnceLock::new();
+static RESOURCE_LOCKS: OnceLock<Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>> = OnceLock::new();
+static COMPILE_SLOTS: OnceLock<Arc<Semaphore>> = OnceLock::new();
+static JOB_EVENTS: OnceLock<broadcast::Sender<JobInfo>> = OnceLock::new();
+
+// Work that undoes a job's partial results when it is cancelled
+pub enum JobCleanup {
+    // Remove a directory, e.g. a half-written build directory
+    RemoveDir(PathBuf),
+    // Run an Arduino CLI command, e.g. uninstall a partially installed core
+    Command(ArduinoCommand),
+}
+
+// Runs once the job holds its locks and returns cleanup that depends on the state
+// found then, e.g. whether a core was already installed before installing it
+pub type JobCheck = Box<dyn FnOnce() -> BoxFuture<'static, Vec<JobCleanup>> + Send>;
+// Runs right before the command starts, while the job holds its locks
+pub type JobPrepare = Box<dyn FnOnce() + Send>;
+// Runs after the command finished (but was not cancelled), while the job holds its locks
+pub type JobFinish = Box<dyn FnOnce(CommandResponse) -> CommandResponse + Send>;
+
+// A long-running Arduino CLI command together with how to schedule and clean up after it
+pub struct JobRequest {
+    pub job_id: String,
+    // The socket command that started the job, e.g. `compile-sketch`
+    pub kind: String,
+    // Jobs on the same sketch run one after another
+    pub sketch: Option<String>,
+    // Jobs on the same port run one after another
+    pub port: Option<String>,
+    pub command: ArduinoCommand,
+    pub cleanup: Vec<JobCleanup>,
+    pub check: Option<JobCheck>,
+    pub prepare: Option<JobPrepare>,
+    pub finish: Option<JobFinish>,
+}
+
+struct JobEntry {
+    info: JobInfo,
+    cancel: watch::Sender<bool>,
+    finished: watch::Receiver<bool>,
+}
+
+// Locks and slots held by a job while it runs
+struct JobResources {
+    _port: Option<OwnedMutexGuard<()>>,
+    _sketch: Option<OwnedMutexGuard<()>>,
+    _compile: Option<OwnedSemaphorePermit>,
+}
+
+fn jobs() -> &'static Mutex<HashMap<String, JobEntry>> {
+    JOBS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn compile_slots() -> Arc<Semaphore> {
+    Arc::clone(COMPILE_SLOTS.get_or_init(|| Arc::new(Semaphore::new(MAX_CONCURRENT_COMPILES))))
+}
+
+fn job_events() -> &'static broadcast::Sender<JobInfo> {
+    JOB_EVENTS.get_or_init(|| broadcast::channel(256).0)
+}
+
+fn resource_lock(key: String) -> Arc<tokio::sync::Mutex<()>> {
+    let locks = RESOURCE_LOCKS.get_or_init(|| Mutex::new(HashMap::new()));
+    match locks.lock() {
+        Ok(mut locks) => Arc::clone(locks.entry(key).or_default()),
+        Err(e) => {
+            error!(?e, "Failed to lock resource locks");
+            Arc::new(tokio::sync::Mutex::new(()))
+        }
+    }
+}
+
+// Take a sketch's job lock outside of a job, e.g. while renaming the sketch.
+// None while a job holds it.
+pub fn try_lock_sketch(sketch: &str) -> Option<OwnedMutexGuard<()>> {
+    resource_lock(format!("sketch:{}", sketch)).try_lock_owned().ok()
+}
+
+// Generate a unique id for a long-running job
+pub fn new_job_id() -> String {
+    let sequence = JOB_COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("job-{}-{}", chrono::Utc::now().timestamp_millis(), sequence)
+}
+
+// Subscribe to job state changes (queued, started and finished)
+pub fn subscribe_job_events() -> broadcast::Receiver<JobInfo> {
+    job_events().subscribe()
+}
+
+// List queued, running and recently finished jobs, oldest first
+pub fn list_jobs() -> Vec<JobInfo> {
+    let mut list = match jobs().lock() {
+        Ok(jobs) =>
+            jobs
+                .values()
+                .map(|entry| entry.info.clone())
+                .collect::<Vec<_>>(),
+        Err(e) => {
+            error!(?e, "Failed to lock jobs");
+            vec![]
+        }
+    };
+    list.sort_by(|a, b| a.queued_at.cmp(&b.queued_at));
+    list
+}
+
+// Update a job's info and let subscribers know about it
+fn update_job<F>(job_id: &str, update: F) where F: FnOnce(&mut JobInfo) {
+    let info = match jobs().lock() {
+        Ok(mut jobs) =>
+            jobs.get_mut(job_id).map(|entry| {
+                update(&mut entry.info);
+                entry.info.clone()
+            }),
+        Err(e) => {
+            error!(?e, "Failed to lock jobs");
+            None
+        }
+    };
+    if let Some(info) = info {
+        job_events().send(info).ok();
+    }
+}
+
+// Drop the oldest finished jobs once there are too many of them
+fn prune_finished_jobs() {
+    if let Ok(mut jobs) = jobs().lock() {
+        let mut finished = jobs
+            .values()
+            .filter_map(|entry| {
+                entry.info.finished_at.clone().map(|finished_at| (finished_at, entry.info.job_id.clone()))
+            })
+            .collect::<Vec<_>>();
+        if finished.len() > MAX_FINISHED_JOBS {
+            finished.sort();
+            let excess = finished.len() - MAX_FINISHED_JOBS;
+            for (_, job_id) in finished.into_iter().take(excess) {
+                jobs.remove(&job_id);
+            }
+        }
+    }
+}
+
+// Wait for the port, the sketch and, for compiles, a compile slot.
+// Locks are always taken in the same order so two jobs can never deadlock.
+// Takes owned values, as the request's callbacks cannot be shared across an await.
+async fn acquire_resources(
+    port: Option<String>,
+    sketch: Option<String>,
+    compile: bool
+) -> JobResources {
+    let port = match port {
+        Some(port) => Some(resource_lock(format!("port:{}", port)).lock_owned().await),
+        None => None,
+    };
+    let sketch = match sketch {
+        Some(sketch) => Some(resource_lock(format!("sketch:{}", sketch)).lock_owned().await),
+        None => None,
+    };
+    let compile = if compile {
+        compile_slots().acquire_owned().await.ok()
+    } else {
+        None
+    };
+    JobResources {
+        _port: port,
+        _sketch: sketch,
+        _compile: compile,
+    }
+}
+
+// Queue a job, run it once its port and sketch are free and report output lines through `on_output`
+pub async fn run_job<F>(mut request: JobRequest, on_output: F) -> CommandResponse
+    where F: FnMut(&str, &str)
+{
+    let job_id = request.job_id.clone();
+    let (cancel_tx, cancel_rx) = watch::channel(false);
+    let (finished_tx, finished_rx) = watch::channel(false);
+    let info = JobInfo {
+        job_id: job_id.clone(),
+        kind: request.kind.clone(),
+        status: JobStatus::Queued,
+        sketch: request.sketch.clone(),
+        port: request.port.clone(),
+        queued_at: chrono::Utc::now().to_rfc3339(),
+        started_at: None,
+        finished_at: None,
+    };
+
+    match jobs().lock() {
+        Ok(mut jobs) => {
+            let active = jobs
+                .get(&job_id)
+                .map(|entry| matches!(entry.info.status, JobStatus::Queued | JobStatus::Running))
+                .unwrap_or(false);
+            if active {
+                return CommandResponse {
+                    success: false,
+                    output: String::new(),
+                    output_json: None,
+                    files: None,
+                    error: Some(format!("Job {} is already running", job_id)),
+                    command: request.command.command,
+                    args: request.command.args,
+                    job_id: Some(job_id),
+                    error_code: None,
+                };
+            }
+            jobs.insert(job_id.clone(), JobEntry {
+                info: info.clone(),
+                cancel: cancel_tx,
+                finished: finished_rx,
+            });
+        }
+        Err(e) => {
+            error!(?e, "Failed to lock jobs");
+        }
+    }
+    job_events().send(info).ok();
+
+    // Wait in the queue until the job's resources are free, unless it is cancelled first
+    let mut queue_cancel = cancel_rx.clone();
+    let wanted = acquire_resources(
+        request.port.clone(),
+        request.sketch.clone(),
+        request.command.command == "compile"
+    );
+    let resources = tokio::select! {
+        resources = wanted => Some(resources),
+        true = async { queue_cancel.wait_for(|cancelled| *cancelled).await.is_ok() } => None,
+    };
+
+    let started = resources.is_some();
+    let (response, status) = match resources {
+        Some(resources) => {
+            update_job(&job_id, |info| {
+                info.status = JobStatus::Running;
+                info.started_at = Some(chrono::Utc::now().to_rfc3339());
+            });
+            if let Some(check) = request.check {
+                request.cleanup.extend(check().await);
+            }
+            if let Some(prepare) = request.prepare {
+                prepare();
+            }
+            // Every job logs to its own file, published while the job runs
+            request.command.args.extend(log_args(&job_id));
+            tokio::spawn(tail_job_log(job_id.clone(), finished_tx.subscribe()));
+
+            let response = run_arduino_command_streaming(
+                &request.command,
+                Some(cancel_rx.clone()),
+                on_output
+            ).await;
+
+            let cancelled = *cancel_rx.borrow();
+            let response = if cancelled {
+                info!(?job_id, "Job cancelled, cleaning up");
+                for cleanup in request.cleanup {
+                    run_cleanup(cleanup).await;
+                }
+                response
+            } else {
+                match request.finish {
+                    Some(finish) => finish(response),
+                    None => response,
+                }
+            };
+            drop(resources);
+
+            if cancelled {
+                (response, JobStatus::Cancelled)
+            } else if response.success {
+                (response, JobStatus::Succeeded)
+            } else {
+                (response, JobStatus::Failed)
+            }
+        }
+        None => {
+            info!(?job_id, "Job cancelled while queued");
+            let response = CommandResponse {
+                success: false,
+                output: String::new(),
+                output_json: None,
+                files: None,
+                error: None,
+                command: request.command.command,
+                args: request.command.args,
+                job_id: None,
+                error_code: None,
+            };
+            (response, JobStatus::Cancelled)
+        }
+    };
+
+    update_job(&job_id, |info| {
+        info.status = status;
+        info.finished_at = Some(chrono::Utc::now().to_rfc3339());
+    });
+    prune_finished_jobs();
+    finished_tx.send_replace(true);
+    // The log tail ends the subscriptions after its last read, a job that never
+    // started has no tail
+    if !started {
+        forget_job_log(&job_id);
+    }
+
+    if status == JobStatus::Cancelled {
+        return CommandResponse {
+            success: false,
+            output_json: Some(
+                serde_json::json!({
+                    "job_id": job_id,
+                    "status": JobStatus::Cancelled,
+                })
+            ),
+            error: Some(format!("Job {} was cancelled", job_id)),
+            job_id: Some(job_id),
+            ..response
+        };
+    }
+    CommandResponse {
+        job_id: Some(job_id),
+        ..response
+    }
+}
+
+// Cancel a queued or running job and wait until it has stopped and been cleaned up
+pub async fn cancel_job(job_id: &str) -> Result<(), String> {
+    let mut finished = match jobs().lock() {
+        Ok(jobs) =>
+            match jobs.get(job_id) {
+                Some(job) if matches!(job.info.status, JobStatus::Queued | JobStatus::Running) => {
+                    job.cancel.send_replace(true);
+                    job.finished.clone()
+                }
+                _ => {
+                    return Err(format!("Job {} is not running", job_id));
+                }
+            }
+        Err(e) => {
+            return Err(format!("Failed to lock jobs: {}", e));
+        }
+    };
+    finished.wait_for(|done| *done).await.ok();
+    Ok(())
+}
+
+async fn run_cleanup(cleanup: JobCleanup) {
+    match cleanup {
+        JobCleanup::RemoveDir(path) => {
+            if path.exists() {
+                match tokio::fs::remove_dir_all(&path).await {
+                    Ok(_) => info!(?path, "Removed directory of cancelled job"),
+                    Err(e) => error!(?e, ?path, "Failed to remove directory of cancelled job"),
+                }
+            }
+        }
+        JobCleanup::Command(command) => {
+            let response = run_arduino_command(&command).await;
+            if !response.success {
+                error!(
+                    "Cleanup command {} {:?} failed: {}",
+                    command.command,
+                    command.args,
+                    response.error.unwrap_or_default()
+                );
+            }
+        }
+    }
+}
@@ -2,3 +2,22 @@ pub mod models;
 pub mod socketio;
 pub mod usb;
 pub mod compiler;
+pub mod jobs;
+pub mod progress;
+pub mod builds;
+pub mod sketch_yaml;
+pub mod boards;
+pub mod board_manager;
+pub mod config;
+pub mod libraries;
+pub mod examples;
+pub mod compilation_database;
+pub mod check;
+pub mod environment;
+pub mod logs;
+pub mod sketch_paths;
+pub mod sketches;
+pub mod sketch_archive;
+pub mod diff;
+pub mod snapshots;
+pub mod templates;
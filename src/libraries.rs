@@ -0,0 +1,218 @@
+use std::cmp::Ordering;
+use futures::stream::{ self, StreamExt };
+use semver::Version;
+use serde_json::Value;
+use crate::models::*;
+use crate::compiler::{ json_list, run_arduino_command };
+
+// How many `lib deps` lookups list-outdated-libraries runs at once
+const MAX_CONCURRENT_LOOKUPS: usize = 4;
+
+// Parse a library version, padding short versions such as "1.2" or "v2" to three parts
+fn parse_version(version: &str) -> Option<Version> {
+    let version = version.trim().trim_start_matches('v');
+    if let Ok(parsed) = Version::parse(version) {
+        return Some(parsed);
+    }
+    let (core, suffix) = version.split_at(version.find(['-', '+']).unwrap_or(version.len()));
+    let mut parts = core.split('.').collect::<Vec<_>>();
+    if parts.len() > 3 {
+        return None;
+    }
+    parts.resize(3, "0");
+    Version::parse(&format!("{}{}", parts.join("."), suffix)).ok()
+}
+
+// Major part of a version such as "1.2.3"
+fn major_version(version: &str) -> Option<u64> {
+    parse_version(version).map(|parsed| parsed.major)
+}
+
+// Compare versions with semver precedence, so "1.0.0-beta" comes before "1.0.0"
+// and "1.10.0" after "1.9.2". Versions that are not semver are compared as text.
+fn compare_versions(a: &str, b: &str) -> Ordering {
+    match (parse_version(a), parse_version(b)) {
+        (Some(a), Some(b)) => a.cmp_precedence(&b),
+        _ => a.cmp(b),
+    }
+}
+
+fn json_str(value: &Value, key: &str) -> Option<String> {
+    value
+        .get(key)
+        .and_then(|v| v.as_str())
+        .filter(|v| !v.is_empty())
+        .map(|v| v.to_string())
+}
+
+// What installing `version_required` of a library does to the installed version
+pub fn dependency_change(
+    name: String,
+    version_required: String,
+    version_installed: Option<String>
+) -> LibraryDependency {
+    let (action, breaking) = match &version_installed {
+        Some(installed) => {
+            let action = match compare_versions(&version_required, installed) {
+                Ordering::Greater => "upgrade",
+                Ordering::Less => "downgrade",
+                Ordering::Equal => "none",
+            };
+            (action, major_version(installed) != major_version(&version_required))
+        }
+        None => ("install", false),
+    };
+    LibraryDependency {
+        name,
+        version_required,
+        version_installed,
+        action: action.to_string(),
+        breaking,
+    }
+}
+
+// arduino-cli matches library names ignoring case
+pub fn same_library(a: &str, b: &str) -> bool {
+    a.eq_ignore_ascii_case(b)
+}
+
+// Every library that installing `library` (or `library@version`) needs, including itself
+pub async fn resolve_dependencies(library: &str) -> Result<Vec<LibraryDependency>, String> {
+    let command = ArduinoCommand {
+        command: "lib".to_string(),
+        args: vec![
+            "deps".to_string(),
+            library.to_string(),
+            "--format".to_string(),
+            "json".to_string()
+        ],
+    };
+    let response = run_arduino_command(&command).await;
+    if !response.success {
+        return Err(
+            response.error.unwrap_or_else(|| {
+                format!("Failed to resolve dependencies of {}", library)
+            })
+        );
+    }
+    let json = response.output_json.unwrap_or(Value::Null);
+
+    Ok(
+        json_list(&json, "dependencies")
+            .into_iter()
+            .filter_map(|dependency| {
+                Some(
+                    dependency_change(
+                        json_str(dependency, "name")?,
+                        json_str(dependency, "version_required")?,
+                        json_str(dependency, "version_installed")
+                    )
+                )
+            })
+            .collect()
+    )
+}
+
+// The libraries that installing `library` (or `library@version`) needs, without itself
+pub async fn library_dependencies(library: &str) -> Result<Vec<LibraryDependency>, String> {
+    let library_name = library.split('@').next().unwrap_or(library);
+    Ok(
+        resolve_dependencies(library).await?
+            .into_iter()
+            .filter(|dependency| !same_library(&dependency.name, library_name))
+            .collect()
+    )
+}
+
+// The installed libraries that have a newer release, with what upgrading them would change
+pub async fn list_outdated_libraries() -> Result<Vec<OutdatedLibrary>, String> {
+    let command = ArduinoCommand {
+        command: "lib".to_string(),
+        args: vec![
+            "list".to_string(),
+            "--updatable".to_string(),
+            "--format".to_string(),
+            "json".to_string()
+        ],
+    };
+    let response = run_arduino_command(&command).await;
+    if !response.success {
+        return Err(response.error.unwrap_or_else(|| "Failed to list libraries".to_string()));
+    }
+    let json = response.output_json.unwrap_or(Value::Null);
+
+    let releases = json_list(&json, "installed_libraries")
+        .into_iter()
+        .filter_map(|entry| {
+            let name = json_str(entry.get("library")?, "name")?;
+            let installed_version = json_str(entry.get("library")?, "version")?;
+            let available_version = json_str(entry.get("release")?, "version")?;
+            Some((name, installed_version, available_version))
+        })
+        .collect::<Vec<_>>();
+
+    // One `lib deps` run per library, a few at a time so many updates stay quick
+    let outdated = stream
+        ::iter(releases)
+        .map(|(name, installed_version, available_version)| async move {
+            let release = format!("{}@{}", name, available_version);
+            let dependency_changes = library_dependencies(&release).await
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|dependency| dependency.action != "none")
+                .collect();
+            let major_update =
+                major_version(&installed_version) != major_version(&available_version);
+            OutdatedLibrary {
+                major_update,
+                name,
+                installed_version,
+                available_version,
+                dependency_changes,
+            }
+        })
+        .buffered(MAX_CONCURRENT_LOOKUPS)
+        .collect::<Vec<_>>().await;
+    Ok(outdated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orders_pre_releases_before_releases() {
+        assert_eq!(compare_versions("1.0.0-beta", "1.0.0"), Ordering::Less);
+        assert_eq!(compare_versions("1.0.0-beta.2", "1.0.0-beta.11"), Ordering::Less);
+        assert_eq!(compare_versions("1.0.0-alpha", "1.0.0-beta"), Ordering::Less);
+    }
+
+    #[test]
+    fn compares_parts_as_numbers() {
+        assert_eq!(compare_versions("1.10.0", "1.9.2"), Ordering::Greater);
+        assert_eq!(compare_versions("v2.0.0", "2.0.0"), Ordering::Equal);
+        assert_eq!(compare_versions("1.2", "1.2.0"), Ordering::Equal);
+        assert_eq!(compare_versions("1.2", "1.2.1"), Ordering::Less);
+        assert_eq!(compare_versions("1.0.0+build.5", "1.0.0"), Ordering::Equal);
+    }
+
+    #[test]
+    fn describes_dependency_changes() {
+        let change = dependency_change("Servo".into(), "1.2.1".into(), Some("1.1.8".into()));
+        assert_eq!((change.action.as_str(), change.breaking), ("upgrade", false));
+        let change = dependency_change("Servo".into(), "1.2.1".into(), Some("2.0.0".into()));
+        assert_eq!((change.action.as_str(), change.breaking), ("downgrade", true));
+        let change = dependency_change("Servo".into(), "1.2".into(), Some("1.2.0".into()));
+        assert_eq!(change.action, "none");
+        assert_eq!(dependency_change("Servo".into(), "1.2.1".into(), None).action, "install");
+        assert!(same_library("Adafruit_GFX", "adafruit_gfx"));
+    }
+
+    #[test]
+    fn reads_major_versions() {
+        assert_eq!(major_version("2.1.0"), Some(2));
+        assert_eq!(major_version("v3"), Some(3));
+        assert_eq!(major_version("2.0.0-rc1"), Some(2));
+        assert_eq!(major_version("latest"), None);
+    }
+}
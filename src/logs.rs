@@ -0,0 +1,496 @@
+use std::collections::{ BTreeMap, HashMap, HashSet };
+use std::fs;
+use std::io::{ Read, Seek, SeekFrom };
+use std::path::PathBuf;
+use std::sync::{ Arc, Mutex, OnceLock };
+use std::time::{ Duration, SystemTime };
+use serde_json::Value;
+use notify::{ RecursiveMode, Watcher };
+use tokio::sync::{ broadcast, mpsc, watch };
+use tracing::{ info, error };
+use crate::models::{ JobLog, LogEntry };
+use crate::environment::get_agent_home;
+
+// How many job logs are kept, and for how long
+const MAX_JOB_LOGS: usize = 200;
+const MAX_JOB_LOG_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+// How often a log is read when there is no file watcher, or as a safety net next to it
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+const FALLBACK_POLL_INTERVAL: Duration = Duration::from_secs(2);
+const MAX_JOB_ID_LENGTH: usize = 64;
+
+static LOG_EVENTS: OnceLock<broadcast::Sender<LogEvent>> = OnceLock::new();
+// Socket ids that receive the log entries of a job, by job id
+static LOG_SUBSCRIBERS: OnceLock<Mutex<HashMap<String, HashSet<String>>>> = OnceLock::new();
+
+// A published log entry and the sockets subscribed to its job when it was published,
+// so entries still reach them after the job's subscriptions ended
+#[derive(Clone, Debug)]
+pub struct LogEvent {
+    pub log: JobLog,
+    pub sockets: Arc<HashSet<String>>,
+}
+
+fn log_events() -> &'static broadcast::Sender<LogEvent> {
+    LOG_EVENTS.get_or_init(|| broadcast::channel(1024).0)
+}
+
+fn log_subscribers() -> &'static Mutex<HashMap<String, HashSet<String>>> {
+    LOG_SUBSCRIBERS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+// Directory the per-job arduino-cli logs are written to
+pub fn get_logs_directory() -> PathBuf {
+    get_agent_home().join("logs")
+}
+
+// Job ids come from clients and name log files, so they are limited to characters
+// that are safe in a file name. Other ids are refused rather than rewritten, which
+// could give two jobs the same log.
+fn is_valid_job_id(job_id: &str) -> bool {
+    !job_id.is_empty() &&
+        job_id.len() <= MAX_JOB_ID_LENGTH &&
+        job_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+pub fn job_log_path(job_id: &str) -> Result<PathBuf, String> {
+    if !is_valid_job_id(job_id) {
+        return Err(
+            format!(
+                "Invalid job id {:?}, use up to {} letters, digits, - and _",
+                job_id,
+                MAX_JOB_ID_LENGTH
+            )
+        );
+    }
+    Ok(get_logs_directory().join(format!("{}.log", job_id)))
+}
+
+// arduino-cli flags that write a job's log to its own file
+pub fn log_args(job_id: &str) -> Vec<String> {
+    let path = match job_log_path(job_id) {
+        Ok(path) => path,
+        Err(error_msg) => {
+            error!("Not logging job: {}", error_msg);
+            return vec![];
+        }
+    };
+    prune_logs();
+    if let Err(e) = fs::create_dir_all(get_logs_directory()) {
+        error!(?e, "Failed to create logs directory");
+    }
+    vec![
+        "--log".to_string(),
+        "--log-format".to_string(),
+        "json".to_string(),
+        "--log-file".to_string(),
+        path.to_string_lossy().to_string()
+    ]
+}
+
+// Rotate old job logs away: drop logs past their age, then the oldest beyond the limit
+pub fn prune_logs() {
+    let Ok(entries) = fs::read_dir(get_logs_directory()) else {
+        return;
+    };
+    let mut logs = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "log"))
+        .map(|entry| {
+            let modified = entry
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+            (modified, entry.path())
+        })
+        .collect::<Vec<_>>();
+    logs.sort();
+
+    let now = SystemTime::now();
+    let excess = logs.len().saturating_sub(MAX_JOB_LOGS);
+    for (index, (modified, path)) in logs.iter().enumerate() {
+        let expired = now.duration_since(*modified).is_ok_and(|age| age > MAX_JOB_LOG_AGE);
+        if (index < excess || expired) && let Err(e) = fs::remove_file(path) {
+            error!(?e, ?path, "Failed to remove old job log");
+        }
+    }
+}
+
+pub fn subscribe_log_events() -> broadcast::Receiver<LogEvent> {
+    log_events().subscribe()
+}
+
+// Send the log entries of a job to a socket
+pub fn subscribe_job_log(job_id: &str, socket_id: &str) {
+    match log_subscribers().lock() {
+        Ok(mut subscribers) => {
+            subscribers.entry(job_id.to_string()).or_default().insert(socket_id.to_string());
+        }
+        Err(e) => error!(?e, "Failed to lock log subscribers"),
+    }
+}
+
+pub fn unsubscribe_job_log(job_id: &str, socket_id: &str) {
+    match log_subscribers().lock() {
+        Ok(mut subscribers) => {
+            if let Some(sockets) = subscribers.get_mut(job_id) {
+                sockets.remove(socket_id);
+                if sockets.is_empty() {
+                    subscribers.remove(job_id);
+                }
+            }
+        }
+        Err(e) => error!(?e, "Failed to lock log subscribers"),
+    }
+}
+
+// Forget every subscription of a disconnected socket
+pub fn unsubscribe_socket(socket_id: &str) {
+    match log_subscribers().lock() {
+        Ok(mut subscribers) => {
+            subscribers.retain(|_, sockets| {
+                sockets.remove(socket_id);
+                !sockets.is_empty()
+            });
+        }
+        Err(e) => error!(?e, "Failed to lock log subscribers"),
+    }
+}
+
+// Forget every subscription of a job that has finished
+pub fn forget_job_log(job_id: &str) {
+    match log_subscribers().lock() {
+        Ok(mut subscribers) => {
+            subscribers.remove(job_id);
+        }
+        Err(e) => error!(?e, "Failed to lock log subscribers"),
+    }
+}
+
+fn job_subscribers(job_id: &str) -> Arc<HashSet<String>> {
+    match log_subscribers().lock() {
+        Ok(subscribers) => Arc::new(subscribers.get(job_id).cloned().unwrap_or_default()),
+        Err(_) => Arc::new(HashSet::new()),
+    }
+}
+
+// Turn an arduino-cli log line into a typed entry. JSON lines look like
+// {"level":"info","msg":"Compiling sketch","time":"...","sketch":"..."},
+// other lines become info entries.
+pub fn parse_log_line(line: &str) -> LogEntry {
+    let mut fields = match serde_json::from_str::<Value>(line) {
+        Ok(Value::Object(fields)) => fields,
+        _ => {
+            return LogEntry {
+                level: "info".to_string(),
+                message: line.to_string(),
+                time: chrono::Utc::now().to_rfc3339(),
+                fields: BTreeMap::new(),
+            };
+        }
+    };
+    let mut take = |keys: &[&str]| {
+        keys.iter()
+            .find_map(|key| fields.remove(*key))
+            .map(|value| {
+                match value {
+                    Value::String(text) => text,
+                    other => other.to_string(),
+                }
+            })
+    };
+    let level = take(&["level"]).unwrap_or_else(|| "info".to_string());
+    let message = take(&["msg", "message"]).unwrap_or_default();
+    let time = take(&["time", "timestamp"]).unwrap_or_else(|| chrono::Utc::now().to_rfc3339());
+    LogEntry {
+        level,
+        message,
+        time,
+        fields: fields.into_iter().collect(),
+    }
+}
+
+// Read the whole log of a job
+pub fn read_job_log(job_id: &str) -> Result<Vec<LogEntry>, String> {
+    let path = job_log_path(job_id)?;
+    let content = fs
+        ::read(&path)
+        .map_err(|e| format!("No log for job {}: {}", job_id, e))?;
+    Ok(
+        String::from_utf8_lossy(&content)
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(parse_log_line)
+            .collect()
+    )
+}
+
+// Something that tells a rotated log file apart from the one read before
+#[cfg(unix)]
+fn file_identity(metadata: &fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.ino())
+}
+
+#[cfg(not(unix))]
+fn file_identity(metadata: &fs::Metadata) -> Option<u64> {
+    metadata
+        .created()
+        .ok()
+        .and_then(|created| created.duration_since(SystemTime::UNIX_EPOCH).ok())
+        .map(|created| created.as_nanos() as u64)
+}
+
+// Follows a log file by byte offset, so every line is published exactly once
+struct LogTail {
+    path: PathBuf,
+    identity: Option<u64>,
+    position: u64,
+    // Bytes of a line that is still being written
+    pending: Vec<u8>,
+}
+
+impl LogTail {
+    fn new(path: PathBuf) -> Self {
+        LogTail {
+            path,
+            identity: None,
+            position: 0,
+            pending: vec![],
+        }
+    }
+
+    // Read what was appended since the last read and return the complete lines
+    fn read_lines(&mut self) -> Vec<String> {
+        let Ok(mut file) = fs::File::open(&self.path) else {
+            return vec![];
+        };
+        let Ok(metadata) = file.metadata() else {
+            return vec![];
+        };
+        let identity = file_identity(&metadata);
+        // A new file (rotation) or a shorter one (truncation) is read from the start
+        if identity != self.identity || metadata.len() < self.position {
+            self.identity = identity;
+            self.position = 0;
+            self.pending.clear();
+        }
+        if metadata.len() == self.position {
+            return vec![];
+        }
+
+        let mut buffer = vec![];
+        let read = file
+            .seek(SeekFrom::Start(self.position))
+            .and_then(|_| file.read_to_end(&mut buffer));
+        match read {
+            Ok(bytes) => {
+                self.position += bytes as u64;
+                self.pending.extend_from_slice(&buffer);
+            }
+            Err(e) => {
+                info!(?e, "Error reading job log");
+                return vec![];
+            }
+        }
+
+        // Decode whole lines only, so multi-byte characters are never split
+        let mut lines = vec![];
+        while let Some(end) = self.pending.iter().position(|byte| *byte == b'\n') {
+            let line = self.pending.drain(..=end).collect::<Vec<_>>();
+            let line = String::from_utf8_lossy(&line).trim().to_string();
+            if !line.is_empty() {
+                lines.push(line);
+            }
+        }
+        lines
+    }
+}
+
+// Publish new entries of a job's log until the job has finished, then end the job's
+// subscriptions. Reads are driven by file system events, with a slow poll as a safety
+// net for missed events.
+pub async fn tail_job_log(job_id: String, mut finished: watch::Receiver<bool>) {
+    let Ok(path) = job_log_path(&job_id) else {
+        return;
+    };
+    let (events_tx, mut events_rx) = mpsc::unbounded_channel();
+    let watched_path = path.clone();
+    let watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        if let Ok(event) = event && event.paths.iter().any(|path| path == &watched_path) {
+            events_tx.send(()).ok();
+        }
+    });
+    // Watch the directory, the log file is only created once arduino-cli starts
+    let watcher = match watcher {
+        Ok(mut watcher) =>
+            match watcher.watch(&get_logs_directory(), RecursiveMode::NonRecursive) {
+                Ok(()) => Some(watcher),
+                Err(e) => {
+                    error!(?e, "Failed to watch logs directory, polling instead");
+                    None
+                }
+            }
+        Err(e) => {
+            error!(?e, "Failed to create log watcher, polling instead");
+            None
+        }
+    };
+    let poll_interval = if watcher.is_some() { FALLBACK_POLL_INTERVAL } else { POLL_INTERVAL };
+
+    let mut tail = LogTail::new(path);
+    let mut closed = false;
+    loop {
+        let done = *finished.borrow() || closed;
+        let lines = tail.read_lines();
+        if !lines.is_empty() {
+            let sockets = job_subscribers(&job_id);
+            for line in lines {
+                log_events()
+                    .send(LogEvent {
+                        log: JobLog {
+                            job_id: job_id.clone(),
+                            entry: parse_log_line(&line),
+                        },
+                        sockets: sockets.clone(),
+                    })
+                    .ok();
+            }
+        }
+        if done {
+            forget_job_log(&job_id);
+            break;
+        }
+        tokio::select! {
+            changed = finished.changed() => {
+                closed = changed.is_err();
+            }
+            Some(_) = events_rx.recv() => {
+                // Handle a burst of events with a single read
+                while events_rx.try_recv().is_ok() {}
+            }
+            _ = tokio::time::sleep(poll_interval) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn append(path: &std::path::Path, bytes: &[u8]) {
+        let mut file = fs::OpenOptions::new().create(true).append(true).open(path).unwrap();
+        file.write_all(bytes).unwrap();
+    }
+
+    #[test]
+    fn parses_json_log_lines() {
+        let entry = parse_log_line(
+            r#"{"level":"warning","msg":"Port busy","time":"2025-01-01T00:00:00Z","port":"COM3"}"#
+        );
+        assert_eq!(entry.level, "warning");
+        assert_eq!(entry.message, "Port busy");
+        assert_eq!(entry.time, "2025-01-01T00:00:00Z");
+        assert_eq!(entry.fields.len(), 1);
+        assert_eq!(entry.fields["port"], "COM3");
+    }
+
+    #[test]
+    fn keeps_other_lines_as_info_messages() {
+        let entry = parse_log_line("Sketch uses 924 bytes");
+        assert_eq!(entry.level, "info");
+        assert_eq!(entry.message, "Sketch uses 924 bytes");
+        assert!(entry.fields.is_empty());
+
+        // JSON that is not an object is not a log entry either
+        assert_eq!(parse_log_line("42").message, "42");
+    }
+
+    #[test]
+    fn accepts_alternative_field_names() {
+        let entry = parse_log_line(r#"{"message":"Done","timestamp":"2025-01-01T00:00:00Z"}"#);
+        assert_eq!(entry.level, "info");
+        assert_eq!(entry.message, "Done");
+        assert_eq!(entry.time, "2025-01-01T00:00:00Z");
+    }
+
+    #[test]
+    fn refuses_unsafe_job_ids() {
+        assert!(job_log_path("compile-1_a").is_ok());
+        assert!(job_log_path("").is_err());
+        assert!(job_log_path("../secret").is_err());
+        assert!(job_log_path("a/b").is_err());
+        assert!(job_log_path("job.log").is_err());
+        assert!(job_log_path(&"a".repeat(MAX_JOB_ID_LENGTH + 1)).is_err());
+        assert!(log_args("../secret").is_empty());
+    }
+
+    #[test]
+    fn finished_jobs_end_their_subscriptions() {
+        subscribe_job_log("subscribed-job", "socket-a");
+        subscribe_job_log("subscribed-job", "socket-b");
+        unsubscribe_job_log("subscribed-job", "socket-b");
+        let sockets = job_subscribers("subscribed-job");
+        assert_eq!(sockets.len(), 1);
+        assert!(sockets.contains("socket-a"));
+
+        forget_job_log("subscribed-job");
+        assert!(job_subscribers("subscribed-job").is_empty());
+        // Entries published before keep their sockets
+        assert!(sockets.contains("socket-a"));
+    }
+
+    #[test]
+    fn tail_returns_each_complete_line_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("job.log");
+        let mut tail = LogTail::new(path.clone());
+        assert!(tail.read_lines().is_empty());
+
+        append(&path, b"first\nsec");
+        assert_eq!(tail.read_lines(), vec!["first"]);
+        assert!(tail.read_lines().is_empty());
+        append(&path, b"ond\n\nthird\n");
+        assert_eq!(tail.read_lines(), vec!["second", "third"]);
+    }
+
+    #[test]
+    fn tail_does_not_split_multi_byte_characters() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("job.log");
+        let mut tail = LogTail::new(path.clone());
+        let line = "Größe\n".as_bytes();
+        append(&path, &line[..3]);
+        assert!(tail.read_lines().is_empty());
+        append(&path, &line[3..]);
+        assert_eq!(tail.read_lines(), vec!["Größe"]);
+    }
+
+    #[test]
+    fn tail_reads_truncated_logs_from_the_start() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("job.log");
+        let mut tail = LogTail::new(path.clone());
+        append(&path, b"one\ntwo\n");
+        assert_eq!(tail.read_lines().len(), 2);
+
+        fs::write(&path, b"new\n").unwrap();
+        assert_eq!(tail.read_lines(), vec!["new"]);
+    }
+
+    #[test]
+    fn tail_reads_replaced_logs_from_the_start() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("job.log");
+        let mut tail = LogTail::new(path.clone());
+        append(&path, b"old line\n");
+        assert_eq!(tail.read_lines(), vec!["old line"]);
+
+        // A longer file under the same name, as left by log rotation
+        let replacement = dir.path().join("job.log.new");
+        fs::write(&replacement, b"rotated one\nrotated two\n").unwrap();
+        fs::rename(&replacement, &path).unwrap();
+        assert_eq!(tail.read_lines(), vec!["rotated one", "rotated two"]);
+    }
+}
@@ -1,10 +1,16 @@
 use axum::routing::{ get, post };
+use axum::extract::DefaultBodyLimit;
 use socketioxide::SocketIo;
 use tracing::info;
 use tracing_subscriber::FmtSubscriber;
 use tower_http::cors::{ CorsLayer, Any };
 use plode_web_agent::socketio::on_connect;
 use plode_web_agent::compiler::{ health_check, upload_library };
+use plode_web_agent::builds::{ download_artifact, upload_binary };
+use plode_web_agent::sketch_archive::{ download_export, upload_sketch_archive };
+use plode_web_agent::config::apply_agent_config;
+use plode_web_agent::environment::ensure_cli_environment;
+use plode_web_agent::snapshots::prune_all_snapshots;
 use include_dir::{ include_dir, Dir };
 use plode_web_agent::models::{ LibraryUploadResponse, DownloadError };
 use std::path::Path;
@@ -12,6 +18,10 @@ use std::fs;
 // Embed entire directory at compile time
 static ASSETS_DIR: Dir<'_> = include_dir!("$CARGO_MANIFEST_DIR/assets");
 const ASSETS_DIR_PATH: &str = "assets_test";
+// Firmware images for ESP32-class boards are several megabytes
+const MAX_BINARY_UPLOAD_SIZE: usize = 32 * 1024 * 1024;
+// Sketch archives can carry data files next to the sources
+const MAX_SKETCH_ARCHIVE_SIZE: usize = 16 * 1024 * 1024;
 fn extract_assets_to_temp() -> Result<String, Box<dyn std::error::Error>> {
     let temp_dir = std::env::temp_dir().join("my_app_assets");
 
@@ -83,6 +93,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     //     false
     // ).await.expect("Failed to download asset");
     use_embedded_assets()?;
+    ensure_cli_environment()?;
     // Health check for arduino-cli
     match health_check() {
         true => info!("arduino-cli initialized successfully"),
@@ -91,6 +102,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             std::process::exit(1);
         }
     }
+    // Apply the arduino-cli settings once instead of on every connection
+    apply_agent_config().await;
+    // Drop expired snapshots, including those of sketches that were not edited since
+    tokio::task::spawn_blocking(prune_all_snapshots);
 
     let (socketio_layer, io) = SocketIo::new_layer();
     io.ns("/", on_connect);
@@ -103,6 +118,16 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             get(|| async { "alive" })
         )
         .route("/upload-library", post(upload_library))
+        .route("/artifacts/{sketch_name}/{file_name}", get(download_artifact))
+        .route(
+            "/upload-binary",
+            post(upload_binary).layer(DefaultBodyLimit::max(MAX_BINARY_UPLOAD_SIZE))
+        )
+        .route("/exports/{file_name}", get(download_export))
+        .route(
+            "/import-sketch",
+            post(upload_sketch_archive).layer(DefaultBodyLimit::max(MAX_SKETCH_ARCHIVE_SIZE))
+        )
         .layer(socketio_layer)
         .layer(cors);
     #[cfg(debug_assertions)]
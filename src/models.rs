@@ -1,4 +1,5 @@
 use serde::{ Deserialize, Serialize };
+use std::collections::BTreeMap;
 #[cfg(target_os = "windows")]
 const WCH_ASSETS_LINK: &str = "https://example.com/assets/";
 #[cfg(target_os = "linux")]
@@ -17,6 +18,70 @@ pub struct CommandResponse {
     pub error: Option<String>,
     pub command: String,
     pub args: Vec<String>,
+    pub job_id: Option<String>,
+    // Machine-readable reason of a rejected request, e.g. "path_escape"
+    pub error_code: Option<String>,
+}
+
+// A single line of output produced by a running job
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct JobOutput {
+    pub job_id: String,
+    pub stream: String,
+    pub line: String,
+}
+
+// An arduino-cli log entry of a job, emitted as `logs`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct JobLog {
+    pub job_id: String,
+    pub entry: LogEntry,
+}
+
+// A typed arduino-cli log line
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LogEntry {
+    // trace, debug, info, warning, error, fatal or panic
+    pub level: String,
+    pub message: String,
+    pub time: String,
+    // Any other fields of the line, e.g. the sketch or port
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields: BTreeMap<String, serde_json::Value>,
+}
+
+// Download/extraction progress of a core or library install
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct InstallProgress {
+    pub job_id: String,
+    pub step: String,
+    pub item: Option<String>,
+    pub bytes_done: Option<u64>,
+    pub bytes_total: Option<u64>,
+    pub percent: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+// State of a queued, running or finished job as reported by list-jobs and job events
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct JobInfo {
+    pub job_id: String,
+    pub kind: String,
+    pub status: JobStatus,
+    pub sketch: Option<String>,
+    pub port: Option<String>,
+    pub queued_at: String,
+    pub started_at: Option<String>,
+    pub finished_at: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -31,6 +96,209 @@ pub struct FileResponse {
     pub is_file: bool,
 }
 
+// Optional settings accepted by compile-sketch under `options`
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct CompileOptions {
+    // Board menu options, e.g. {"cpu": "atmega328old"}
+    pub board_options: BTreeMap<String, String>,
+    // Extra `--build-property` values, e.g. "build.extra_flags=-DDEBUG"
+    pub build_properties: Vec<String>,
+    // One of none, default, more, all
+    pub warnings: Option<String>,
+    pub optimize_for_debug: bool,
+    pub verbose: bool,
+    pub jobs: Option<u32>,
+    pub export_binaries: bool,
+}
+
+// Result of the last compile of a sketch, stored next to its build artifacts
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BuildInfo {
+    pub sketch_name: String,
+    pub fqbn: Option<String>,
+    #[serde(default)]
+    pub profile: Option<String>,
+    #[serde(default)]
+    pub build_properties: Vec<String>,
+    pub success: bool,
+    pub finished_at: String,
+    pub artifacts: Vec<String>,
+}
+
+// The sketch.yaml project file of a sketch. Keys the agent does not know about
+// are kept as they are when the file is written back.
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct SketchYaml {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_fqbn: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_port: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_profile: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, SketchProfile>,
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
+}
+
+// A sketch.yaml profile pinning the board, core and library versions a sketch builds with
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct SketchProfile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+    pub fqbn: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub programmer: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<String>,
+    #[serde(default)]
+    pub platforms: Vec<ProfilePlatform>,
+    // Pinned libraries, e.g. "Servo (1.2.1)"
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub libraries: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct ProfilePlatform {
+    // Pinned core, e.g. "arduino:avr (1.8.6)"
+    pub platform: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub platform_index_url: Option<String>,
+}
+
+// Typed `arduino-cli board details` output
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct BoardDetails {
+    pub fqbn: String,
+    pub name: String,
+    pub version: String,
+    // Board menu options such as CPU speed, upload speed or partition scheme
+    pub config_options: Vec<BoardConfigOption>,
+    pub programmers: Vec<BoardProgrammer>,
+    pub default_programmer_id: Option<String>,
+    // Tools the board's core needs, e.g. the compiler and uploader
+    pub tools_dependencies: Vec<BoardToolDependency>,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct BoardConfigOption {
+    pub option: String,
+    pub option_label: String,
+    pub values: Vec<BoardConfigValue>,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct BoardConfigValue {
+    pub value: String,
+    pub value_label: String,
+    pub selected: bool,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct BoardProgrammer {
+    pub platform: String,
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct BoardToolDependency {
+    pub packager: String,
+    pub name: String,
+    pub version: String,
+}
+
+// A library pulled in by installing or upgrading another library, from `lib deps`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LibraryDependency {
+    pub name: String,
+    pub version_required: String,
+    pub version_installed: Option<String>,
+    // What installing would do to it: install, upgrade, downgrade or none
+    pub action: String,
+    // The installed version has a different major version than the required one
+    pub breaking: bool,
+}
+
+// An installed library with a newer release
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct OutdatedLibrary {
+    pub name: String,
+    pub installed_version: String,
+    pub available_version: String,
+    // The new release has a different major version
+    pub major_update: bool,
+    // Dependencies that the upgrade would install or change
+    pub dependency_changes: Vec<LibraryDependency>,
+}
+
+// An example sketch shipped with a library
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SketchExample {
+    // Path below the library's examples folder, e.g. "Basics/Sweep"
+    pub name: String,
+    pub path: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LibraryExamples {
+    pub library: String,
+    pub version: Option<String>,
+    pub examples: Vec<SketchExample>,
+}
+
+// The libraries bundled with a core, e.g. Wire and SPI in arduino:avr
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CoreExamples {
+    pub core: String,
+    pub libraries: Vec<LibraryExamples>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ExampleGroups {
+    pub libraries: Vec<LibraryExamples>,
+    pub cores: Vec<CoreExamples>,
+}
+
+// A compiler error or warning, with the file relative to the sketch folder
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Diagnostic {
+    pub file: String,
+    pub line: u32,
+    pub column: Option<u32>,
+    // error, warning or note
+    pub severity: String,
+    pub message: String,
+}
+
+// An entry of the table mapping USB ids to Plode boards, built in or added by the user
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BoardId {
+    pub vid: String,
+    // Matches every product of the vendor when omitted
+    pub pid: Option<String>,
+    pub fqbn: String,
+    pub name: String,
+    // Whether the entry ships with the agent, set when listing
+    #[serde(default)]
+    pub builtin: bool,
+}
+
+// A board that may be connected to a port
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BoardCandidate {
+    pub fqbn: String,
+    pub name: String,
+    // Where the match came from: "board-list", "board-ids" or "sketch-default"
+    pub source: String,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct UsbAddFileRequest {
     pub name: String,
@@ -53,6 +321,145 @@ pub struct LibraryUploadResponse {
     pub library_name: String,
     pub file_path: Option<String>,
 }
+#[derive(Serialize, Deserialize)]
+pub struct BinaryUploadResponse {
+    pub success: bool,
+    pub message: String,
+    pub file_name: String,
+    pub file_path: Option<String>,
+}
+// A library a sketch needs, as listed in an exported sketch archive
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RequiredLibrary {
+    pub name: String,
+    #[serde(default)]
+    pub version: Option<String>,
+}
+
+// The manifest.json at the root of an exported sketch archive
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SketchManifest {
+    pub sketch_name: String,
+    pub exported_at: String,
+    #[serde(default)]
+    pub libraries: Vec<RequiredLibrary>,
+}
+
+// A file in a sketch snapshot and the stored object holding its content
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SnapshotFile {
+    pub path: String,
+    pub object: String,
+    pub size: u64,
+}
+
+// The saved state of a sketch's files
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Snapshot {
+    pub id: String,
+    // Why it was taken: before-edit, compiled, uploaded or before-restore
+    pub reason: String,
+    pub created_at: String,
+    pub files: Vec<SnapshotFile>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SnapshotSummary {
+    pub id: String,
+    pub reason: String,
+    pub created_at: String,
+    pub file_count: usize,
+    pub size: u64,
+}
+
+// How a file differs between a snapshot and another state of the sketch
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FileDiff {
+    pub path: String,
+    // added, removed or modified
+    pub status: String,
+    // Unified diff of the file, empty for binary files
+    pub diff: String,
+}
+
+// Sorting and filtering accepted by list-sketches
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct SketchListOptions {
+    // One of name, modified, size, built
+    pub sort: Option<String>,
+    pub descending: bool,
+    // Part of the sketch name, ignoring case
+    pub search: Option<String>,
+    // Only sketches whose default board is this fqbn
+    pub fqbn: Option<String>,
+    // Only valid or only invalid sketches
+    pub valid: Option<bool>,
+}
+
+// A sketch as listed by list-sketches
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SketchInfo {
+    pub name: String,
+    // <name>.ino, when it exists
+    pub main_file: Option<String>,
+    // Whether arduino-cli can build the sketch, i.e. its main file exists
+    pub valid: bool,
+    pub file_count: usize,
+    pub total_size: u64,
+    // Newest change to any file of the sketch
+    pub last_modified: Option<String>,
+    pub default_fqbn: Option<String>,
+    pub default_port: Option<String>,
+    pub last_build_success: Option<bool>,
+    pub last_build_at: Option<String>,
+}
+
+// A value a sketch template asks for, written wherever its files say {{name}}
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct TemplatePlaceholder {
+    #[serde(default)]
+    pub description: String,
+    // Used when create-sketch does not pass a value
+    #[serde(default)]
+    pub default: Option<String>,
+}
+
+// A sketch template, described by the template.json in its folder
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SketchTemplate {
+    // Name of the template folder
+    #[serde(default)]
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub default_fqbn: Option<String>,
+    // The file that becomes <sketch_name>.ino, sketch.ino when not given
+    #[serde(default)]
+    pub main: Option<String>,
+    #[serde(default)]
+    pub placeholders: BTreeMap<String, TemplatePlaceholder>,
+    // Whether the template ships with the agent, set when listing
+    #[serde(default)]
+    pub builtin: bool,
+    // Files of the template, set when listing
+    #[serde(default)]
+    pub files: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SketchImportResponse {
+    pub success: bool,
+    pub message: String,
+    pub error_code: Option<String>,
+    pub sketch_name: Option<String>,
+    pub files: Vec<String>,
+    // Required libraries that are not installed or not in the required version, as
+    // library-deps lists them. version_required is empty when any version will do.
+    pub missing_libraries: Vec<LibraryDependency>,
+}
 #[derive(Debug)]
 pub enum DownloadError {
     NetworkError(reqwest::Error),
@@ -0,0 +1,221 @@
+use crate::models::InstallProgress;
+
+// Turns the text output of arduino-cli downloads and installs into `install-progress`
+// events. Installs run without `--format json` because arduino-cli prints no progress
+// at all in JSON mode. Progress bars are redrawn with `\r`, which the output reader
+// splits into lines of their own, e.g.
+// `arduino:avr-gcc@7.3.0 12.34 MiB / 32.00 MiB [====>----]  38.56% 00m05s`.
+// Without a bar, e.g. when arduino-cli does not draw one into a pipe, each item still
+// reports its downloading, downloaded, installing and installed steps.
+pub struct InstallProgressParser {
+    job_id: String,
+    current_item: Option<String>,
+    bytes_total: Option<u64>,
+}
+
+impl InstallProgressParser {
+    pub fn new(job_id: &str) -> Self {
+        InstallProgressParser {
+            job_id: job_id.to_string(),
+            current_item: None,
+            bytes_total: None,
+        }
+    }
+
+    pub fn parse(&mut self, line: &str) -> Option<InstallProgress> {
+        let line = line.trim();
+        if line.is_empty() {
+            return None;
+        }
+        self.parse_text(line)
+    }
+
+    fn progress(
+        &self,
+        step: &str,
+        item: Option<String>,
+        bytes_done: Option<u64>,
+        bytes_total: Option<u64>
+    ) -> InstallProgress {
+        let percent = match (bytes_done, bytes_total) {
+            (Some(done), Some(total)) if total > 0 => Some(((done as f64) * 100.0) / (total as f64)),
+            _ => None,
+        };
+        InstallProgress {
+            job_id: self.job_id.clone(),
+            step: step.to_string(),
+            item,
+            bytes_done,
+            bytes_total,
+            percent,
+        }
+    }
+
+    fn parse_text(&mut self, line: &str) -> Option<InstallProgress> {
+        if let Some((item, done, total)) = parse_progress_bar(line) {
+            self.current_item = Some(item.clone());
+            self.bytes_total = Some(total);
+            return Some(self.progress("downloading", Some(item), Some(done), Some(total)));
+        }
+
+        let item = |text: &str| {
+            let text = text
+                .trim()
+                .trim_end_matches("...")
+                .trim_end_matches(':')
+                .trim_start_matches("platform ")
+                .trim_start_matches("Platform ")
+                .trim();
+            if text.is_empty() { None } else { Some(text.to_string()) }
+        };
+
+        if line == "Downloading packages" || line == "Downloading missing tool" {
+            return Some(self.progress("downloading", None, None, None));
+        }
+        if let Some(rest) = line.strip_suffix(" already downloaded") {
+            return Some(self.progress("downloaded", item(rest), None, None));
+        }
+        if let Some(rest) = line.strip_suffix(" downloaded") {
+            let rest = rest.strip_prefix("Downloading ").unwrap_or(rest);
+            return Some(self.progress("downloaded", item(rest), None, None));
+        }
+        if let Some(rest) = line.strip_prefix("Downloading ") {
+            self.current_item = item(rest);
+            return Some(self.progress("downloading", self.current_item.clone(), None, None));
+        }
+        if let Some(rest) = line.strip_prefix("Installing ") {
+            self.current_item = item(rest);
+            return Some(self.progress("installing", self.current_item.clone(), None, None));
+        }
+        if line.starts_with("Configuring") {
+            return Some(self.progress("configuring", self.current_item.clone(), None, None));
+        }
+        if let Some(rest) = line.strip_prefix("Installed ") {
+            return Some(self.progress("installed", item(rest), None, None));
+        }
+        if let Some(rest) = line.strip_suffix(" installed") {
+            return Some(self.progress("installed", item(rest), None, None));
+        }
+        if let Some(rest) = line.strip_prefix("Skipping ") {
+            return Some(self.progress("skipped", item(rest), None, None));
+        }
+        None
+    }
+}
+
+// Parse a size such as `12.34` + `MiB` into bytes
+fn parse_size(number: &str, unit: &str) -> Option<u64> {
+    let number = number.parse::<f64>().ok()?;
+    let multiplier = match unit {
+        "B" => 1.0,
+        "kB" | "KB" => 1000.0,
+        "KiB" => 1024.0,
+        "MB" => 1000.0 * 1000.0,
+        "MiB" => 1024.0 * 1024.0,
+        "GB" => 1000.0 * 1000.0 * 1000.0,
+        "GiB" => 1024.0 * 1024.0 * 1024.0,
+        _ => {
+            return None;
+        }
+    };
+    Some((number * multiplier) as u64)
+}
+
+// Parse a progress bar line into (item, bytes done, bytes total)
+fn parse_progress_bar(line: &str) -> Option<(String, u64, u64)> {
+    let tokens = line.split_whitespace().collect::<Vec<_>>();
+    let slash = tokens.iter().position(|token| *token == "/")?;
+    if slash < 2 || slash + 2 >= tokens.len() {
+        return None;
+    }
+    let done = parse_size(tokens[slash - 2], tokens[slash - 1])?;
+    let total = parse_size(tokens[slash + 1], tokens[slash + 2])?;
+    let item = tokens[..slash - 2].join(" ");
+    Some((item, done, total))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // step, item, bytes done, bytes total
+    type Step = (String, Option<String>, Option<u64>, Option<u64>);
+
+    fn steps(lines: &[&str]) -> Vec<Step> {
+        let mut parser = InstallProgressParser::new("job-1");
+        lines
+            .iter()
+            .filter_map(|line| parser.parse(line))
+            .map(|p| (p.step, p.item, p.bytes_done, p.bytes_total))
+            .collect()
+    }
+
+    fn step(step: &str, item: Option<&str>) -> Step {
+        (step.to_string(), item.map(|item| item.to_string()), None, None)
+    }
+
+    #[test]
+    fn parses_core_install() {
+        let lines = [
+            "Downloading packages",
+            "arduino:avr-gcc@7.3.0-atmel3.6.1-arduino7 0 B / 31.38 MiB [------]   0.00%",
+            "arduino:avr-gcc@7.3.0-atmel3.6.1-arduino7 15.69 MiB / 31.38 MiB [=>-]  50.00% 00m02s",
+            "arduino:avr-gcc@7.3.0-atmel3.6.1-arduino7 downloaded                        ",
+            "arduino:avrdude@6.3.0-arduino17 already downloaded",
+            "Installing arduino:avr-gcc@7.3.0-atmel3.6.1-arduino7...",
+            "arduino:avr-gcc@7.3.0-atmel3.6.1-arduino7 installed",
+            "Installing platform arduino:avr@1.8.6...",
+            "Configuring platform.",
+            "Platform arduino:avr@1.8.6 installed",
+        ];
+        let gcc = Some("arduino:avr-gcc@7.3.0-atmel3.6.1-arduino7");
+        let total = 31.38 * 1024.0 * 1024.0;
+        assert_eq!(steps(&lines), vec![
+            step("downloading", None),
+            ("downloading".to_string(), gcc.map(String::from), Some(0), Some(total as u64)),
+            (
+                "downloading".to_string(),
+                gcc.map(String::from),
+                Some((15.69 * 1024.0 * 1024.0) as u64),
+                Some(total as u64),
+            ),
+            step("downloaded", gcc),
+            step("downloaded", Some("arduino:avrdude@6.3.0-arduino17")),
+            step("installing", gcc),
+            step("installed", gcc),
+            step("installing", Some("arduino:avr@1.8.6")),
+            step("configuring", Some("arduino:avr@1.8.6")),
+            step("installed", Some("arduino:avr@1.8.6")),
+        ]);
+    }
+
+    #[test]
+    fn parses_library_install() {
+        let lines = [
+            "Downloading Servo@1.2.1...",
+            "Servo@1.2.1 downloaded",
+            "Installing Servo@1.2.1...",
+            "Installed Servo@1.2.1",
+        ];
+        assert_eq!(steps(&lines), vec![
+            step("downloading", Some("Servo@1.2.1")),
+            step("downloaded", Some("Servo@1.2.1")),
+            step("installing", Some("Servo@1.2.1")),
+            step("installed", Some("Servo@1.2.1")),
+        ]);
+    }
+
+    #[test]
+    fn computes_percent_from_sizes() {
+        let mut parser = InstallProgressParser::new("job-1");
+        let progress = parser.parse("tool@1.0 512 KiB / 1.00 MiB [==>--]  50.00%").unwrap();
+        assert_eq!(progress.bytes_done, Some(512 * 1024));
+        assert_eq!(progress.percent, Some(50.0));
+    }
+
+    #[test]
+    fn ignores_other_output() {
+        let lines = ["", "   ", "Sketch uses 924 bytes", "Used platform Version Path"];
+        assert!(steps(&lines).is_empty());
+    }
+}
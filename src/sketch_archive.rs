@@ -0,0 +1,646 @@
+use std::collections::{ BTreeSet, HashMap };
+use std::fs;
+use std::io::{ Cursor, Read, Write };
+use std::path::{ Path, PathBuf };
+use axum::{
+    body::Bytes,
+    extract::{ Multipart, Path as UrlPath, Query },
+    http::{ header, StatusCode },
+    response::{ IntoResponse, Json as ResponseJson },
+};
+use serde_json::Value;
+use tracing::{ info, error };
+use zip::write::SimpleFileOptions;
+use crate::models::*;
+use crate::builds::is_plain_name;
+use crate::compiler::{ json_list, run_arduino_command };
+use crate::libraries::{ dependency_change, same_library };
+use crate::sketch_yaml::read_sketch_yaml;
+use crate::sketches::rename_main_file;
+use crate::sketch_paths::{
+    existing_sketch_dir,
+    list_sketch_files,
+    resolve_sketch_dir,
+    PathError,
+    ALREADY_EXISTS,
+    COMMAND_FAILED,
+    INVALID_ARCHIVE,
+    IO_ERROR,
+};
+
+const MANIFEST_FILE: &str = "manifest.json";
+const SKETCH_YAML_FILE: &str = "sketch.yaml";
+const SOURCE_EXTENSIONS: [&str; 6] = ["ino", "pde", "h", "hpp", "c", "cpp"];
+// Limits for uploaded archives, so a zip bomb cannot fill the disk
+const MAX_ARCHIVE_ENTRIES: usize = 1000;
+const MAX_ARCHIVE_UNPACKED_SIZE: u64 = 64 * 1024 * 1024;
+
+// Directory exported sketch archives are kept in until they are downloaded
+pub fn get_exports_directory() -> PathBuf {
+    let current_dir = std::env::current_dir().unwrap_or_else(|_| Path::new(".").to_path_buf());
+    current_dir.join("exports")
+}
+
+// Header names of the #include lines of a source, e.g. "Servo.h" for `#include <Servo.h>`
+fn included_headers(source: &str) -> Vec<String> {
+    source
+        .lines()
+        .filter_map(|line| {
+            let include = line.trim_start().strip_prefix('#')?.trim_start();
+            let include = include.strip_prefix("include")?.trim();
+            let (header, _) = include
+                .strip_prefix('<')
+                .and_then(|rest| rest.split_once('>'))
+                .or_else(|| include.strip_prefix('"').and_then(|rest| rest.split_once('"')))?;
+            Some(header.rsplit('/').next().unwrap_or(header).to_string())
+        })
+        .collect()
+}
+
+// A sketch.yaml profile library such as "Servo (1.2.1)"
+fn parse_profile_library(entry: &str) -> RequiredLibrary {
+    match entry.trim().strip_suffix(')').and_then(|entry| entry.rsplit_once(" (")) {
+        Some((name, version)) =>
+            RequiredLibrary {
+                name: name.trim().to_string(),
+                version: Some(version.trim().to_string()),
+            },
+        None =>
+            RequiredLibrary {
+                name: entry.trim().to_string(),
+                version: None,
+            },
+    }
+}
+
+// The libraries pinned in the profiles of a sketch's sketch.yaml
+fn profile_libraries(sketch_name: &str) -> Vec<RequiredLibrary> {
+    read_sketch_yaml(sketch_name)
+        .map(|sketch_yaml| {
+            sketch_yaml.profiles
+                .values()
+                .flat_map(|profile| profile.libraries.iter())
+                .map(|entry| parse_profile_library(entry))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// The installed libraries with the headers each of them provides
+async fn installed_libraries() -> Result<Vec<(RequiredLibrary, Vec<String>)>, String> {
+    let command = ArduinoCommand {
+        command: "lib".to_string(),
+        args: vec!["list".to_string(), "--format".to_string(), "json".to_string()],
+    };
+    let response = run_arduino_command(&command).await;
+    if !response.success {
+        return Err(response.error.unwrap_or_else(|| "Failed to list libraries".to_string()));
+    }
+    let json = response.output_json.unwrap_or(Value::Null);
+    Ok(
+        json_list(&json, "installed_libraries")
+            .into_iter()
+            .filter_map(|entry| {
+                let library = entry.get("library")?;
+                let name = library.get("name")?.as_str()?.to_string();
+                let version = library
+                    .get("version")
+                    .and_then(|version| version.as_str())
+                    .map(|version| version.to_string());
+                let includes = library
+                    .get("provides_includes")
+                    .and_then(|includes| includes.as_array())
+                    .map(|includes| {
+                        includes
+                            .iter()
+                            .filter_map(|include| include.as_str().map(|i| i.to_string()))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                Some((RequiredLibrary { name, version }, includes))
+            })
+            .collect()
+    )
+}
+
+// The libraries a sketch needs: the installed libraries providing the headers it
+// includes, and the libraries pinned in its sketch.yaml profiles
+pub async fn required_libraries(sketch_name: &str) -> Result<Vec<RequiredLibrary>, PathError> {
+    let files = list_sketch_files(sketch_name)?;
+    let local_files = files
+        .iter()
+        .map(|file| file.filename.clone())
+        .collect::<BTreeSet<_>>();
+    let headers = files
+        .iter()
+        .filter(|file| file.is_file && SOURCE_EXTENSIONS.contains(&file.filetype.as_str()))
+        .flat_map(|file| included_headers(&fs::read_to_string(&file.path).unwrap_or_default()))
+        .filter(|header| !local_files.contains(header))
+        .collect::<BTreeSet<_>>();
+
+    let installed = installed_libraries().await.map_err(|e| PathError::new(COMMAND_FAILED, e))?;
+    let mut libraries = installed
+        .into_iter()
+        .filter(|(_, includes)| includes.iter().any(|include| headers.contains(include)))
+        .map(|(library, _)| library)
+        .collect::<Vec<_>>();
+    for library in profile_libraries(sketch_name) {
+        if !libraries.iter().any(|known| same_library(&known.name, &library.name)) {
+            libraries.push(library);
+        }
+    }
+    libraries.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(libraries)
+}
+
+// Path of a file inside an archive, always with forward slashes
+fn archive_name(sketch_name: &str, relative: &Path) -> String {
+    let mut name = sketch_name.to_string();
+    for component in relative.components() {
+        name.push('/');
+        name.push_str(&component.as_os_str().to_string_lossy());
+    }
+    name
+}
+
+fn write_archive(
+    archive_path: &Path,
+    manifest: &SketchManifest,
+    files: &[(String, PathBuf)]
+) -> zip::result::ZipResult<()> {
+    let mut writer = zip::ZipWriter::new(fs::File::create(archive_path)?);
+    let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    writer.start_file(MANIFEST_FILE, options)?;
+    writer.write_all(serde_json::to_string_pretty(manifest).unwrap_or_default().as_bytes())?;
+    for (name, path) in files {
+        writer.start_file(name.as_str(), options)?;
+        writer.write_all(&fs::read(path)?)?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+// Pack a sketch into exports/<sketch>.zip: the sketch folder, and a manifest.json
+// with the libraries it needs
+pub async fn export_sketch(
+    sketch_name: &str,
+    include_sketch_yaml: bool,
+    include_libraries: bool
+) -> Result<(PathBuf, SketchManifest), PathError> {
+    let sketch_dir = existing_sketch_dir(sketch_name)?;
+    let base = sketch_dir.canonicalize().unwrap_or(sketch_dir);
+    let files = list_sketch_files(sketch_name)?
+        .into_iter()
+        .filter(|file| file.is_file)
+        .filter_map(|file| {
+            let path = PathBuf::from(&file.path);
+            let relative = path.strip_prefix(&base).ok()?.to_path_buf();
+            if !include_sketch_yaml && relative == Path::new(SKETCH_YAML_FILE) {
+                return None;
+            }
+            Some((archive_name(sketch_name, &relative), path))
+        })
+        .collect::<Vec<_>>();
+    let libraries = if include_libraries {
+        required_libraries(sketch_name).await?
+    } else {
+        vec![]
+    };
+    let manifest = SketchManifest {
+        sketch_name: sketch_name.to_string(),
+        exported_at: chrono::Utc::now().to_rfc3339(),
+        libraries,
+    };
+
+    let exports_dir = get_exports_directory();
+    let archive_path = exports_dir.join(format!("{}.zip", sketch_name));
+    fs
+        ::create_dir_all(&exports_dir)
+        .map_err(zip::result::ZipError::from)
+        .and_then(|_| write_archive(&archive_path, &manifest, &files))
+        .map_err(|e| {
+            PathError::new(IO_ERROR, format!("Failed to write {}: {}", archive_path.display(), e))
+        })?;
+    info!("Exported sketch {} to {}", sketch_name, archive_path.display());
+    Ok((archive_path, manifest))
+}
+
+// Handler for downloading an exported sketch, e.g. GET /exports/MySketch.zip
+pub async fn download_export(
+    UrlPath(file_name): UrlPath<String>
+) -> Result<impl IntoResponse, StatusCode> {
+    if !is_plain_name(&file_name) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let archive_path = get_exports_directory().join(&file_name);
+    let data = tokio::fs::read(&archive_path).await.map_err(|_| StatusCode::NOT_FOUND)?;
+    info!("Serving sketch archive {}", archive_path.display());
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/zip".to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", file_name)),
+        ],
+        data,
+    ))
+}
+
+// A file of an uploaded archive, relative to the sketch folder
+struct ArchiveEntry {
+    path: PathBuf,
+    data: Vec<u8>,
+}
+
+// An uploaded archive that passed validation
+struct SketchArchive {
+    sketch_name: String,
+    manifest: Option<SketchManifest>,
+    entries: Vec<ArchiveEntry>,
+}
+
+fn invalid_archive(message: String) -> PathError {
+    PathError::new(INVALID_ARCHIVE, message)
+}
+
+// Read and check an uploaded archive: no paths leaving it, no links, limited size,
+// and one sketch whose main .ino is named after it. The sketch may sit in a folder
+// (as exported) or at the root of the archive.
+fn read_archive(data: &[u8]) -> Result<SketchArchive, PathError> {
+    let mut archive = zip::ZipArchive
+        ::new(Cursor::new(data))
+        .map_err(|e| invalid_archive(format!("Not a zip archive: {}", e)))?;
+    if archive.len() > MAX_ARCHIVE_ENTRIES {
+        return Err(
+            invalid_archive(format!("The archive has more than {} files", MAX_ARCHIVE_ENTRIES))
+        );
+    }
+
+    let mut manifest = None;
+    let mut entries = vec![];
+    let mut unpacked_size = 0;
+    for index in 0..archive.len() {
+        let mut file = archive
+            .by_index(index)
+            .map_err(|e| invalid_archive(format!("Invalid archive entry: {}", e)))?;
+        let Some(path) = file.enclosed_name() else {
+            return Err(invalid_archive(format!("{} points outside the archive", file.name())));
+        };
+        if file.unix_mode().is_some_and(|mode| mode & 0o170000 == 0o120000) {
+            return Err(invalid_archive(format!("{} is a symbolic link", file.name())));
+        }
+        if file.is_dir() {
+            continue;
+        }
+        // Count what is actually unpacked, the sizes in the archive may lie
+        let mut content = vec![];
+        (&mut file)
+            .take(MAX_ARCHIVE_UNPACKED_SIZE - unpacked_size + 1)
+            .read_to_end(&mut content)
+            .map_err(|e| invalid_archive(format!("Failed to unpack {}: {}", path.display(), e)))?;
+        unpacked_size += content.len() as u64;
+        if unpacked_size > MAX_ARCHIVE_UNPACKED_SIZE {
+            return Err(
+                invalid_archive(
+                    format!("The archive unpacks to more than {} bytes", MAX_ARCHIVE_UNPACKED_SIZE)
+                )
+            );
+        }
+        if path == Path::new(MANIFEST_FILE) {
+            manifest = Some(
+                serde_json
+                    ::from_slice::<SketchManifest>(&content)
+                    .map_err(|e| invalid_archive(format!("Invalid {}: {}", MANIFEST_FILE, e)))?
+            );
+            continue;
+        }
+        entries.push(ArchiveEntry { path, data: content });
+    }
+
+    // All files in one top-level folder: that folder is the sketch
+    let first_component = |path: &Path| {
+        path.components()
+            .next()
+            .map(|component| component.as_os_str().to_string_lossy().to_string())
+    };
+    let folder = entries
+        .first()
+        .and_then(|entry| first_component(&entry.path))
+        .filter(|folder| {
+            entries.iter().all(|entry| {
+                entry.path.components().count() > 1 &&
+                    first_component(&entry.path).as_ref() == Some(folder)
+            })
+        });
+    if let Some(folder) = &folder {
+        for entry in &mut entries {
+            entry.path = entry.path.strip_prefix(folder).unwrap_or(&entry.path).to_path_buf();
+        }
+    }
+    let main_files = entries
+        .iter()
+        .filter(|entry| {
+            entry.path.components().count() == 1 &&
+                entry.path.extension().is_some_and(|ext| ext == "ino")
+        })
+        .filter_map(|entry| entry.path.file_stem().map(|stem| stem.to_string_lossy().to_string()))
+        .collect::<Vec<_>>();
+    let sketch_name = folder
+        .or_else(|| manifest.as_ref().map(|manifest| manifest.sketch_name.clone()))
+        .or_else(|| (main_files.len() == 1).then(|| main_files[0].clone()))
+        .ok_or_else(|| invalid_archive("The archive does not contain a sketch".to_string()))?;
+    if !main_files.contains(&sketch_name) {
+        return Err(invalid_archive(format!("The archive has no {}.ino", sketch_name)));
+    }
+    Ok(SketchArchive { sketch_name, manifest, entries })
+}
+
+// The required libraries that are not installed, or not in the pinned version, as
+// library-deps lists them. Libraries without a version are satisfied by any version.
+fn libraries_to_install(
+    required: Vec<RequiredLibrary>,
+    installed: &[(RequiredLibrary, Vec<String>)]
+) -> Vec<LibraryDependency> {
+    required
+        .into_iter()
+        .filter_map(|library| {
+            let known = installed
+                .iter()
+                .map(|(known, _)| known)
+                .find(|known| same_library(&known.name, &library.name));
+            if known.is_some() && library.version.is_none() {
+                return None;
+            }
+            let change = dependency_change(
+                library.name,
+                library.version.unwrap_or_default(),
+                known.and_then(|known| known.version.clone())
+            );
+            (change.action != "none").then_some(change)
+        })
+        .collect()
+}
+
+fn unpack(target: &Path, entries: &[ArchiveEntry]) -> std::io::Result<()> {
+    fs::create_dir_all(target)?;
+    for entry in entries {
+        let path = target.join(&entry.path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, &entry.data)?;
+    }
+    Ok(())
+}
+
+// Unpack an uploaded sketch archive as a new sketch, named `sketch_name` or as in
+// the archive, and report which of the libraries it needs are not installed
+pub async fn import_sketch(
+    data: &[u8],
+    sketch_name: Option<&str>
+) -> Result<SketchImportResponse, PathError> {
+    let archive = read_archive(data)?;
+    let target_name = sketch_name.unwrap_or(&archive.sketch_name).to_string();
+    let target = resolve_sketch_dir(&target_name)?;
+    if fs::symlink_metadata(&target).is_ok() {
+        return Err(
+            PathError::new(ALREADY_EXISTS, format!("Sketch {} already exists", target_name))
+        );
+    }
+
+    // Unpack next to the sketchbook's sketches so a failed import leaves nothing behind
+    let staging = target.with_file_name(format!(".{}.importing", target_name));
+    if let Err(e) = unpack(&staging, &archive.entries).and_then(|_| fs::rename(&staging, &target)) {
+        let _ = fs::remove_dir_all(&staging);
+        return Err(PathError::new(IO_ERROR, format!("Failed to unpack {}: {}", target_name, e)));
+    }
+    if target_name != archive.sketch_name {
+        rename_main_file(&target, &archive.sketch_name, &target_name)?;
+    }
+
+    // The archive's manifest lists what the sketch needs, plain zips only have their profiles
+    let required = match archive.manifest {
+        Some(manifest) => manifest.libraries,
+        None => profile_libraries(&target_name),
+    };
+    let installed = installed_libraries().await.unwrap_or_else(|e| {
+        error!("Failed to list installed libraries: {}", e);
+        vec![]
+    });
+    let missing_libraries = libraries_to_install(required, &installed);
+
+    let base = target.canonicalize().unwrap_or(target);
+    let files = list_sketch_files(&target_name)?
+        .into_iter()
+        .filter(|file| file.is_file)
+        .filter_map(|file| {
+            let path = PathBuf::from(&file.path);
+            Some(path.strip_prefix(&base).ok()?.to_string_lossy().to_string())
+        })
+        .collect::<Vec<_>>();
+    info!("Imported sketch {} with {} files", target_name, files.len());
+    Ok(SketchImportResponse {
+        success: true,
+        message: format!("Sketch '{}' imported successfully", target_name),
+        error_code: None,
+        sketch_name: Some(target_name),
+        files,
+        missing_libraries,
+    })
+}
+
+// The first file of a multipart upload
+async fn read_upload(multipart: &mut Multipart) -> Result<Option<Bytes>, String> {
+    let Some(field) = multipart.next_field().await.map_err(|e| e.to_string())? else {
+        return Ok(None);
+    };
+    field
+        .bytes().await
+        .map(Some)
+        .map_err(|e| e.to_string())
+}
+
+// Handler for importing a sketch archive, e.g. POST /import-sketch?sketch_name=Blink2
+pub async fn upload_sketch_archive(
+    Query(params): Query<HashMap<String, String>>,
+    mut multipart: Multipart
+) -> (StatusCode, ResponseJson<SketchImportResponse>) {
+    let rejected = |status: StatusCode, error_code: Option<&str>, message: String| {
+        (
+            status,
+            ResponseJson(SketchImportResponse {
+                success: false,
+                message,
+                error_code: error_code.map(|code| code.to_string()),
+                sketch_name: None,
+                files: vec![],
+                missing_libraries: vec![],
+            }),
+        )
+    };
+    let data = match read_upload(&mut multipart).await {
+        Ok(Some(data)) => data,
+        Ok(None) => {
+            return rejected(StatusCode::BAD_REQUEST, None, "No archive uploaded".to_string());
+        }
+        Err(e) => {
+            return rejected(StatusCode::BAD_REQUEST, None, format!("Failed to read upload: {}", e));
+        }
+    };
+    info!("Received sketch archive of {} bytes", data.len());
+
+    match import_sketch(&data, params.get("sketch_name").map(|name| name.as_str())).await {
+        Ok(response) => (StatusCode::OK, ResponseJson(response)),
+        Err(error) => {
+            let status = match error.code {
+                ALREADY_EXISTS => StatusCode::CONFLICT,
+                IO_ERROR | COMMAND_FAILED => StatusCode::INTERNAL_SERVER_ERROR,
+                _ => StatusCode::BAD_REQUEST,
+            };
+            rejected(status, Some(error.code), error.message)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn zip_of(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(vec![]));
+        for (name, content) in files {
+            writer.start_file(*name, SimpleFileOptions::default()).unwrap();
+            writer.write_all(content).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn code<T>(result: Result<T, PathError>) -> &'static str {
+        result.err().unwrap().code
+    }
+
+    fn library(name: &str, version: Option<&str>) -> RequiredLibrary {
+        RequiredLibrary {
+            name: name.to_string(),
+            version: version.map(|version| version.to_string()),
+        }
+    }
+
+    #[test]
+    fn reads_sketches_in_a_folder_or_at_the_root() {
+        let archive = read_archive(
+            &zip_of(&[("Blink/Blink.ino", b"void setup() {}"), ("Blink/src/led.h", b"")])
+        ).unwrap();
+        assert_eq!(archive.sketch_name, "Blink");
+        let paths = archive.entries
+            .iter()
+            .map(|entry| entry.path.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(paths, vec![PathBuf::from("Blink.ino"), Path::new("src").join("led.h")]);
+
+        let archive = read_archive(&zip_of(&[("Blink.ino", b""), ("notes.txt", b"")])).unwrap();
+        assert_eq!(archive.sketch_name, "Blink");
+    }
+
+    #[test]
+    fn names_the_sketch_after_the_manifest() {
+        let manifest = br#"{"sketch_name":"Blink","exported_at":"","libraries":[]}"#;
+        let archive = read_archive(
+            &zip_of(&[("manifest.json", manifest), ("Blink.ino", b""), ("Other.ino", b"")])
+        ).unwrap();
+        assert_eq!(archive.sketch_name, "Blink");
+        assert!(archive.manifest.is_some());
+        assert_eq!(archive.entries.len(), 2);
+    }
+
+    #[test]
+    fn refuses_archives_without_a_main_file() {
+        assert_eq!(code(read_archive(b"not a zip")), INVALID_ARCHIVE);
+        assert_eq!(code(read_archive(&zip_of(&[("Blink/Other.ino", b"")]))), INVALID_ARCHIVE);
+        assert_eq!(code(read_archive(&zip_of(&[("A.ino", b""), ("B.ino", b"")]))), INVALID_ARCHIVE);
+        assert_eq!(code(read_archive(&zip_of(&[("readme.txt", b"")]))), INVALID_ARCHIVE);
+    }
+
+    #[test]
+    fn refuses_entries_leaving_the_archive() {
+        for name in ["../Blink.ino", "Blink/../../Blink.ino", "/etc/Blink.ino"] {
+            let data = zip_of(&[("Blink/Blink.ino", b""), (name, b"")]);
+            assert_eq!(code(read_archive(&data)), INVALID_ARCHIVE, "{}", name);
+        }
+    }
+
+    #[test]
+    fn refuses_symbolic_links() {
+        let mut writer = zip::ZipWriter::new(Cursor::new(vec![]));
+        writer.start_file("Blink/Blink.ino", SimpleFileOptions::default()).unwrap();
+        writer.add_symlink("Blink/secret", "/etc/passwd", SimpleFileOptions::default()).unwrap();
+        let data = writer.finish().unwrap().into_inner();
+        assert_eq!(code(read_archive(&data)), INVALID_ARCHIVE);
+    }
+
+    #[test]
+    fn refuses_too_many_entries() {
+        let names = (0..=MAX_ARCHIVE_ENTRIES)
+            .map(|index| format!("Blink/file{}.h", index))
+            .collect::<Vec<_>>();
+        let mut files = names
+            .iter()
+            .map(|name| (name.as_str(), &b""[..]))
+            .collect::<Vec<_>>();
+        files.push(("Blink/Blink.ino", b""));
+        assert_eq!(code(read_archive(&zip_of(&files))), INVALID_ARCHIVE);
+    }
+
+    #[test]
+    fn refuses_archives_unpacking_too_much() {
+        let mut writer = zip::ZipWriter::new(Cursor::new(vec![]));
+        let options = SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Stored)
+            .large_file(true);
+        writer.start_file("Blink/Blink.ino", options).unwrap();
+        let chunk = vec![0u8; 1024 * 1024];
+        for _ in 0..=MAX_ARCHIVE_UNPACKED_SIZE / chunk.len() as u64 {
+            writer.write_all(&chunk).unwrap();
+        }
+        let data = writer.finish().unwrap().into_inner();
+        assert_eq!(code(read_archive(&data)), INVALID_ARCHIVE);
+    }
+
+    #[test]
+    fn finds_included_headers() {
+        let source = "#include <Servo.h>\n  # include \"src/motor.h\"\n// #include <Nope.h>\n";
+        assert_eq!(included_headers(source), vec!["Servo.h", "motor.h"]);
+    }
+
+    #[test]
+    fn parses_pinned_profile_libraries() {
+        let pinned = parse_profile_library("Adafruit GFX Library (1.11.9)");
+        assert_eq!(pinned.name, "Adafruit GFX Library");
+        assert_eq!(pinned.version.as_deref(), Some("1.11.9"));
+        assert_eq!(parse_profile_library("Servo").version, None);
+    }
+
+    #[test]
+    fn lists_missing_libraries_like_library_deps() {
+        let installed = vec![
+            (library("Servo", Some("1.2.1")), vec![]),
+            (library("ArduinoJson", Some("6.21.5")), vec![])
+        ];
+        let missing = libraries_to_install(
+            vec![
+                library("servo", Some("1.2.1")),
+                library("ARDUINOJSON", None),
+                library("ArduinoJson", Some("7.0.0")),
+                library("WiFi101", None)
+            ],
+            &installed
+        );
+        assert_eq!(missing.len(), 2);
+        assert_eq!(missing[0].name, "ArduinoJson");
+        assert_eq!(missing[0].version_installed.as_deref(), Some("6.21.5"));
+        assert_eq!(missing[0].action, "upgrade");
+        assert!(missing[0].breaking);
+        assert_eq!(missing[1].name, "WiFi101");
+        assert_eq!(missing[1].version_required, "");
+        assert_eq!(missing[1].action, "install");
+    }
+}
@@ -0,0 +1,270 @@
+use std::fmt;
+use std::fs;
+use std::path::{ Component, Path, PathBuf };
+use crate::models::FileResponse;
+use crate::builds::is_plain_name;
+use crate::socketio::get_sketch_directory;
+use crate::usb::list_directory_recursive;
+
+// Error codes sent to clients when a sketch command is refused
+pub const SKETCHBOOK_MISSING: &str = "sketchbook_missing";
+pub const INVALID_PATH: &str = "invalid_path";
+pub const PATH_ESCAPE: &str = "path_escape";
+pub const SYMLINK_ESCAPE: &str = "symlink_escape";
+pub const NOT_FOUND: &str = "not_found";
+pub const ALREADY_EXISTS: &str = "already_exists";
+pub const SKETCH_BUSY: &str = "sketch_busy";
+pub const IO_ERROR: &str = "io_error";
+pub const INVALID_ARCHIVE: &str = "invalid_archive";
+pub const COMMAND_FAILED: &str = "command_failed";
+pub const INVALID_TEMPLATE: &str = "invalid_template";
+pub const INVALID_SKETCH_YAML: &str = "invalid_sketch_yaml";
+pub const INVALID_PROFILE: &str = "invalid_profile";
+
+// Why a sketch path was refused
+#[derive(Debug)]
+pub struct PathError {
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl PathError {
+    pub fn new(code: &'static str, message: String) -> Self {
+        PathError { code, message }
+    }
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+// The sketchbook with all symlinks resolved, everything handed out must stay below it
+fn sketchbook_root() -> Result<PathBuf, PathError> {
+    let root = get_sketch_directory().map_err(|e| PathError::new(SKETCHBOOK_MISSING, e))?;
+    root.canonicalize().map_err(|e| {
+        PathError::new(SKETCHBOOK_MISSING, format!("Failed to resolve sketchbook: {}", e))
+    })
+}
+
+// `C:` at the start of a path names a drive on Windows
+fn has_drive_prefix(path: &str) -> bool {
+    let bytes = path.as_bytes();
+    bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':'
+}
+
+// A path relative to a sketch, made of plain names only, e.g. "src/motor.h".
+// Backslashes separate folders and drive prefixes are refused on every platform,
+// so a name that would leave the sketch on Windows is refused everywhere.
+fn relative_path(path: &str) -> Result<PathBuf, PathError> {
+    if path.trim().is_empty() || path.contains('\0') {
+        return Err(PathError::new(INVALID_PATH, format!("Invalid file name {:?}", path)));
+    }
+    let normalized = path.replace('\\', "/");
+    if has_drive_prefix(&normalized) {
+        return Err(PathError::new(PATH_ESCAPE, format!("{} points outside the sketch", path)));
+    }
+    let mut relative = PathBuf::new();
+    for component in Path::new(&normalized).components() {
+        match component {
+            Component::Normal(part) => relative.push(part),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                return Err(
+                    PathError::new(PATH_ESCAPE, format!("{} points outside the sketch", path))
+                );
+            }
+        }
+    }
+    if relative.as_os_str().is_empty() {
+        return Err(PathError::new(INVALID_PATH, format!("Invalid file name {:?}", path)));
+    }
+    Ok(relative)
+}
+
+// Check that the part of `path` that exists on disk resolves to a place below `base`,
+// so a symlink in the sketchbook cannot lead reads or writes somewhere else
+fn ensure_inside(base: &Path, path: &Path) -> Result<(), PathError> {
+    let mut existing = path;
+    while fs::symlink_metadata(existing).is_err() {
+        match existing.parent() {
+            Some(parent) => {
+                existing = parent;
+            }
+            None => {
+                return Ok(());
+            }
+        }
+    }
+    // A dangling symlink would make a write create its target, wherever that is
+    let resolved = existing.canonicalize().map_err(|_| {
+        PathError::new(SYMLINK_ESCAPE, format!("{} is a broken link", existing.display()))
+    })?;
+    if !resolved.starts_with(base) {
+        return Err(
+            PathError::new(
+                SYMLINK_ESCAPE,
+                format!("{} links outside the sketchbook", existing.display())
+            )
+        );
+    }
+    Ok(())
+}
+
+// Sketch names are single folder names. Names starting with a dot are hidden from
+// the sketch list and used for staging folders, and Windows drops trailing dots and
+// spaces, which would make two names the same folder.
+fn is_sketch_name(sketch_name: &str) -> bool {
+    is_plain_name(sketch_name) &&
+        !sketch_name.starts_with('.') &&
+        !sketch_name.ends_with(['.', ' '])
+}
+
+fn sketch_dir_in(root: &Path, sketch_name: &str) -> Result<PathBuf, PathError> {
+    if !is_sketch_name(sketch_name) {
+        return Err(PathError::new(INVALID_PATH, format!("Invalid sketch name {}", sketch_name)));
+    }
+    let sketch_dir = root.join(sketch_name);
+    ensure_inside(root, &sketch_dir)?;
+    Ok(sketch_dir)
+}
+
+fn sketch_file_in(root: &Path, sketch_name: &str, file_name: &str) -> Result<PathBuf, PathError> {
+    let sketch_dir = sketch_dir_in(root, sketch_name)?;
+    let relative = relative_path(file_name)?;
+    // A sketch folder may itself be a link to another folder in the sketchbook
+    let base = sketch_dir.canonicalize().unwrap_or(sketch_dir);
+    let path = base.join(relative);
+    ensure_inside(&base, &path)?;
+    Ok(path)
+}
+
+// The folder of a sketch, which does not have to exist yet
+pub fn resolve_sketch_dir(sketch_name: &str) -> Result<PathBuf, PathError> {
+    sketch_dir_in(&sketchbook_root()?, sketch_name)
+}
+
+// The folder of a sketch that must exist
+pub fn existing_sketch_dir(sketch_name: &str) -> Result<PathBuf, PathError> {
+    let sketch_dir = resolve_sketch_dir(sketch_name)?;
+    if !sketch_dir.is_dir() {
+        return Err(PathError::new(NOT_FOUND, format!("Sketch {} does not exist", sketch_name)));
+    }
+    Ok(sketch_dir)
+}
+
+// A file of a sketch, e.g. ("Blink", "src/motor.h"). The file does not have to exist.
+pub fn resolve_sketch_file(sketch_name: &str, file_name: &str) -> Result<PathBuf, PathError> {
+    sketch_file_in(&sketchbook_root()?, sketch_name, file_name)
+}
+
+// The files of a sketch, leaving out whatever symlinks inside it point to elsewhere
+pub fn list_sketch_files(sketch_name: &str) -> Result<Vec<FileResponse>, PathError> {
+    let sketch_dir = existing_sketch_dir(sketch_name)?;
+    let base = sketch_dir.canonicalize().unwrap_or(sketch_dir);
+    let files = list_directory_recursive(&base.to_string_lossy()).map_err(|e| {
+        PathError::new(NOT_FOUND, format!("Error listing sketch files: {}", e))
+    })?;
+    Ok(
+        files
+            .into_iter()
+            .filter(|file| ensure_inside(&base, Path::new(&file.path)).is_ok())
+            .collect()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sketchbook() -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        fs::create_dir(root.join("Blink")).unwrap();
+        (dir, root)
+    }
+
+    fn code(result: Result<PathBuf, PathError>) -> &'static str {
+        result.unwrap_err().code
+    }
+
+    #[test]
+    fn resolves_files_inside_the_sketch() {
+        let (_dir, root) = sketchbook();
+        assert_eq!(
+            sketch_file_in(&root, "Blink", "src/motor.h").unwrap(),
+            root.join("Blink").join("src").join("motor.h")
+        );
+        assert_eq!(
+            sketch_file_in(&root, "Blink", "./Blink.ino").unwrap(),
+            root.join("Blink").join("Blink.ino")
+        );
+    }
+
+    #[test]
+    fn refuses_parent_and_absolute_paths() {
+        let (_dir, root) = sketchbook();
+        assert_eq!(code(sketch_file_in(&root, "Blink", "../Other/Other.ino")), PATH_ESCAPE);
+        assert_eq!(code(sketch_file_in(&root, "Blink", "src/../../x")), PATH_ESCAPE);
+        assert_eq!(code(sketch_file_in(&root, "Blink", "/etc/passwd")), PATH_ESCAPE);
+        assert_eq!(code(sketch_dir_in(&root, "..")), INVALID_PATH);
+        assert_eq!(code(sketch_dir_in(&root, "../Blink")), INVALID_PATH);
+    }
+
+    #[test]
+    fn refuses_windows_prefixes_on_every_platform() {
+        let (_dir, root) = sketchbook();
+        assert_eq!(code(sketch_file_in(&root, "Blink", "C:\\Windows\\win.ini")), PATH_ESCAPE);
+        assert_eq!(code(sketch_file_in(&root, "Blink", "c:win.ini")), PATH_ESCAPE);
+        assert_eq!(code(sketch_file_in(&root, "Blink", "\\\\server\\share\\x")), PATH_ESCAPE);
+        assert_eq!(code(sketch_file_in(&root, "Blink", "\\\\?\\C:\\x")), PATH_ESCAPE);
+        assert_eq!(code(sketch_file_in(&root, "Blink", "src\\..\\..\\x")), PATH_ESCAPE);
+        assert_eq!(
+            sketch_file_in(&root, "Blink", "src\\motor.h").unwrap(),
+            root.join("Blink").join("src").join("motor.h")
+        );
+        assert_eq!(code(sketch_dir_in(&root, "C:")), INVALID_PATH);
+        assert_eq!(code(sketch_dir_in(&root, "a\\b")), INVALID_PATH);
+    }
+
+    #[test]
+    fn refuses_empty_and_dot_names() {
+        let (_dir, root) = sketchbook();
+        assert_eq!(code(sketch_file_in(&root, "Blink", "")), INVALID_PATH);
+        assert_eq!(code(sketch_file_in(&root, "Blink", ".")), INVALID_PATH);
+        assert_eq!(code(sketch_file_in(&root, "Blink", "a\0b")), INVALID_PATH);
+        for name in ["", ".", ".hidden", ".Blink.renaming", "Blink.", "Blink "] {
+            assert_eq!(code(sketch_dir_in(&root, name)), INVALID_PATH, "{:?}", name);
+        }
+        // Dot files inside a sketch are fine
+        assert!(sketch_file_in(&root, "Blink", ".gitignore").is_ok());
+        assert!(sketch_dir_in(&root, "Blink.v2").is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn follows_symlinked_sketches_inside_the_sketchbook() {
+        let (_dir, root) = sketchbook();
+        std::os::unix::fs::symlink(root.join("Blink"), root.join("Alias")).unwrap();
+        assert!(sketch_dir_in(&root, "Alias").is_ok());
+        assert_eq!(
+            sketch_file_in(&root, "Alias", "Blink.ino").unwrap(),
+            root.join("Blink").join("Blink.ino")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn refuses_symlinks_leading_outside() {
+        let (_dir, root) = sketchbook();
+        let outside = tempfile::tempdir().unwrap();
+        std::os::unix::fs::symlink(outside.path(), root.join("Escape")).unwrap();
+        std::os::unix::fs::symlink(outside.path(), root.join("Blink").join("src")).unwrap();
+        std::os::unix::fs::symlink(root.join("missing"), root.join("Dangling")).unwrap();
+
+        assert_eq!(code(sketch_dir_in(&root, "Escape")), SYMLINK_ESCAPE);
+        assert_eq!(code(sketch_file_in(&root, "Blink", "src/motor.h")), SYMLINK_ESCAPE);
+        assert_eq!(code(sketch_dir_in(&root, "Dangling")), SYMLINK_ESCAPE);
+    }
+}
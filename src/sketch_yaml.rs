@@ -0,0 +1,220 @@
+use std::fs;
+use std::path::PathBuf;
+use crate::models::{ SketchProfile, SketchYaml };
+use crate::sketch_paths::{
+    resolve_sketch_dir,
+    PathError,
+    INVALID_PROFILE,
+    INVALID_SKETCH_YAML,
+    IO_ERROR,
+    NOT_FOUND,
+};
+
+const SKETCH_YAML_FILE: &str = "sketch.yaml";
+
+fn io_error(message: String) -> PathError {
+    PathError::new(IO_ERROR, message)
+}
+
+fn invalid_profile(message: String) -> PathError {
+    PathError::new(INVALID_PROFILE, message)
+}
+
+fn no_profile(sketch_name: &str, profile_name: &str) -> PathError {
+    PathError::new(NOT_FOUND, format!("Sketch {} has no profile {}", sketch_name, profile_name))
+}
+
+fn sketch_yaml_path(sketch_name: &str) -> Result<PathBuf, PathError> {
+    Ok(resolve_sketch_dir(sketch_name)?.join(SKETCH_YAML_FILE))
+}
+
+// Read a sketch's sketch.yaml, returning an empty project when it has none
+pub fn read_sketch_yaml(sketch_name: &str) -> Result<SketchYaml, PathError> {
+    let path = sketch_yaml_path(sketch_name)?;
+    if !path.exists() {
+        return Ok(SketchYaml::default());
+    }
+    let content = fs
+        ::read_to_string(&path)
+        .map_err(|e| io_error(format!("Failed to read {}: {}", path.display(), e)))?;
+    if content.trim().is_empty() {
+        return Ok(SketchYaml::default());
+    }
+    serde_yaml::from_str(&content).map_err(|e| {
+        PathError::new(INVALID_SKETCH_YAML, format!("Invalid {}: {}", path.display(), e))
+    })
+}
+
+// Write a sketch's sketch.yaml
+pub fn write_sketch_yaml(sketch_name: &str, sketch_yaml: &SketchYaml) -> Result<(), PathError> {
+    let path = sketch_yaml_path(sketch_name)?;
+    let content = serde_yaml
+        ::to_string(sketch_yaml)
+        .map_err(|e| io_error(format!("Failed to serialize {}: {}", path.display(), e)))?;
+    fs::write(&path, content).map_err(|e| {
+        io_error(format!("Failed to write {}: {}", path.display(), e))
+    })
+}
+
+// Remember the board and port a sketch was uploaded to, like `arduino-cli board attach`.
+// Only empty fields are filled in unless `overwrite` is set, so a sketch.yaml kept
+// in version control is not rewritten by every upload.
+pub fn remember_board(
+    sketch_name: &str,
+    fqbn: &str,
+    port: &str,
+    overwrite: bool
+) -> Result<(), PathError> {
+    let mut sketch_yaml = read_sketch_yaml(sketch_name)?;
+    let mut changed = false;
+    for (field, value) in [
+        (&mut sketch_yaml.default_fqbn, fqbn),
+        (&mut sketch_yaml.default_port, port),
+    ] {
+        if (overwrite || field.is_none()) && field.as_deref() != Some(value) {
+            *field = Some(value.to_string());
+            changed = true;
+        }
+    }
+    if !changed {
+        return Ok(());
+    }
+    write_sketch_yaml(sketch_name, &sketch_yaml)
+}
+
+// Check a `name (version)` entry of a profile
+fn is_pinned_entry(entry: &str) -> bool {
+    match entry.trim().strip_suffix(')').and_then(|rest| rest.split_once(" (")) {
+        Some((name, version)) => !name.trim().is_empty() && !version.trim().is_empty(),
+        None => false,
+    }
+}
+
+fn validate_profile(profile_name: &str, profile: &SketchProfile) -> Result<(), PathError> {
+    if profile_name.is_empty() || profile_name.contains(char::is_whitespace) {
+        return Err(invalid_profile(format!("Invalid profile name {:?}", profile_name)));
+    }
+    if profile.fqbn.split(':').count() < 3 {
+        return Err(
+            invalid_profile(format!("Invalid fqbn {} in profile {}", profile.fqbn, profile_name))
+        );
+    }
+    if profile.platforms.is_empty() {
+        return Err(
+            invalid_profile(format!("Profile {} needs at least one platform", profile_name))
+        );
+    }
+    for platform in &profile.platforms {
+        if !is_pinned_entry(&platform.platform) {
+            return Err(
+                invalid_profile(format!(
+                    "Invalid platform {:?} in profile {}, expected e.g. \"arduino:avr (1.8.6)\"",
+                    platform.platform,
+                    profile_name
+                ))
+            );
+        }
+    }
+    for library in &profile.libraries {
+        if !is_pinned_entry(library) {
+            return Err(
+                invalid_profile(format!(
+                    "Invalid library {:?} in profile {}, expected e.g. \"Servo (1.2.1)\"",
+                    library,
+                    profile_name
+                ))
+            );
+        }
+    }
+    Ok(())
+}
+
+// Get a profile of a sketch, failing when the sketch has no such profile
+pub fn get_profile(sketch_name: &str, profile_name: &str) -> Result<SketchProfile, PathError> {
+    read_sketch_yaml(sketch_name)?
+        .profiles
+        .remove(profile_name)
+        .ok_or_else(|| no_profile(sketch_name, profile_name))
+}
+
+// Create or replace a profile of a sketch
+pub fn set_profile(
+    sketch_name: &str,
+    profile_name: &str,
+    profile: SketchProfile,
+    make_default: bool
+) -> Result<SketchYaml, PathError> {
+    validate_profile(profile_name, &profile)?;
+    let mut sketch_yaml = read_sketch_yaml(sketch_name)?;
+    sketch_yaml.profiles.insert(profile_name.to_string(), profile);
+    if make_default {
+        sketch_yaml.default_profile = Some(profile_name.to_string());
+    }
+    write_sketch_yaml(sketch_name, &sketch_yaml)?;
+    Ok(sketch_yaml)
+}
+
+// Delete a profile of a sketch, and stop using it as the default
+pub fn delete_profile(sketch_name: &str, profile_name: &str) -> Result<SketchYaml, PathError> {
+    let mut sketch_yaml = read_sketch_yaml(sketch_name)?;
+    if sketch_yaml.profiles.remove(profile_name).is_none() {
+        return Err(no_profile(sketch_name, profile_name));
+    }
+    if sketch_yaml.default_profile.as_deref() == Some(profile_name) {
+        sketch_yaml.default_profile = None;
+    }
+    write_sketch_yaml(sketch_name, &sketch_yaml)?;
+    Ok(sketch_yaml)
+}
+
+// Select the profile arduino-cli uses when none is given, or clear it with None
+pub fn set_default_profile(
+    sketch_name: &str,
+    profile_name: Option<&str>
+) -> Result<SketchYaml, PathError> {
+    let mut sketch_yaml = read_sketch_yaml(sketch_name)?;
+    if let Some(profile_name) = profile_name
+        && !sketch_yaml.profiles.contains_key(profile_name)
+    {
+        return Err(no_profile(sketch_name, profile_name));
+    }
+    sketch_yaml.default_profile = profile_name.map(|name| name.to_string());
+    write_sketch_yaml(sketch_name, &sketch_yaml)?;
+    Ok(sketch_yaml)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(platform: &str, libraries: &[&str]) -> SketchProfile {
+        serde_json
+            ::from_value(
+                serde_json::json!({
+                    "fqbn": "arduino:avr:uno",
+                    "platforms": [{ "platform": platform }],
+                    "libraries": libraries,
+                })
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn accepts_pinned_profiles() {
+        let profile = profile("arduino:avr (1.8.6)", &["Servo (1.2.1)"]);
+        assert!(validate_profile("uno", &profile).is_ok());
+    }
+
+    #[test]
+    fn refuses_profiles_without_pinned_versions() {
+        let code = |name: &str, profile: SketchProfile| {
+            validate_profile(name, &profile).unwrap_err().code
+        };
+        assert_eq!(code("my profile", profile("arduino:avr (1.8.6)", &[])), INVALID_PROFILE);
+        assert_eq!(code("uno", profile("arduino:avr", &[])), INVALID_PROFILE);
+        assert_eq!(code("uno", profile("arduino:avr (1.8.6)", &["Servo"])), INVALID_PROFILE);
+        let mut no_board = profile("arduino:avr (1.8.6)", &[]);
+        no_board.fqbn = "arduino:avr".to_string();
+        assert_eq!(code("uno", no_board), INVALID_PROFILE);
+    }
+}
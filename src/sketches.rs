@@ -0,0 +1,316 @@
+use std::fs;
+use std::path::{ Path, PathBuf };
+use tokio::sync::OwnedMutexGuard;
+use tracing::{ info, error };
+use crate::models::{ JobStatus, SketchInfo, SketchListOptions };
+use crate::builds::{ read_build_info, write_build_info };
+use crate::compilation_database::{ clear_fingerprint, get_compilation_database_path };
+use crate::examples::copy_dir_recursive;
+use crate::jobs::{ list_jobs, try_lock_sketch };
+use crate::snapshots::{ remove_snapshots, rename_snapshots };
+use crate::sketch_paths::{
+    existing_sketch_dir,
+    list_sketch_files,
+    resolve_sketch_dir,
+    PathError,
+    ALREADY_EXISTS,
+    INVALID_PATH,
+    IO_ERROR,
+    SKETCH_BUSY,
+};
+use crate::sketch_yaml::read_sketch_yaml;
+use crate::socketio::{ get_build_directory, get_sketch_directory };
+
+// Keys list-sketches can sort by
+const SORT_KEYS: [&str; 4] = ["name", "modified", "size", "built"];
+
+fn io_error(message: String) -> PathError {
+    PathError::new(IO_ERROR, message)
+}
+
+// Source and target folder of a rename or copy, refusing to overwrite a sketch
+fn resolve_source_and_target(from: &str, to: &str) -> Result<(PathBuf, PathBuf), PathError> {
+    let source = existing_sketch_dir(from)?;
+    let target = resolve_sketch_dir(to)?;
+    if from == to {
+        return Err(PathError::new(INVALID_PATH, format!("Sketch {} already has that name", to)));
+    }
+    // On case-insensitive file systems "blink" is the same folder as "Blink"
+    let same_folder = match (source.canonicalize(), target.canonicalize()) {
+        (Ok(source), Ok(target)) => source == target,
+        _ => false,
+    };
+    if fs::symlink_metadata(&target).is_ok() && !same_folder {
+        return Err(PathError::new(ALREADY_EXISTS, format!("Sketch {} already exists", to)));
+    }
+    Ok((source, target))
+}
+
+// arduino-cli only accepts a sketch whose main file is named after its folder
+pub fn rename_main_file(sketch_dir: &Path, from: &str, to: &str) -> Result<(), PathError> {
+    let main_file = sketch_dir.join(format!("{}.ino", from));
+    if !main_file.exists() {
+        return Ok(());
+    }
+    fs::rename(&main_file, sketch_dir.join(format!("{}.ino", to))).map_err(|e| {
+        io_error(format!("Failed to rename {}: {}", main_file.display(), e))
+    })
+}
+
+// Move a sketch folder through a temporary name, so changing only the case works on
+// every file system. If the second move fails the folder is moved back.
+fn move_sketch_folder(source: &Path, target: &Path, staging: &Path) -> Result<(), PathError> {
+    fs::rename(source, staging).map_err(|e| {
+        io_error(format!("Failed to rename sketch {}: {}", source.display(), e))
+    })?;
+    if let Err(e) = fs::rename(staging, target) {
+        if let Err(rollback_error) = fs::rename(staging, source) {
+            error!(?rollback_error, "Failed to move sketch back to {}", source.display());
+            return Err(
+                io_error(
+                    format!(
+                        "Failed to rename sketch {}, it was left in {}: {}",
+                        source.display(),
+                        staging.display(),
+                        e
+                    )
+                )
+            );
+        }
+        return Err(io_error(format!("Failed to rename sketch {}: {}", source.display(), e)));
+    }
+    Ok(())
+}
+
+// Hold a sketch's job lock, which keeps jobs that start meanwhile waiting. Queued jobs
+// do not hold it yet, so a sketch with a queued job is refused as well.
+fn lock_idle_sketch(sketch_name: &str) -> Result<OwnedMutexGuard<()>, PathError> {
+    let busy_error = || {
+        PathError::new(SKETCH_BUSY, format!("Sketch {} has a queued or running job", sketch_name))
+    };
+    let Some(sketch_lock) = try_lock_sketch(sketch_name) else {
+        return Err(busy_error());
+    };
+    let busy = list_jobs()
+        .into_iter()
+        .any(|job| {
+            job.sketch.as_deref() == Some(sketch_name) &&
+                matches!(job.status, JobStatus::Queued | JobStatus::Running)
+        });
+    if busy {
+        return Err(busy_error());
+    }
+    Ok(sketch_lock)
+}
+
+// The build cache belongs to the sketch folder, the compilation database points at
+// the old paths so it is rebuilt on next use
+fn move_build(from: &str, to: &str) -> Result<(), PathError> {
+    let old_build = get_build_directory(from);
+    let new_build = get_build_directory(to);
+    if !old_build.exists() {
+        return Ok(());
+    }
+    if new_build.exists() {
+        fs::remove_dir_all(&new_build).map_err(|e| {
+            io_error(format!("Failed to clear stale build of {}: {}", to, e))
+        })?;
+    }
+    fs::rename(&old_build, &new_build).map_err(|e| {
+        io_error(format!("Failed to move build of {}: {}", from, e))
+    })?;
+    clear_fingerprint(&get_compilation_database_path(to));
+    if let Some(mut build_info) = read_build_info(to) {
+        build_info.sketch_name = to.to_string();
+        write_build_info(to, &build_info);
+    }
+    Ok(())
+}
+
+// Rename a sketch with its main file, build cache, snapshots and sketch.yaml profiles.
+// Once the folder has moved the rename stands: snapshots or a build that cannot be
+// moved along are logged and left behind.
+pub fn rename_sketch(from: &str, to: &str) -> Result<PathBuf, PathError> {
+    let (source, target) = resolve_source_and_target(from, to)?;
+    let _sketch_lock = lock_idle_sketch(from)?;
+
+    let staging = target.with_file_name(format!(".{}.renaming", to));
+    move_sketch_folder(&source, &target, &staging)?;
+    if let Err(error) = rename_main_file(&target, from, to) {
+        let _ = fs::rename(&target, &source);
+        return Err(error);
+    }
+    if let Err(error) = rename_snapshots(from, to) {
+        error!("Failed to move snapshots of {} to {}: {}", from, to, error.message);
+    }
+    if let Err(error) = move_build(from, to) {
+        error!("{}", error.message);
+    }
+    info!("Renamed sketch {} to {}", from, to);
+    Ok(target)
+}
+
+// Remove a sketch and its snapshots, refusing a sketch with a queued or running job
+pub fn remove_sketch(sketch_name: &str) -> Result<PathBuf, PathError> {
+    let sketch_dir = existing_sketch_dir(sketch_name)?;
+    let _sketch_lock = lock_idle_sketch(sketch_name)?;
+    fs::remove_dir_all(&sketch_dir).map_err(|e| {
+        io_error(format!("Failed to remove sketch {}: {}", sketch_name, e))
+    })?;
+    if let Err(error) = remove_snapshots(sketch_name) {
+        error!("Failed to remove snapshots of {}: {}", sketch_name, error.message);
+    }
+    info!("Removed sketch {}", sketch_name);
+    Ok(sketch_dir)
+}
+
+// Copy a sketch under a new name. The build cache is not copied, the copy
+// builds from scratch.
+pub fn duplicate_sketch(from: &str, to: &str) -> Result<PathBuf, PathError> {
+    let (source, target) = resolve_source_and_target(from, to)?;
+    if fs::symlink_metadata(&target).is_ok() {
+        return Err(PathError::new(ALREADY_EXISTS, format!("Sketch {} already exists", to)));
+    }
+    copy_dir_recursive(&source, &target)
+        .map_err(|e| io_error(format!("Failed to copy sketch {}: {}", from, e)))
+        .and_then(|_| rename_main_file(&target, from, to))
+        .inspect_err(|_| {
+            let _ = fs::remove_dir_all(&target);
+        })?;
+    info!("Duplicated sketch {} as {}", from, to);
+    Ok(target)
+}
+
+// What the project picker shows about a sketch, read from its files, sketch.yaml and last build
+pub fn sketch_info(sketch_name: &str) -> Result<SketchInfo, PathError> {
+    let sketch_dir = existing_sketch_dir(sketch_name)?;
+    let files = list_sketch_files(sketch_name)?;
+    let main_file = format!("{}.ino", sketch_name);
+    let valid = sketch_dir.join(&main_file).is_file();
+    // A broken sketch.yaml leaves the board and port empty
+    let sketch_yaml = read_sketch_yaml(sketch_name).unwrap_or_default();
+    let build_info = read_build_info(sketch_name);
+    Ok(SketchInfo {
+        name: sketch_name.to_string(),
+        main_file: valid.then_some(main_file),
+        valid,
+        file_count: files
+            .iter()
+            .filter(|file| file.is_file)
+            .count(),
+        total_size: files
+            .iter()
+            .filter(|file| file.is_file)
+            .map(|file| file.size)
+            .sum(),
+        last_modified: files
+            .iter()
+            .map(|file| file.last_modified.clone())
+            .max(),
+        default_fqbn: sketch_yaml.default_fqbn,
+        default_port: sketch_yaml.default_port,
+        last_build_success: build_info.as_ref().map(|build_info| build_info.success),
+        last_build_at: build_info.map(|build_info| build_info.finished_at),
+    })
+}
+
+// The sketches in the sketches directory, filtered and sorted as asked
+pub fn get_sketches_list(options: &SketchListOptions) -> Result<Vec<SketchInfo>, String> {
+    let sort = options.sort.as_deref().unwrap_or("name");
+    if !SORT_KEYS.contains(&sort) {
+        return Err(format!("Invalid sort {}, expected one of {:?}", sort, SORT_KEYS));
+    }
+    let sketch_dir = get_sketch_directory()?;
+    let entries = fs
+        ::read_dir(&sketch_dir)
+        .map_err(|e| format!("Failed to read sketches directory: {}", e))?;
+    let search = options.search.as_ref().map(|search| search.to_lowercase());
+    let mut sketches = entries
+        .flatten()
+        .filter(|entry| entry.path().is_dir())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        // Renames, imports and templates stage their folders under a dot name
+        .filter(|name| !name.starts_with('.'))
+        .filter(|name| {
+            search.as_ref().is_none_or(|search| name.to_lowercase().contains(search))
+        })
+        .filter_map(|name| sketch_info(&name).ok())
+        .filter(|sketch| options.fqbn.is_none() || sketch.default_fqbn == options.fqbn)
+        .filter(|sketch| options.valid.is_none_or(|valid| sketch.valid == valid))
+        .collect::<Vec<_>>();
+
+    // Sorting by name first keeps ties in name order
+    sketches.sort_by_key(|sketch| sketch.name.to_lowercase());
+    match sort {
+        "modified" => sketches.sort_by(|a, b| a.last_modified.cmp(&b.last_modified)),
+        "size" => sketches.sort_by_key(|sketch| sketch.total_size),
+        "built" => sketches.sort_by(|a, b| a.last_build_at.cmp(&b.last_build_at)),
+        _ => {}
+    }
+    if options.descending {
+        sketches.reverse();
+    }
+    Ok(sketches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sketch(root: &Path, name: &str) -> PathBuf {
+        let sketch_dir = root.join(name);
+        fs::create_dir(&sketch_dir).unwrap();
+        fs::write(sketch_dir.join(format!("{}.ino", name)), "void setup() {}").unwrap();
+        sketch_dir
+    }
+
+    #[test]
+    fn renames_when_only_the_case_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = sketch(dir.path(), "Blink");
+        let target = dir.path().join("blink");
+        let staging = dir.path().join(".blink.renaming");
+
+        move_sketch_folder(&source, &target, &staging).unwrap();
+        rename_main_file(&target, "Blink", "blink").unwrap();
+        let names = fs
+            ::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["blink"]);
+        assert!(target.join("blink.ino").is_file());
+    }
+
+    #[test]
+    fn moves_the_sketch_back_when_the_rename_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = sketch(dir.path(), "Blink");
+        // A folder that is not empty cannot be replaced by a rename
+        let target = sketch(dir.path(), "Other");
+        let staging = dir.path().join(".Other.renaming");
+
+        let error = move_sketch_folder(&source, &target, &staging).unwrap_err();
+        assert_eq!(error.code, IO_ERROR);
+        assert!(source.join("Blink.ino").is_file());
+        assert!(target.join("Other.ino").is_file());
+        assert!(!staging.exists());
+    }
+
+    #[test]
+    fn refuses_sketches_whose_job_lock_is_held() {
+        let sketch_lock = lock_idle_sketch("LockedSketch").unwrap();
+        assert_eq!(lock_idle_sketch("LockedSketch").unwrap_err().code, SKETCH_BUSY);
+        drop(sketch_lock);
+        assert!(lock_idle_sketch("LockedSketch").is_ok());
+    }
+
+    #[test]
+    fn leaves_sketches_without_a_main_file_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let sketch_dir = dir.path().join("Blink");
+        fs::create_dir(&sketch_dir).unwrap();
+        rename_main_file(&sketch_dir, "Blink", "Blinker").unwrap();
+        assert!(!sketch_dir.join("Blinker.ino").exists());
+    }
+}
@@ -0,0 +1,518 @@
+use std::collections::{ BTreeMap, BTreeSet, HashMap };
+use std::fs;
+use std::path::{ Path, PathBuf };
+use std::sync::{ Mutex, OnceLock };
+use std::time::{ Duration, Instant };
+use sha2::{ Digest, Sha256 };
+use tracing::{ info, error };
+use crate::models::*;
+use crate::builds::is_plain_name;
+use crate::diff::unified_diff;
+use crate::sketch_paths::{
+    existing_sketch_dir,
+    list_sketch_files,
+    resolve_sketch_file,
+    PathError,
+    INVALID_PATH,
+    IO_ERROR,
+    NOT_FOUND,
+};
+
+// Writes closer together than this belong to the same editing burst
+const BURST_GAP: Duration = Duration::from_secs(60);
+// Files larger than this (e.g. data files) are left out of snapshots
+const MAX_SNAPSHOT_FILE_SIZE: u64 = 4 * 1024 * 1024;
+// Per sketch: at most this many snapshots, none older than the age limit,
+// but the newest few are always kept
+const MAX_SNAPSHOTS: usize = 50;
+const MAX_SNAPSHOT_AGE_DAYS: i64 = 30;
+const MIN_SNAPSHOTS: usize = 5;
+
+// Serialises snapshots, restores and pruning so objects are never collected while in use.
+// The public functions block on it and on file I/O, call them on a blocking thread.
+static SNAPSHOT_LOCK: OnceLock<Mutex<()>> = OnceLock::new();
+// When each sketch was last written through write-sketch-file or delete-sketch-file
+static LAST_WRITES: OnceLock<Mutex<HashMap<String, Instant>>> = OnceLock::new();
+
+// Directory holding the snapshots of all sketches and the file contents they share
+pub fn get_snapshots_directory() -> PathBuf {
+    let current_dir = std::env::current_dir().unwrap_or_else(|_| Path::new(".").to_path_buf());
+    current_dir.join("snapshots")
+}
+
+fn objects_directory() -> PathBuf {
+    get_snapshots_directory().join("objects")
+}
+
+fn sketch_snapshots_directory(sketch_name: &str) -> PathBuf {
+    get_snapshots_directory().join("sketches").join(sketch_name)
+}
+
+fn last_writes() -> &'static Mutex<HashMap<String, Instant>> {
+    LAST_WRITES.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn io_error(message: String) -> PathError {
+    PathError::new(IO_ERROR, message)
+}
+
+fn lock_snapshots() -> Result<std::sync::MutexGuard<'static, ()>, PathError> {
+    SNAPSHOT_LOCK.get_or_init(|| Mutex::new(()))
+        .lock()
+        .map_err(|_| io_error("Failed to lock snapshots".to_string()))
+}
+
+fn object_name(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+// Store a file content once, named after its SHA-256 digest. An existing object is
+// only reused when its bytes match, a damaged one is written again.
+fn store_object(objects_dir: &Path, data: &[u8]) -> std::io::Result<String> {
+    let name = object_name(data);
+    let path = objects_dir.join(&name);
+    if fs::read(&path).is_ok_and(|existing| existing == data) {
+        return Ok(name);
+    }
+    // Write under another name first, so an interrupted write leaves no partial object
+    fs::create_dir_all(objects_dir)?;
+    let partial = objects_dir.join(format!(".{}.partial", name));
+    fs::write(&partial, data)?;
+    fs::rename(&partial, &path)?;
+    Ok(name)
+}
+
+fn read_object(objects_dir: &Path, name: &str) -> Result<Vec<u8>, PathError> {
+    if !is_plain_name(name) {
+        return Err(PathError::new(INVALID_PATH, format!("Invalid snapshot object {}", name)));
+    }
+    let data = fs::read(objects_dir.join(name)).map_err(|e| {
+        io_error(format!("Failed to read snapshot object {}: {}", name, e))
+    })?;
+    if object_name(&data) != name {
+        return Err(io_error(format!("Snapshot object {} is damaged", name)));
+    }
+    Ok(data)
+}
+
+// The snapshots of a sketch, oldest first
+fn read_snapshots(sketch_name: &str) -> Vec<Snapshot> {
+    let Ok(entries) = fs::read_dir(sketch_snapshots_directory(sketch_name)) else {
+        return vec![];
+    };
+    let mut snapshots = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "json"))
+        .filter_map(|entry| {
+            let content = fs::read_to_string(entry.path()).ok()?;
+            serde_json::from_str::<Snapshot>(&content).ok()
+        })
+        .collect::<Vec<_>>();
+    snapshots.sort_by(|a, b| a.id.cmp(&b.id));
+    snapshots
+}
+
+fn write_snapshot(sketch_name: &str, snapshot: &Snapshot) -> Result<(), PathError> {
+    let directory = sketch_snapshots_directory(sketch_name);
+    let path = directory.join(format!("{}.json", snapshot.id));
+    fs
+        ::create_dir_all(&directory)
+        .and_then(|_| {
+            fs::write(&path, serde_json::to_string_pretty(snapshot).unwrap_or_default())
+        })
+        .map_err(|e| io_error(format!("Failed to write {}: {}", path.display(), e)))
+}
+
+fn find_snapshot(sketch_name: &str, snapshot_id: &str) -> Result<Snapshot, PathError> {
+    if !is_plain_name(snapshot_id) {
+        return Err(PathError::new(INVALID_PATH, format!("Invalid snapshot id {}", snapshot_id)));
+    }
+    read_snapshots(sketch_name)
+        .into_iter()
+        .find(|snapshot| snapshot.id == snapshot_id)
+        .ok_or_else(|| {
+            PathError::new(
+                NOT_FOUND,
+                format!("Sketch {} has no snapshot {}", sketch_name, snapshot_id)
+            )
+        })
+}
+
+// The files of a sketch by their path relative to it, e.g. "src/motor.h"
+fn current_files(sketch_name: &str) -> Result<BTreeMap<String, PathBuf>, PathError> {
+    let sketch_dir = existing_sketch_dir(sketch_name)?;
+    let base = sketch_dir.canonicalize().unwrap_or(sketch_dir);
+    Ok(
+        list_sketch_files(sketch_name)?
+            .into_iter()
+            .filter(|file| file.is_file)
+            .filter_map(|file| {
+                let path = PathBuf::from(&file.path);
+                let relative = path
+                    .strip_prefix(&base)
+                    .ok()?
+                    .components()
+                    .map(|component| component.as_os_str().to_string_lossy().to_string())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                Some((relative, path))
+            })
+            .collect()
+    )
+}
+
+fn take_snapshot_locked(sketch_name: &str, reason: &str) -> Result<Snapshot, PathError> {
+    let objects_dir = objects_directory();
+    let mut files = vec![];
+    for (relative, path) in current_files(sketch_name)? {
+        let size = fs::metadata(&path).map(|metadata| metadata.len()).unwrap_or(0);
+        if size > MAX_SNAPSHOT_FILE_SIZE {
+            continue;
+        }
+        let data = fs
+            ::read(&path)
+            .map_err(|e| io_error(format!("Failed to read {}: {}", path.display(), e)))?;
+        let object = store_object(&objects_dir, &data).map_err(|e| {
+            io_error(format!("Failed to store snapshot of {}: {}", relative, e))
+        })?;
+        files.push(SnapshotFile { path: relative, object, size });
+    }
+
+    // Nothing changed since the last snapshot, keep that one
+    let snapshots = read_snapshots(sketch_name);
+    if let Some(latest) = snapshots.last() {
+        let unchanged =
+            latest.files.len() == files.len() &&
+            latest.files
+                .iter()
+                .zip(&files)
+                .all(|(a, b)| a.path == b.path && a.object == b.object);
+        if unchanged {
+            return Ok(latest.clone());
+        }
+    }
+
+    let now = chrono::Utc::now();
+    let mut id = now.format("%Y%m%dT%H%M%S%3fZ").to_string();
+    if snapshots.iter().any(|snapshot| snapshot.id == id) {
+        id = format!("{}-{}", id, snapshots.len());
+    }
+    let snapshot = Snapshot {
+        id,
+        reason: reason.to_string(),
+        created_at: now.to_rfc3339(),
+        files,
+    };
+    write_snapshot(sketch_name, &snapshot)?;
+    info!("Took {} snapshot {} of {}", reason, snapshot.id, sketch_name);
+    prune_snapshots(sketch_name);
+    Ok(snapshot)
+}
+
+// Save the current files of a sketch, unless they match its latest snapshot
+pub fn take_snapshot(sketch_name: &str, reason: &str) -> Result<Snapshot, PathError> {
+    let _guard = lock_snapshots()?;
+    take_snapshot_locked(sketch_name, reason)
+}
+
+// Snapshot a sketch before the first write of an editing burst, so the state before
+// the edits can be restored. Failures are logged, they must not block the write.
+pub fn snapshot_before_write(sketch_name: &str) {
+    let new_burst = match last_writes().lock() {
+        Ok(mut writes) => {
+            let now = Instant::now();
+            let previous = writes.insert(sketch_name.to_string(), now);
+            previous.is_none_or(|previous| now.duration_since(previous) > BURST_GAP)
+        }
+        Err(_) => true,
+    };
+    if
+        new_burst &&
+        existing_sketch_dir(sketch_name).is_ok() &&
+        let Err(e) = take_snapshot(sketch_name, "before-edit")
+    {
+        error!("Failed to snapshot {}: {}", sketch_name, e);
+    }
+}
+
+// The snapshots of a sketch, newest first. Expired snapshots are removed first, so
+// they go away even when no new snapshot is taken.
+pub fn list_snapshots(sketch_name: &str) -> Result<Vec<SnapshotSummary>, PathError> {
+    existing_sketch_dir(sketch_name)?;
+    let _guard = lock_snapshots()?;
+    prune_snapshots(sketch_name);
+    Ok(
+        read_snapshots(sketch_name)
+            .into_iter()
+            .rev()
+            .map(|snapshot| SnapshotSummary {
+                file_count: snapshot.files.len(),
+                size: snapshot.files
+                    .iter()
+                    .map(|file| file.size)
+                    .sum(),
+                id: snapshot.id,
+                reason: snapshot.reason,
+                created_at: snapshot.created_at,
+            })
+            .collect()
+    )
+}
+
+// Contents of a snapshot's files by path
+fn snapshot_contents(snapshot: &Snapshot) -> Result<BTreeMap<String, Vec<u8>>, PathError> {
+    let objects_dir = objects_directory();
+    snapshot.files
+        .iter()
+        .map(|file| Ok((file.path.clone(), read_object(&objects_dir, &file.object)?)))
+        .collect()
+}
+
+// Per changed file, a unified diff from a snapshot to another snapshot, or to the
+// current files when `against` is not given
+pub fn diff_snapshot(
+    sketch_name: &str,
+    snapshot_id: &str,
+    against: Option<&str>
+) -> Result<Vec<FileDiff>, PathError> {
+    existing_sketch_dir(sketch_name)?;
+    // Keeps the objects from being collected while they are read
+    let _guard = lock_snapshots()?;
+    let old = snapshot_contents(&find_snapshot(sketch_name, snapshot_id)?)?;
+    let (new, new_label) = match against {
+        Some(against) => (snapshot_contents(&find_snapshot(sketch_name, against)?)?, against),
+        None => {
+            let mut current = BTreeMap::new();
+            for (relative, path) in current_files(sketch_name)? {
+                let data = fs
+                    ::read(&path)
+                    .map_err(|e| io_error(format!("Failed to read {}: {}", path.display(), e)))?;
+                current.insert(relative, data);
+            }
+            (current, "current")
+        }
+    };
+
+    let paths = old.keys().chain(new.keys()).collect::<BTreeSet<_>>();
+    let mut diffs = vec![];
+    for path in paths {
+        let (old_data, new_data) = (old.get(path), new.get(path));
+        let status = match (old_data, new_data) {
+            (Some(a), Some(b)) if a == b => {
+                continue;
+            }
+            (Some(_), Some(_)) => "modified",
+            (Some(_), None) => "removed",
+            _ => "added",
+        };
+        let text = |data: Option<&Vec<u8>>| {
+            data.map_or(Some(String::new()), |data| String::from_utf8(data.clone()).ok())
+        };
+        let diff = match (text(old_data), text(new_data)) {
+            (Some(old_text), Some(new_text)) =>
+                unified_diff(
+                    &old_text,
+                    &new_text,
+                    &format!("{} ({})", path, snapshot_id),
+                    &format!("{} ({})", path, new_label)
+                ),
+            // Binary files are only reported as changed
+            _ => String::new(),
+        };
+        diffs.push(FileDiff {
+            path: path.clone(),
+            status: status.to_string(),
+            diff,
+        });
+    }
+    Ok(diffs)
+}
+
+// Put a sketch's files back as they were in a snapshot. The current state is
+// snapshotted first, so a restore can be undone.
+pub fn restore_snapshot(sketch_name: &str, snapshot_id: &str) -> Result<Snapshot, PathError> {
+    let _guard = lock_snapshots()?;
+    let snapshot = find_snapshot(sketch_name, snapshot_id)?;
+    let contents = snapshot_contents(&snapshot)?;
+    take_snapshot_locked(sketch_name, "before-restore")?;
+
+    for (relative, path) in current_files(sketch_name)? {
+        let too_large = fs::metadata(&path).is_ok_and(|metadata| {
+            metadata.len() > MAX_SNAPSHOT_FILE_SIZE
+        });
+        // Files never snapshotted because of their size are left alone
+        if !contents.contains_key(&relative) && !too_large {
+            fs::remove_file(&path).map_err(|e| {
+                io_error(format!("Failed to remove {}: {}", path.display(), e))
+            })?;
+        }
+    }
+    for (relative, data) in &contents {
+        let path = resolve_sketch_file(sketch_name, relative)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| {
+                io_error(format!("Failed to create {}: {}", parent.display(), e))
+            })?;
+        }
+        fs::write(&path, data).map_err(|e| {
+            io_error(format!("Failed to write {}: {}", path.display(), e))
+        })?;
+    }
+    info!("Restored snapshot {} of {}", snapshot_id, sketch_name);
+    Ok(snapshot)
+}
+
+// Apply the retention policy to a sketch's snapshots and drop unused objects
+fn prune_snapshots(sketch_name: &str) {
+    let snapshots = read_snapshots(sketch_name);
+    let cutoff = chrono::Utc::now() - chrono::TimeDelta::days(MAX_SNAPSHOT_AGE_DAYS);
+    let excess = snapshots.len().saturating_sub(MAX_SNAPSHOTS);
+    let removable = snapshots.len().saturating_sub(MIN_SNAPSHOTS);
+    let mut removed = 0;
+    for (index, snapshot) in snapshots.iter().enumerate().take(removable) {
+        let expired = chrono::DateTime
+            ::parse_from_rfc3339(&snapshot.created_at)
+            .is_ok_and(|created_at| created_at < cutoff);
+        if index < excess || expired {
+            let path = sketch_snapshots_directory(sketch_name).join(
+                format!("{}.json", snapshot.id)
+            );
+            match fs::remove_file(&path) {
+                Ok(()) => {
+                    removed += 1;
+                }
+                Err(e) => error!(?e, ?path, "Failed to remove snapshot"),
+            }
+        }
+    }
+    if removed > 0 {
+        info!("Removed {} old snapshots of {}", removed, sketch_name);
+        collect_garbage();
+    }
+}
+
+// Apply the retention policy to the snapshots of every sketch, e.g. at startup
+pub fn prune_all_snapshots() {
+    let Ok(_guard) = lock_snapshots() else {
+        return;
+    };
+    let Ok(sketches) = fs::read_dir(get_snapshots_directory().join("sketches")) else {
+        return;
+    };
+    for entry in sketches.filter_map(|entry| entry.ok()) {
+        prune_snapshots(&entry.file_name().to_string_lossy());
+    }
+}
+
+// Drop the history of a removed sketch
+pub fn remove_snapshots(sketch_name: &str) -> Result<(), PathError> {
+    if !is_plain_name(sketch_name) {
+        return Err(PathError::new(INVALID_PATH, format!("Invalid sketch name {}", sketch_name)));
+    }
+    let _guard = lock_snapshots()?;
+    if let Ok(mut writes) = last_writes().lock() {
+        writes.remove(sketch_name);
+    }
+    let directory = sketch_snapshots_directory(sketch_name);
+    if !directory.exists() {
+        return Ok(());
+    }
+    fs::remove_dir_all(&directory).map_err(|e| {
+        io_error(format!("Failed to remove snapshots of {}: {}", sketch_name, e))
+    })?;
+    info!("Removed the snapshots of {}", sketch_name);
+    collect_garbage();
+    Ok(())
+}
+
+// Remove the objects no snapshot of any sketch refers to anymore
+fn collect_garbage() {
+    let Ok(sketches) = fs::read_dir(get_snapshots_directory().join("sketches")) else {
+        return;
+    };
+    let referenced = sketches
+        .filter_map(|entry| entry.ok())
+        .flat_map(|entry| read_snapshots(&entry.file_name().to_string_lossy()))
+        .flat_map(|snapshot| snapshot.files.into_iter().map(|file| file.object))
+        .collect::<BTreeSet<_>>();
+    let Ok(objects) = fs::read_dir(objects_directory()) else {
+        return;
+    };
+    for object in objects.filter_map(|entry| entry.ok()) {
+        if
+            !referenced.contains(&*object.file_name().to_string_lossy()) &&
+            let Err(e) = fs::remove_file(object.path())
+        {
+            error!(?e, "Failed to remove snapshot object");
+        }
+    }
+}
+
+// Move the history of a renamed sketch along, with its main file renamed in every snapshot
+pub fn rename_snapshots(from: &str, to: &str) -> Result<(), PathError> {
+    let _guard = lock_snapshots()?;
+    let source = sketch_snapshots_directory(from);
+    if !source.exists() {
+        return Ok(());
+    }
+    let target = sketch_snapshots_directory(to);
+    if target.exists() {
+        fs::remove_dir_all(&target).map_err(|e| {
+            io_error(format!("Failed to clear old snapshots of {}: {}", to, e))
+        })?;
+    }
+    fs::rename(&source, &target).map_err(|e| {
+        io_error(format!("Failed to move snapshots of {}: {}", from, e))
+    })?;
+    let (old_main, new_main) = (format!("{}.ino", from), format!("{}.ino", to));
+    for mut snapshot in read_snapshots(to) {
+        for file in &mut snapshot.files {
+            if file.path == old_main {
+                file.path = new_main.clone();
+            }
+        }
+        write_snapshot(to, &snapshot)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_objects_by_sha256() {
+        assert_eq!(
+            object_name(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn stores_and_reads_objects() {
+        let dir = tempfile::tempdir().unwrap();
+        let name = store_object(dir.path(), b"void setup() {}\n").unwrap();
+        assert_eq!(store_object(dir.path(), b"void setup() {}\n").unwrap(), name);
+        assert_eq!(read_object(dir.path(), &name).unwrap(), b"void setup() {}\n");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn refuses_damaged_objects() {
+        let dir = tempfile::tempdir().unwrap();
+        let name = store_object(dir.path(), b"void loop() {}\n").unwrap();
+        fs::write(dir.path().join(&name), b"void loop() { broken }\n").unwrap();
+        let error = read_object(dir.path(), &name).unwrap_err();
+        assert_eq!(error.code, IO_ERROR);
+
+        // Storing the content again repairs the object
+        assert_eq!(store_object(dir.path(), b"void loop() {}\n").unwrap(), name);
+        assert_eq!(read_object(dir.path(), &name).unwrap(), b"void loop() {}\n");
+    }
+
+    #[test]
+    fn refuses_object_names_with_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(read_object(dir.path(), "../secret").unwrap_err().code, INVALID_PATH);
+    }
+}
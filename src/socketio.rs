@@ -4,7 +4,86 @@ use socketioxide::{ extract::{ AckSender, Data, SocketRef } };
 use tracing::{ info, error };
 use crate::usb::{ find_mount_point, list_directory_recursive, read_file_as_base64, write_file };
 use crate::models::*;
-use crate::compiler::run_arduino_command;
+use crate::compiler::{ is_core_installed, is_library_installed, run_arduino_command };
+use crate::progress::InstallProgressParser;
+use crate::board_manager::{ add_additional_url, list_additional_urls, remove_additional_url };
+use crate::config::{ dump_settings, get_setting, reset_settings, set_setting };
+use crate::libraries::{ list_outdated_libraries, resolve_dependencies };
+use crate::examples::{ create_sketch_from_example, find_example, list_examples };
+use crate::check::{ check_sketch, CheckResult, DEFAULT_DEBOUNCE };
+use crate::environment::{ describe_ide_import, import_ide_sketchbook };
+use crate::logs::{
+    job_log_path,
+    read_job_log,
+    subscribe_job_log,
+    subscribe_log_events,
+    unsubscribe_job_log,
+    unsubscribe_socket,
+};
+use crate::compilation_database::{
+    cached_compilation_database,
+    clear_fingerprint,
+    compilation_database_fingerprint,
+    get_compilation_database_path,
+    read_compilation_database,
+    store_fingerprint,
+};
+use crate::boards::{
+    add_board_id,
+    build_fqbn,
+    get_board_details,
+    read_board_ids,
+    resolve_fqbn,
+    FqbnResolution,
+};
+use crate::sketch_yaml::{
+    delete_profile,
+    get_profile,
+    read_sketch_yaml,
+    remember_board,
+    set_default_profile,
+    set_profile,
+};
+use crate::sketch_paths::{
+    existing_sketch_dir,
+    list_sketch_files,
+    resolve_sketch_dir,
+    resolve_sketch_file,
+    PathError,
+    IO_ERROR,
+};
+use crate::sketches::{ duplicate_sketch, get_sketches_list, remove_sketch, rename_sketch };
+use crate::sketch_archive::export_sketch;
+use crate::templates::{ create_sketch_from_template, list_templates };
+use crate::snapshots::{
+    diff_snapshot,
+    list_snapshots,
+    restore_snapshot,
+    snapshot_before_write,
+    take_snapshot,
+};
+use crate::builds::{
+    compile_args,
+    finish_compile,
+    get_binaries_directory,
+    get_build_path,
+    get_output_path,
+    is_build_current,
+    is_plain_name,
+    read_build_info,
+    reset_dir,
+};
+use crate::jobs::{
+    cancel_job,
+    list_jobs,
+    new_job_id,
+    run_job,
+    subscribe_job_events,
+    JobCheck,
+    JobCleanup,
+    JobRequest,
+};
+use std::collections::BTreeMap;
 use std::path::{ Path, PathBuf };
 use std::fs;
 use chrono;
@@ -26,10 +105,13 @@ pub fn on_connect(socket: SocketRef, Data(data): Data<Value>) {
     // Specific commands for common Arduino CLI operations
     register_arduino_handlers(&socket, Arc::clone(&port_address));
     register_usb_handlers(&socket);
+    register_job_handlers(&socket);
     check_port_connection(socket.clone(), Arc::clone(&port_address));
-    // Start automatic log monitoring
-    start_log_monitoring(socket.clone());
-    enable_unsafe_install(true);
+    forward_job_events(socket.clone());
+    forward_log_events(socket.clone());
+    socket.on_disconnect(|socket: SocketRef| {
+        unsubscribe_socket(&socket.id.to_string());
+    });
 }
 fn is_device_connected(port: &str) -> bool {
     let ports = serialport::available_ports().unwrap_or_else(|_| { vec![] });
@@ -64,6 +146,58 @@ fn check_port_connection(socket: SocketRef, port_address: Arc<Mutex<Option<Strin
         }
     });
 }
+// Forward job queue changes from every client to this socket
+fn forward_job_events(socket: SocketRef) {
+    let mut events = subscribe_job_events();
+    tokio::spawn(async move {
+        loop {
+            match events.recv().await {
+                Ok(info) => {
+                    let event = match info.status {
+                        JobStatus::Queued => "job-queued",
+                        JobStatus::Running => "job-started",
+                        _ => "job-finished",
+                    };
+                    // Stop forwarding once the socket is gone
+                    if socket.emit(event, &info).is_err() {
+                        break;
+                    }
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                    info!(skipped, "Job event forwarding lagged behind");
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => {
+                    break;
+                }
+            }
+        }
+    });
+}
+// Forward the log entries of the jobs this socket started or subscribed to
+fn forward_log_events(socket: SocketRef) {
+    let mut events = subscribe_log_events();
+    let socket_id = socket.id.to_string();
+    tokio::spawn(async move {
+        loop {
+            match events.recv().await {
+                Ok(event) => {
+                    if !event.sockets.contains(&socket_id) {
+                        continue;
+                    }
+                    if socket.emit("logs", &event.log).is_err() {
+                        break;
+                    }
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                    info!(skipped, "Log forwarding lagged behind");
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => {
+                    break;
+                }
+            }
+        }
+    });
+}
 fn register_usb_handlers(socket: &SocketRef) {
     socket.on("list-mount", |ack: AckSender| {
         tokio::spawn(async move {
@@ -79,6 +213,8 @@ fn register_usb_handlers(socket: &SocketRef) {
                         error: None,
                         command: "list-mount".to_string(),
                         args: vec![],
+                        job_id: None,
+                        error_code: None,
                     };
                     ack.send(&response).ok();
                 }
@@ -92,6 +228,8 @@ fn register_usb_handlers(socket: &SocketRef) {
                         error: Some("Mount point not found".to_string()),
                         command: "list-mount".to_string(),
                         args: vec![],
+                        job_id: None,
+                        error_code: None,
                     };
                     ack.send(&response).ok();
                 }
@@ -120,6 +258,8 @@ fn register_usb_handlers(socket: &SocketRef) {
                                 error: None,
                                 command: "list-files".to_string(),
                                 args: vec![file_path.to_string()],
+                                job_id: None,
+                                error_code: None,
                             };
                             ack.send(&response).ok();
                         }
@@ -133,6 +273,8 @@ fn register_usb_handlers(socket: &SocketRef) {
                                 error: Some(format!("Error listing files: {}", e)),
                                 command: "list-files".to_string(),
                                 args: vec![file_path.to_string()],
+                                job_id: None,
+                                error_code: None,
                             };
                             ack.send(&response).ok();
                         }
@@ -148,6 +290,8 @@ fn register_usb_handlers(socket: &SocketRef) {
                         error: Some("Mount point not found".to_string()),
                         command: "list-files".to_string(),
                         args: vec![],
+                        job_id: None,
+                        error_code: None,
                     };
                     ack.send(&response).ok();
                 }
@@ -174,6 +318,8 @@ fn register_usb_handlers(socket: &SocketRef) {
                             error: None,
                             command: "add-file".to_string(),
                             args: vec![path],
+                            job_id: None,
+                            error_code: None,
                         };
                         ack.send(&response).ok();
                     }
@@ -187,6 +333,8 @@ fn register_usb_handlers(socket: &SocketRef) {
                             error: Some(format!("Error writing file: {}", e)),
                             command: "add-file".to_string(),
                             args: vec![path],
+                            job_id: None,
+                            error_code: None,
                         };
                         ack.send(&response).ok();
                     }
@@ -202,6 +350,8 @@ fn register_usb_handlers(socket: &SocketRef) {
                     error: Some("Mount point not found".to_string()),
                     command: "add-file".to_string(),
                     args: vec![],
+                    job_id: None,
+                    error_code: None,
                 };
                 ack.send(&response).ok();
             }
@@ -228,6 +378,8 @@ fn register_usb_handlers(socket: &SocketRef) {
                                 error: None,
                                 command: "remove-file".to_string(),
                                 args: vec![file_path.to_string()],
+                                job_id: None,
+                                error_code: None,
                             };
                             ack.send(&response).ok();
                         }
@@ -241,6 +393,8 @@ fn register_usb_handlers(socket: &SocketRef) {
                                 error: Some(format!("Error removing file: {}", e)),
                                 command: "remove-file".to_string(),
                                 args: vec![file_path.to_string()],
+                                job_id: None,
+                                error_code: None,
                             };
                             ack.send(&response).ok();
                         }
@@ -256,6 +410,8 @@ fn register_usb_handlers(socket: &SocketRef) {
                         error: Some("Mount point not found".to_string()),
                         command: "remove-file".to_string(),
                         args: vec![],
+                        job_id: None,
+                        error_code: None,
                     };
                     ack.send(&response).ok();
                 }
@@ -283,6 +439,8 @@ fn register_usb_handlers(socket: &SocketRef) {
                                 error: None,
                                 command: "remove-dir".to_string(),
                                 args: vec![dir_path.to_string()],
+                                job_id: None,
+                                error_code: None,
                             };
                             ack.send(&response).ok();
                         }
@@ -296,6 +454,8 @@ fn register_usb_handlers(socket: &SocketRef) {
                                 error: Some(format!("Error removing directory: {}", e)),
                                 command: "remove-dir".to_string(),
                                 args: vec![dir_path.to_string()],
+                                job_id: None,
+                                error_code: None,
                             };
                             ack.send(&response).ok();
                         }
@@ -311,6 +471,8 @@ fn register_usb_handlers(socket: &SocketRef) {
                         command: "remove-dir".to_string(),
                         args: vec![],
                         output_json: None,
+                        job_id: None,
+                        error_code: None,
                     };
                     ack.send(&response).ok();
                 }
@@ -338,6 +500,8 @@ fn register_usb_handlers(socket: &SocketRef) {
                                 error: None,
                                 command: "read-file".to_string(),
                                 args: vec![file_path.to_string()],
+                                job_id: None,
+                                error_code: None,
                             };
 
                             ack.send(&response).ok();
@@ -352,6 +516,8 @@ fn register_usb_handlers(socket: &SocketRef) {
                                 error: Some(format!("Error reading file: {}", e)),
                                 command: "read-file".to_string(),
                                 args: vec![file_path.to_string()],
+                                job_id: None,
+                                error_code: None,
                             };
                             ack.send(&response).ok();
                         }
@@ -367,6 +533,8 @@ fn register_usb_handlers(socket: &SocketRef) {
                         error: Some("Mount point not found".to_string()),
                         command: "read-file".to_string(),
                         args: vec![],
+                        job_id: None,
+                        error_code: None,
                     };
                     ack.send(&response).ok();
                 }
@@ -374,8 +542,133 @@ fn register_usb_handlers(socket: &SocketRef) {
         });
     });
 }
-fn get_sketches_list() -> Value {
-    todo!("Implement get_sketches_list function to return a list of sketches in JSON format");
+fn register_job_handlers(socket: &SocketRef) {
+    // List queued, running and recently finished jobs
+    socket.on("list-jobs", |ack: AckSender| {
+        let jobs = list_jobs();
+        let response = create_success_response(
+            format!("{} jobs", jobs.len()),
+            "list-jobs",
+            vec![],
+            Some(serde_json::to_value(jobs).unwrap_or(Value::Null))
+        );
+        ack.send(&response).ok();
+    });
+    // Receive the `logs` entries of a job started by another socket
+    socket.on("subscribe-job-log", |socket: SocketRef, Data::<Value>(data), ack: AckSender| {
+        let Some(job_id) = extract_string_field(&data, "job_id") else {
+            let error_response = create_error_response(
+                "Missing job id",
+                "subscribe-job-log",
+                vec![]
+            );
+            ack.send(&error_response).ok();
+            return;
+        };
+        // Subscribe before looking at the job, so a job that finishes in between still
+        // ends the subscription
+        let socket_id = socket.id.to_string();
+        subscribe_job_log(&job_id, &socket_id);
+        let active = list_jobs()
+            .iter()
+            .any(
+                |job|
+                    job.job_id == job_id &&
+                    matches!(job.status, JobStatus::Queued | JobStatus::Running)
+            );
+        if !active {
+            unsubscribe_job_log(&job_id, &socket_id);
+            let error_response = create_error_response(
+                &format!("Job {} is not queued or running, use read-job-log", job_id),
+                "subscribe-job-log",
+                vec![job_id]
+            );
+            ack.send(&error_response).ok();
+            return;
+        }
+        let response = create_success_response(
+            format!("Subscribed to the log of job {}", job_id),
+            "subscribe-job-log",
+            vec![job_id],
+            None
+        );
+        ack.send(&response).ok();
+    });
+    socket.on("unsubscribe-job-log", |socket: SocketRef, Data::<Value>(data), ack: AckSender| {
+        let Some(job_id) = extract_string_field(&data, "job_id") else {
+            let error_response = create_error_response(
+                "Missing job id",
+                "unsubscribe-job-log",
+                vec![]
+            );
+            ack.send(&error_response).ok();
+            return;
+        };
+        unsubscribe_job_log(&job_id, &socket.id.to_string());
+        let response = create_success_response(
+            format!("Unsubscribed from the log of job {}", job_id),
+            "unsubscribe-job-log",
+            vec![job_id],
+            None
+        );
+        ack.send(&response).ok();
+    });
+    // Read the whole log of a job, e.g. after it finished
+    socket.on("read-job-log", |Data::<Value>(data), ack: AckSender| {
+        let Some(job_id) = extract_string_field(&data, "job_id") else {
+            let error_response = create_error_response("Missing job id", "read-job-log", vec![]);
+            ack.send(&error_response).ok();
+            return;
+        };
+        let response = match read_job_log(&job_id) {
+            Ok(entries) =>
+                create_success_response(
+                    format!("{} log entries", entries.len()),
+                    "read-job-log",
+                    vec![job_id],
+                    Some(serde_json::to_value(entries).unwrap_or(Value::Null))
+                ),
+            Err(error_msg) => create_error_response(&error_msg, "read-job-log", vec![job_id]),
+        };
+        ack.send(&response).ok();
+    });
+    // Cancel a running compile, upload or install job
+    socket.on("cancel-job", |Data::<Value>(data), ack: AckSender| {
+        let job_id = match extract_string_field(&data, "job_id") {
+            Some(id) => id,
+            None => {
+                let error_response = create_error_response("Missing job id", "cancel-job", vec![]);
+                ack.send(&error_response).ok();
+                return;
+            }
+        };
+        tokio::spawn(async move {
+            match cancel_job(&job_id).await {
+                Ok(_) => {
+                    let response = create_success_response(
+                        format!("Job {} cancelled", job_id),
+                        "cancel-job",
+                        vec![job_id.clone()],
+                        Some(
+                            serde_json::json!({
+                                "job_id": job_id,
+                                "status": JobStatus::Cancelled,
+                            })
+                        )
+                    );
+                    ack.send(&response).ok();
+                }
+                Err(error_msg) => {
+                    let error_response = create_error_response(
+                        &error_msg,
+                        "cancel-job",
+                        vec![job_id]
+                    );
+                    ack.send(&error_response).ok();
+                }
+            }
+        });
+    });
 }
 // Register specific handlers for common Arduino CLI operations
 // Helper function to create error responses
@@ -388,9 +681,33 @@ fn create_error_response(error_msg: &str, command: &str, args: Vec<String>) -> C
         error: Some(error_msg.to_string()),
         command: command.to_string(),
         args,
+        job_id: None,
+        error_code: None,
     }
 }
 
+// Helper function to create error responses for refused sketch paths
+fn create_path_error_response(
+    error: &PathError,
+    command: &str,
+    args: Vec<String>
+) -> CommandResponse {
+    CommandResponse {
+        error_code: Some(error.code.to_string()),
+        ..create_error_response(&error.message, command, args)
+    }
+}
+
+// Run sketch file work that blocks on I/O or locks, such as snapshots, on a
+// blocking thread instead of an async worker
+async fn run_blocking<T, F>(work: F) -> Result<T, PathError>
+    where F: FnOnce() -> Result<T, PathError> + Send + 'static, T: Send + 'static
+{
+    tokio::task
+        ::spawn_blocking(work).await
+        .unwrap_or_else(|e| Err(PathError::new(IO_ERROR, format!("Task failed: {}", e))))
+}
+
 // Helper function to create success responses
 fn create_success_response(
     output: String,
@@ -406,6 +723,8 @@ fn create_success_response(
         error: None,
         command: command.to_string(),
         args,
+        job_id: None,
+        error_code: None,
     }
 }
 
@@ -428,6 +747,12 @@ pub fn get_sketch_directory() -> Result<std::path::PathBuf, String> {
     Ok(sketch_dir)
 }
 
+// Helper function to get the directory holding a sketch's build output
+pub fn get_build_directory(sketch_name: &str) -> PathBuf {
+    let current_dir = std::env::current_dir().unwrap_or_else(|_| Path::new(".").to_path_buf());
+    current_dir.join("builds").join(sketch_name)
+}
+
 // Helper function to read file content
 async fn read_file_content(file_path: &std::path::Path) -> Result<String, String> {
     match std::fs::read_to_string(file_path) {
@@ -459,30 +784,106 @@ async fn run_arduino_command_async(command: &str, args: Vec<String>, ack: AckSen
     let response = run_arduino_command(&arduino_command).await;
     ack.send(&response).ok();
 }
-fn enable_unsafe_install(enable: bool) {
-    tokio::spawn(async move {
-        let args = vec![
-            "set".to_string(),
-            "library.enable_unsafe_install".to_string(),
-            enable.to_string()
-        ];
-        let arduino_command = ArduinoCommand {
-            command: "config".to_string(),
-            args,
-        };
-        let response = run_arduino_command(&arduino_command).await;
-        match response {
-            CommandResponse { success: true, output, .. } => {
-                info!("Unsafe install enabled: {}", output);
-            }
-            CommandResponse { error: Some(e), .. } => {
-                error!("Failed to enable unsafe install: {}", e);
-            }
-            _ => {
-                error!("Unexpected response when enabling unsafe install");
-            }
+
+// Helper function to use the requested FQBN, or detect the board connected to the port.
+// Fails with the candidate boards when the choice is ambiguous.
+async fn resolve_upload_fqbn(
+    fqbn: Option<String>,
+    port: &str,
+    sketch_name: Option<&str>,
+    command: &str
+) -> Result<String, CommandResponse> {
+    if let Some(fqbn) = fqbn {
+        return Ok(fqbn);
+    }
+    let default_fqbn = sketch_name
+        .and_then(|name| read_sketch_yaml(name).ok())
+        .and_then(|sketch_yaml| sketch_yaml.default_fqbn);
+    match resolve_fqbn(port, default_fqbn.as_deref()).await {
+        FqbnResolution::Resolved(fqbn) => {
+            info!(?port, ?fqbn, "Detected board");
+            Ok(fqbn)
         }
-    });
+        FqbnResolution::Ambiguous(candidates) => {
+            let error_response = create_error_response(
+                &format!("Several boards match port {}, choose an fqbn", port),
+                command,
+                vec![port.to_string()]
+            );
+            Err(CommandResponse {
+                output_json: Some(
+                    serde_json::json!({ "ambiguous": true, "candidates": candidates })
+                ),
+                ..error_response
+            })
+        }
+        FqbnResolution::NotFound =>
+            Err(
+                create_error_response(
+                    &format!("Could not detect the board on port {}, provide an fqbn", port),
+                    command,
+                    vec![port.to_string()]
+                )
+            ),
+    }
+}
+
+// Helper function to extract the selected board menu options, e.g.
+// {"board_options": {"PartitionScheme": "huge_app"}}
+fn extract_board_options(data: &Value) -> Result<BTreeMap<String, String>, String> {
+    match data.get("board_options") {
+        Some(Value::Null) | None => Ok(BTreeMap::new()),
+        Some(options) =>
+            serde_json
+                ::from_value(options.clone())
+                .map_err(|e| format!("Invalid board options: {}", e)),
+    }
+}
+
+// Helper function to get the job id requested by the client, or generate a new one
+fn extract_job_id(data: &Value) -> String {
+    extract_string_field(data, "job_id").unwrap_or_else(new_job_id)
+}
+
+// Helper function to run a long-running Arduino command as a job.
+// Output lines are emitted as `job-output` events while the command runs and
+// the final response is still sent through the ack.
+async fn run_arduino_job(socket: SocketRef, request: JobRequest, ack: AckSender) {
+    let job_id = request.job_id.clone();
+    if let Err(error_msg) = job_log_path(&job_id) {
+        let error_response = create_error_response(
+            &error_msg,
+            &request.command.command,
+            request.command.args
+        );
+        ack.send(&error_response).ok();
+        return;
+    }
+    // Tell the client its job id before the job is queued, so it can cancel the job
+    // without waiting for the final ack
+    socket
+        .emit("job-accepted", &serde_json::json!({ "job_id": job_id, "kind": request.kind }))
+        .ok();
+    // The socket that started the job receives its log entries
+    subscribe_job_log(&job_id, &socket.id.to_string());
+    // Downloads and installs also report structured `install-progress` events
+    let mut progress = match request.kind.as_str() {
+        "install-core" | "install-library" | "update-index" | "upgrade-core" | "upgrade-library" =>
+            Some(InstallProgressParser::new(&job_id)),
+        _ => None,
+    };
+    let response = run_job(request, |stream, line| {
+        let output = JobOutput {
+            job_id: job_id.clone(),
+            stream: stream.to_string(),
+            line: line.to_string(),
+        };
+        socket.emit("job-output", &output).ok();
+        if let Some(install_progress) = progress.as_mut().and_then(|parser| parser.parse(line)) {
+            socket.emit("install-progress", &install_progress).ok();
+        }
+    }).await;
+    ack.send(&response).ok();
 }
 fn extract_zip(zip_path: &str, extract_to: &str) -> Result<Vec<String>, DownloadError> {
     let file = fs::File::open(zip_path)?;
@@ -637,7 +1038,7 @@ fn register_arduino_handlers(socket: &SocketRef, port_address: Arc<Mutex<Option<
         });
     });
     // Install a core
-    socket.on("install-core", |Data::<Value>(data), ack: AckSender| {
+    socket.on("install-core", |socket: SocketRef, Data::<Value>(data), ack: AckSender| {
         let core_name = match extract_string_field(&data, "core") {
             Some(name) => name,
             None => {
@@ -651,15 +1052,202 @@ fn register_arduino_handlers(socket: &SocketRef, port_address: Arc<Mutex<Option<
             }
         };
 
+        let job_id = extract_job_id(&data);
+
         tokio::spawn(async move {
+            // Only roll back a cancelled install if the core was not there before. Checked
+            // inside the job so the install can already be cancelled while it looks.
+            let check_core = core_name.clone();
+            let check: JobCheck = Box::new(move || {
+                Box::pin(async move {
+                    if is_core_installed(&check_core).await {
+                        return vec![];
+                    }
+                    let core_id = check_core.split('@').next().unwrap_or(&check_core).to_string();
+                    vec![
+                        JobCleanup::Command(ArduinoCommand {
+                            command: "core".to_string(),
+                            args: vec!["uninstall".to_string(), core_id],
+                        })
+                    ]
+                })
+            });
             let args = vec![
                 "install".to_string(),
-                core_name,
-                "--log".to_string(),
-                "--log-file".to_string(),
-                "log.txt".to_string()
+                core_name
             ];
-            run_arduino_command_async("core", args, ack).await;
+            let request = JobRequest {
+                job_id,
+                kind: "install-core".to_string(),
+                sketch: None,
+                port: None,
+                command: ArduinoCommand {
+                    command: "core".to_string(),
+                    args,
+                },
+                cleanup: vec![],
+                check: Some(check),
+                prepare: None,
+                finish: None,
+            };
+            run_arduino_job(socket, request, ack).await;
+        });
+    });
+    // Update the core and library indexes
+    socket.on("update-index", |socket: SocketRef, Data::<Value>(data), ack: AckSender| {
+        let request = JobRequest {
+            job_id: extract_job_id(&data),
+            kind: "update-index".to_string(),
+            sketch: None,
+            port: None,
+            command: ArduinoCommand {
+                command: "update".to_string(),
+                args: vec![],
+            },
+            cleanup: vec![],
+            check: None,
+            prepare: None,
+            finish: None,
+        };
+        tokio::spawn(async move {
+            run_arduino_job(socket, request, ack).await;
+        });
+    });
+    // Upgrade a core, or every installed core when no core is given
+    socket.on("upgrade-core", |socket: SocketRef, Data::<Value>(data), ack: AckSender| {
+        let mut args = vec!["upgrade".to_string()];
+        if let Some(core_name) = extract_string_field(&data, "core") {
+            args.push(core_name);
+        }
+        let request = JobRequest {
+            job_id: extract_job_id(&data),
+            kind: "upgrade-core".to_string(),
+            sketch: None,
+            port: None,
+            command: ArduinoCommand {
+                command: "core".to_string(),
+                args,
+            },
+            cleanup: vec![],
+            check: None,
+            prepare: None,
+            finish: None,
+        };
+        tokio::spawn(async move {
+            run_arduino_job(socket, request, ack).await;
+        });
+    });
+    // Uninstall a core
+    socket.on("uninstall-core", |socket: SocketRef, Data::<Value>(data), ack: AckSender| {
+        let core_name = match extract_string_field(&data, "core") {
+            Some(name) => name,
+            None => {
+                let error_response = create_error_response(
+                    "Missing core name",
+                    "core",
+                    vec!["uninstall".to_string()]
+                );
+                ack.send(&error_response).ok();
+                return;
+            }
+        };
+        let request = JobRequest {
+            job_id: extract_job_id(&data),
+            kind: "uninstall-core".to_string(),
+            sketch: None,
+            port: None,
+            command: ArduinoCommand {
+                command: "core".to_string(),
+                args: vec![
+                    "uninstall".to_string(),
+                    core_name
+                ],
+            },
+            cleanup: vec![],
+            check: None,
+            prepare: None,
+            finish: None,
+        };
+        tokio::spawn(async move {
+            run_arduino_job(socket, request, ack).await;
+        });
+    });
+    // List the installed cores and libraries that have newer versions
+    socket.on("list-outdated", |ack: AckSender| {
+        tokio::spawn(async move {
+            run_arduino_command_async(
+                "outdated",
+                vec!["--format".to_string(), "json".to_string()],
+                ack
+            ).await;
+        });
+    });
+    // List the third-party package index URLs (board_manager.additional_urls)
+    socket.on("list-board-urls", |ack: AckSender| {
+        tokio::spawn(async move {
+            let response = match list_additional_urls().await {
+                Ok(urls) =>
+                    create_success_response(
+                        format!("{} board manager URLs", urls.len()),
+                        "list-board-urls",
+                        vec![],
+                        Some(serde_json::json!(urls))
+                    ),
+                Err(error_msg) => create_error_response(&error_msg, "list-board-urls", vec![]),
+            };
+            ack.send(&response).ok();
+        });
+    });
+    // Add a package index URL after checking it serves a package index
+    socket.on("add-board-url", |Data::<Value>(data), ack: AckSender| {
+        let url = match extract_string_field(&data, "url") {
+            Some(url) => url,
+            None => {
+                let error_response = create_error_response("Missing url", "add-board-url", vec![]);
+                ack.send(&error_response).ok();
+                return;
+            }
+        };
+        tokio::spawn(async move {
+            let response = match add_additional_url(&url).await {
+                Ok(urls) =>
+                    create_success_response(
+                        format!("Added {}", url),
+                        "add-board-url",
+                        vec![url],
+                        Some(serde_json::json!(urls))
+                    ),
+                Err(error_msg) => create_error_response(&error_msg, "add-board-url", vec![url]),
+            };
+            ack.send(&response).ok();
+        });
+    });
+    // Remove a package index URL
+    socket.on("remove-board-url", |Data::<Value>(data), ack: AckSender| {
+        let url = match extract_string_field(&data, "url") {
+            Some(url) => url,
+            None => {
+                let error_response = create_error_response(
+                    "Missing url",
+                    "remove-board-url",
+                    vec![]
+                );
+                ack.send(&error_response).ok();
+                return;
+            }
+        };
+        tokio::spawn(async move {
+            let response = match remove_additional_url(&url).await {
+                Ok(urls) =>
+                    create_success_response(
+                        format!("Removed {}", url),
+                        "remove-board-url",
+                        vec![url],
+                        Some(serde_json::json!(urls))
+                    ),
+                Err(error_msg) => create_error_response(&error_msg, "remove-board-url", vec![url]),
+            };
+            ack.send(&response).ok();
         });
     });
     //create a new sketch
@@ -676,42 +1264,180 @@ fn register_arduino_handlers(socket: &SocketRef, port_address: Arc<Mutex<Option<
                 return;
             }
         };
-        let sketch_path = format!("sketches/{}", sketch_name);
+        let sketch_path = match resolve_sketch_dir(&sketch_name) {
+            Ok(path) => path,
+            Err(error) => {
+                let error_response = create_path_error_response(
+                    &error,
+                    "create-sketch",
+                    vec![sketch_name]
+                );
+                ack.send(&error_response).ok();
+                return;
+            }
+        };
+        let Some(template) = extract_string_field(&data, "template") else {
+            tokio::spawn(async move {
+                let args = vec![
+                    "new".to_string(),
+                    sketch_path.to_string_lossy().to_string(),
+                    "--format".to_string(),
+                    "json".to_string()
+                ];
+                run_arduino_command_async("sketch", args, ack).await;
+            });
+            return;
+        };
+        // Placeholder values, e.g. {led_pin: "13"}. Numbers are accepted as well.
+        let placeholders = data
+            .get("placeholders")
+            .and_then(|values| values.as_object())
+            .map(|values| {
+                values
+                    .iter()
+                    .filter_map(|(name, value)| {
+                        let value = match value {
+                            Value::String(text) => text.clone(),
+                            Value::Number(number) => number.to_string(),
+                            Value::Bool(flag) => flag.to_string(),
+                            _ => {
+                                return None;
+                            }
+                        };
+                        Some((name.clone(), value))
+                    })
+                    .collect::<BTreeMap<_, _>>()
+            })
+            .unwrap_or_default();
+        let fqbn = extract_string_field(&data, "fqbn");
         tokio::spawn(async move {
-            let args = vec![
-                "new".to_string(),
-                sketch_path,
-                "--format".to_string(),
-                "json".to_string()
-            ];
-            run_arduino_command_async("sketch", args, ack).await;
+            let args = vec![sketch_name.clone(), template.clone()];
+            let response = match
+                create_sketch_from_template(&sketch_name, &template, fqbn.as_deref(), &placeholders)
+            {
+                Ok((sketch_path, files)) =>
+                    create_success_response(
+                        sketch_path.to_string_lossy().to_string(),
+                        "create-sketch",
+                        args,
+                        Some(
+                            serde_json::json!({
+                                "sketch_name": sketch_name,
+                                "template": template,
+                                "files": files
+                            })
+                        )
+                    ),
+                Err(error) => create_path_error_response(&error, "create-sketch", args),
+            };
+            ack.send(&response).ok();
+        });
+    });
+    // List the built-in and user sketch templates for create-sketch
+    socket.on("list-templates", |ack: AckSender| {
+        tokio::spawn(async move {
+            let templates = list_templates();
+            let response = create_success_response(
+                format!("{} templates", templates.len()),
+                "list-templates",
+                vec![],
+                Some(serde_json::to_value(templates).unwrap_or(Value::Null))
+            );
+            ack.send(&response).ok();
+        });
+    });
+    // List the example sketches of the installed libraries, grouped by library and core
+    socket.on("list-examples", |Data::<Value>(data), ack: AckSender| {
+        let fqbn = extract_string_field(&data, "fqbn");
+        tokio::spawn(async move {
+            let response = match list_examples(fqbn.as_deref()).await {
+                Ok(groups) =>
+                    create_success_response(
+                        "Examples listed successfully".to_string(),
+                        "list-examples",
+                        fqbn.into_iter().collect(),
+                        Some(serde_json::to_value(groups).unwrap_or(Value::Null))
+                    ),
+                Err(error_msg) => create_error_response(&error_msg, "list-examples", vec![]),
+            };
+            ack.send(&response).ok();
+        });
+    });
+    // Copy an example into the sketches directory as a new sketch,
+    // e.g. {library: "Servo", example: "Sweep", sketch_name: "MySweep"}
+    socket.on("create-sketch-from-example", |Data::<Value>(data), ack: AckSender| {
+        let fields = match extract_required_fields(&data, &["library", "example", "sketch_name"]) {
+            Ok(values) => values,
+            Err(error_msg) => {
+                let error_response = create_error_response(
+                    &error_msg,
+                    "create-sketch-from-example",
+                    vec![]
+                );
+                ack.send(&error_response).ok();
+                return;
+            }
+        };
+        if let Err(error) = resolve_sketch_dir(&fields[2]) {
+            let error_response = create_path_error_response(
+                &error,
+                "create-sketch-from-example",
+                fields
+            );
+            ack.send(&error_response).ok();
+            return;
+        }
+        let fqbn = extract_string_field(&data, "fqbn");
+        tokio::spawn(async move {
+            let example_path = match find_example(&fields[0], &fields[1], fqbn.as_deref()).await {
+                Ok(example_path) => example_path,
+                Err(error_msg) => {
+                    let error_response = create_error_response(
+                        &error_msg,
+                        "create-sketch-from-example",
+                        fields
+                    );
+                    ack.send(&error_response).ok();
+                    return;
+                }
+            };
+            let response = match create_sketch_from_example(&example_path, &fields[2]) {
+                Ok(sketch_path) =>
+                    create_success_response(
+                        sketch_path.to_string_lossy().to_string(),
+                        "create-sketch-from-example",
+                        fields.clone(),
+                        Some(serde_json::json!({ "sketch_name": fields[2] }))
+                    ),
+                Err(error) =>
+                    create_path_error_response(&error, "create-sketch-from-example", fields),
+            };
+            ack.send(&response).ok();
         });
     });
     socket.on("read-sketch-file", |Data::<Value>(data), ack: AckSender| {
         let fields = match extract_required_fields(&data, &["sketch_name", "file_name"]) {
             Ok(values) => values,
             Err(error_msg) => {
-                let error_response = create_error_response(&error_msg, "read-file", vec![]);
+                let error_response = create_error_response(&error_msg, "read-sketch-file", vec![]);
                 ack.send(&error_response).ok();
                 return;
             }
         };
 
-        let sketch_name = fields[0].clone();
-        let file_name = fields[1].clone();
-
         tokio::spawn(async move {
-            let sketch_dir = match get_sketch_directory() {
-                Ok(dir) => dir,
-                Err(error_msg) => {
-                    let error_response = create_error_response(&error_msg, "read-file", vec![]);
+            let sketch_path = match resolve_sketch_file(&fields[0], &fields[1]) {
+                Ok(path) => path,
+                Err(error) => {
+                    let error_response = create_path_error_response(
+                        &error,
+                        "read-sketch-file",
+                        fields
+                    );
                     ack.send(&error_response).ok();
                     return;
                 }
             };
-
-            let mut sketch_path = sketch_dir.join(&sketch_name);
-            sketch_path.push(&file_name);
             match read_file_content(&sketch_path).await {
                 Ok(content) => {
                     let success_response = create_success_response(
@@ -723,7 +1449,11 @@ fn register_arduino_handlers(socket: &SocketRef, port_address: Arc<Mutex<Option<
                     ack.send(&success_response).ok();
                 }
                 Err(error_msg) => {
-                    let error_response = create_error_response(&error_msg, "read-file", vec![]);
+                    let error_response = create_error_response(
+                        &error_msg,
+                        "read-sketch-file",
+                        fields
+                    );
                     ack.send(&error_response).ok();
                 }
             }
@@ -735,7 +1465,7 @@ fn register_arduino_handlers(socket: &SocketRef, port_address: Arc<Mutex<Option<
         {
             Ok(values) => values,
             Err(error_msg) => {
-                let error_response = create_error_response(&error_msg, "write-file", vec![]);
+                let error_response = create_error_response(&error_msg, "write-sketch-file", vec![]);
                 ack.send(&error_response).ok();
                 return;
             }
@@ -746,24 +1476,28 @@ fn register_arduino_handlers(socket: &SocketRef, port_address: Arc<Mutex<Option<
         let file_value = fields[2].clone();
 
         tokio::spawn(async move {
-            //get the sketch directory
-            let mut sketch_dir = match get_sketch_directory() {
-                Ok(dir) => dir,
-                Err(error_msg) => {
-                    let error_response = create_error_response(&error_msg, "write-file", vec![]);
+            let sketch_path = match resolve_sketch_file(&sketch_name, &file_name) {
+                Ok(path) => path,
+                Err(error) => {
+                    let error_response = create_path_error_response(
+                        &error,
+                        "write-sketch-file",
+                        vec![sketch_name, file_name]
+                    );
                     ack.send(&error_response).ok();
                     return;
                 }
             };
-            sketch_dir.push(&sketch_name);
-            sketch_dir.push(&file_name);
+            let snapshot_name = sketch_name.clone();
+            let _ = tokio::task
+                ::spawn_blocking(move || snapshot_before_write(&snapshot_name)).await;
             //write the file
-            match std::fs::write(&sketch_dir, file_value) {
+            match std::fs::write(&sketch_path, file_value) {
                 Ok(_) => {
                     let success_response = create_success_response(
-                        sketch_dir.to_string_lossy().to_string(),
+                        sketch_path.to_string_lossy().to_string(),
                         "write-sketch-file",
-                        vec![sketch_dir.to_string_lossy().to_string()],
+                        vec![sketch_path.to_string_lossy().to_string()],
                         None
                     );
                     ack.send(&success_response).ok();
@@ -771,8 +1505,8 @@ fn register_arduino_handlers(socket: &SocketRef, port_address: Arc<Mutex<Option<
                 Err(e) => {
                     let error_response = create_error_response(
                         &format!("Failed to write sketch file: {}", e),
-                        "write-file",
-                        vec![]
+                        "write-sketch-file",
+                        vec![sketch_name, file_name]
                     );
                     ack.send(&error_response).ok();
                 }
@@ -780,46 +1514,39 @@ fn register_arduino_handlers(socket: &SocketRef, port_address: Arc<Mutex<Option<
         });
     });
     socket.on("delete-sketch-file", |Data::<Value>(data), ack: AckSender| {
-        let sketch_name = match extract_string_field(&data, "sketch_name") {
-            Some(name) => name,
-            None => {
+        let fields = match extract_required_fields(&data, &["sketch_name", "file_name"]) {
+            Ok(values) => values,
+            Err(error_msg) => {
                 let error_response = create_error_response(
-                    "Missing sketch name",
-                    "delete=sketch-file",
+                    &error_msg,
+                    "delete-sketch-file",
                     vec![]
                 );
                 ack.send(&error_response).ok();
                 return;
             }
         };
-        let file_name = match extract_string_field(&data, "file_name") {
-            Some(name) => name,
-            None => {
-                let error_response = create_error_response(
-                    "Missing file name",
-                    "delete-file",
-                    vec![]
+        let sketch_path = match resolve_sketch_file(&fields[0], &fields[1]) {
+            Ok(path) => path,
+            Err(error) => {
+                let error_response = create_path_error_response(
+                    &error,
+                    "delete-sketch-file",
+                    fields
                 );
                 ack.send(&error_response).ok();
                 return;
             }
         };
-        let mut sketch_path = match get_sketch_directory() {
-            Ok(dir) => dir,
-            Err(error_msg) => {
-                let error_response = create_error_response(&error_msg, "delete-file", vec![]);
-                ack.send(&error_response).ok();
-                return;
-            }
-        };
-        sketch_path.push(&sketch_name);
-        sketch_path.push(&file_name);
         tokio::spawn(async move {
+            let snapshot_name = fields[0].clone();
+            let _ = tokio::task
+                ::spawn_blocking(move || snapshot_before_write(&snapshot_name)).await;
             match std::fs::remove_file(&sketch_path) {
                 Ok(_) => {
                     let success_response = create_success_response(
                         sketch_path.to_string_lossy().to_string(),
-                        "delete-file",
+                        "delete-sketch-file",
                         vec![sketch_path.to_string_lossy().to_string()],
                         None
                     );
@@ -828,69 +1555,49 @@ fn register_arduino_handlers(socket: &SocketRef, port_address: Arc<Mutex<Option<
                 Err(e) => {
                     let error_response = create_error_response(
                         &format!("Failed to delete sketch file: {}", e),
-                        "delete-file",
-                        vec![]
+                        "delete-sketch-file",
+                        fields
                     );
                     ack.send(&error_response).ok();
                 }
             }
         });
     });
-    socket.on("list-sketches", |ack: AckSender| {
-        let mut projects = vec![];
-        tokio::spawn(async move {
-            let sketch_dir = match get_sketch_directory() {
-                Ok(dir) => dir,
-                Err(error_msg) => {
+    // List the sketches with their files, board and last build,
+    // e.g. {sort: "modified", descending: true, search: "robot"}
+    socket.on("list-sketches", |Data::<Value>(data), ack: AckSender| {
+        let options = if data.is_object() {
+            match serde_json::from_value::<SketchListOptions>(data) {
+                Ok(options) => options,
+                Err(e) => {
                     let error_response = create_error_response(
-                        &format!("Failed to read sketches directory: {}", error_msg),
+                        &format!("Invalid list options: {}", e),
                         "list-sketches",
                         vec![]
                     );
                     ack.send(&error_response).ok();
                     return;
                 }
-            };
-            let sketches = match std::fs::read_dir(&sketch_dir) {
-                Ok(entries) => {
-                    entries
-                        .filter_map(|entry| {
-                            entry.ok().and_then(|e| {
-                                let path = e.path();
-                                if path.is_dir() {
-                                    Some(path.file_name()?.to_string_lossy().to_string())
-                                } else {
-                                    None
-                                }
-                            })
-                        })
-                        .collect::<Vec<_>>()
-                }
-                Err(error_msg) => {
-                    let error_response = create_error_response(
-                        &error_msg.to_string(),
+            }
+        } else {
+            SketchListOptions::default()
+        };
+        tokio::spawn(async move {
+            let response = match get_sketches_list(&options) {
+                Ok(sketches) =>
+                    create_success_response(
+                        "Sketches listed successfully".to_string(),
                         "list-sketches",
-                        vec![]
-                    );
-                    ack.send(&error_response).ok();
-                    return;
-                }
-            };
-            projects.extend(sketches);
-            let response = CommandResponse {
-                success: true,
-                output: "Sketches listed successfully".to_string(),
-                output_json: Some(serde_json::to_value(projects.clone()).unwrap_or(Value::Null)),
-                files: None,
-                error: None,
-                command: "list-sketches".to_string(),
-                args: vec![],
+                        vec![],
+                        Some(serde_json::to_value(sketches).unwrap_or(Value::Null))
+                    ),
+                Err(error_msg) => create_error_response(&error_msg, "list-sketches", vec![]),
             };
             ack.send(&response).ok();
         });
     });
-    socket.on("remove-sketch", |Data::<Value>(_data), ack: AckSender| {
-        let sketch_name = match extract_string_field(&_data, "sketch_name") {
+    socket.on("remove-sketch", |Data::<Value>(data), ack: AckSender| {
+        let sketch_name = match extract_string_field(&data, "sketch_name") {
             Some(name) => name,
             None => {
                 let error_response = create_error_response(
@@ -902,70 +1609,219 @@ fn register_arduino_handlers(socket: &SocketRef, port_address: Arc<Mutex<Option<
                 return;
             }
         };
-        let mut sketch_path = match get_sketch_directory() {
-            Ok(dir) => dir,
+        tokio::spawn(async move {
+            let name = sketch_name.clone();
+            let response = match run_blocking(move || remove_sketch(&name)).await {
+                Ok(sketch_path) => {
+                    let sketch_path = sketch_path.to_string_lossy().to_string();
+                    create_success_response(
+                        sketch_path.clone(),
+                        "remove-sketch",
+                        vec![sketch_path],
+                        None
+                    )
+                }
+                Err(error) =>
+                    create_path_error_response(&error, "remove-sketch", vec![sketch_name]),
+            };
+            ack.send(&response).ok();
+        });
+    });
+
+    // Rename a sketch, e.g. {sketch_name: "Blink", new_name: "Blinker"}
+    socket.on("rename-sketch", |Data::<Value>(data), ack: AckSender| {
+        let fields = match extract_required_fields(&data, &["sketch_name", "new_name"]) {
+            Ok(values) => values,
+            Err(error_msg) => {
+                let error_response = create_error_response(&error_msg, "rename-sketch", vec![]);
+                ack.send(&error_response).ok();
+                return;
+            }
+        };
+        tokio::spawn(async move {
+            let (from, to) = (fields[0].clone(), fields[1].clone());
+            let response = match run_blocking(move || rename_sketch(&from, &to)).await {
+                Ok(sketch_path) =>
+                    create_success_response(
+                        sketch_path.to_string_lossy().to_string(),
+                        "rename-sketch",
+                        fields.clone(),
+                        Some(serde_json::json!({ "sketch_name": fields[1] }))
+                    ),
+                Err(error) => create_path_error_response(&error, "rename-sketch", fields),
+            };
+            ack.send(&response).ok();
+        });
+    });
+    // Copy a sketch under a new name, e.g. {sketch_name: "Blink", new_name: "Blink2"}
+    socket.on("duplicate-sketch", |Data::<Value>(data), ack: AckSender| {
+        let fields = match extract_required_fields(&data, &["sketch_name", "new_name"]) {
+            Ok(values) => values,
             Err(error_msg) => {
-                let error_response = create_error_response(&error_msg, "remove-sketch", vec![]);
+                let error_response = create_error_response(&error_msg, "duplicate-sketch", vec![]);
                 ack.send(&error_response).ok();
                 return;
             }
         };
-        sketch_path.push(&sketch_name);
-
         tokio::spawn(async move {
-            if std::fs::remove_dir_all(&sketch_path).is_ok() {
-                let success_response = create_success_response(
-                    sketch_path.to_string_lossy().to_string(),
-                    "remove-sketch",
-                    vec![sketch_path.to_string_lossy().to_string()],
-                    None
-                );
-                ack.send(&success_response).ok();
-            } else {
+            let response = match duplicate_sketch(&fields[0], &fields[1]) {
+                Ok(sketch_path) =>
+                    create_success_response(
+                        sketch_path.to_string_lossy().to_string(),
+                        "duplicate-sketch",
+                        fields.clone(),
+                        Some(serde_json::json!({ "sketch_name": fields[1] }))
+                    ),
+                Err(error) => create_path_error_response(&error, "duplicate-sketch", fields),
+            };
+            ack.send(&response).ok();
+        });
+    });
+    // Pack a sketch into a zip that can be downloaded from /exports/<sketch>.zip
+    socket.on("export-sketch", |Data::<Value>(data), ack: AckSender| {
+        let sketch_name = match extract_string_field(&data, "sketch_name") {
+            Some(name) => name,
+            None => {
                 let error_response = create_error_response(
-                    &format!("Failed to remove sketch: {}", sketch_name),
-                    "remove-sketch",
+                    "Missing sketch name",
+                    "export-sketch",
                     vec![]
                 );
                 ack.send(&error_response).ok();
+                return;
             }
+        };
+        let include_sketch_yaml = data
+            .get("include_sketch_yaml")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(true);
+        let include_libraries = data
+            .get("include_libraries")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(true);
+        tokio::spawn(async move {
+            let response = match
+                export_sketch(&sketch_name, include_sketch_yaml, include_libraries).await
+            {
+                Ok((archive_path, manifest)) => {
+                    let file_name = format!("{}.zip", sketch_name);
+                    create_success_response(
+                        archive_path.to_string_lossy().to_string(),
+                        "export-sketch",
+                        vec![sketch_name],
+                        Some(
+                            serde_json::json!({
+                                "file_name": file_name,
+                                "download_url": format!("/exports/{}", file_name),
+                                "manifest": manifest,
+                            })
+                        )
+                    )
+                }
+                Err(error) =>
+                    create_path_error_response(&error, "export-sketch", vec![sketch_name]),
+            };
+            ack.send(&response).ok();
         });
     });
-
-    //lists files inisde a sketch
-    socket.on("list-sketch-files", |Data::<Value>(data), ack: AckSender| {
+    // The snapshots of a sketch, newest first
+    socket.on("list-snapshots", |Data::<Value>(data), ack: AckSender| {
         let sketch_name = match extract_string_field(&data, "sketch_name") {
             Some(name) => name,
             None => {
                 let error_response = create_error_response(
                     "Missing sketch name",
-                    "list-sketch-files",
+                    "list-snapshots",
                     vec![]
                 );
                 ack.send(&error_response).ok();
                 return;
             }
         };
-        let mut sketch_path = match get_sketch_directory() {
-            Ok(dir) => dir,
+        tokio::spawn(async move {
+            let name = sketch_name.clone();
+            let response = match run_blocking(move || list_snapshots(&name)).await {
+                Ok(snapshots) =>
+                    create_success_response(
+                        format!("{} snapshots", snapshots.len()),
+                        "list-snapshots",
+                        vec![sketch_name],
+                        Some(serde_json::to_value(snapshots).unwrap_or(Value::Null))
+                    ),
+                Err(error) =>
+                    create_path_error_response(&error, "list-snapshots", vec![sketch_name]),
+            };
+            ack.send(&response).ok();
+        });
+    });
+    // Unified diffs from a snapshot to the current files, or to the snapshot `against`
+    socket.on("diff-snapshot", |Data::<Value>(data), ack: AckSender| {
+        let fields = match extract_required_fields(&data, &["sketch_name", "snapshot_id"]) {
+            Ok(values) => values,
             Err(error_msg) => {
-                let error_response = create_error_response(&error_msg, "list-sketch-files", vec![]);
+                let error_response = create_error_response(&error_msg, "diff-snapshot", vec![]);
+                ack.send(&error_response).ok();
+                return;
+            }
+        };
+        let against = extract_string_field(&data, "against");
+        tokio::spawn(async move {
+            let (name, snapshot_id) = (fields[0].clone(), fields[1].clone());
+            let diff = run_blocking(move || diff_snapshot(&name, &snapshot_id, against.as_deref()));
+            let response = match diff.await {
+                Ok(diffs) =>
+                    create_success_response(
+                        format!("{} files changed", diffs.len()),
+                        "diff-snapshot",
+                        fields,
+                        Some(serde_json::to_value(diffs).unwrap_or(Value::Null))
+                    ),
+                Err(error) => create_path_error_response(&error, "diff-snapshot", fields),
+            };
+            ack.send(&response).ok();
+        });
+    });
+    // Put a sketch's files back as they were in a snapshot
+    socket.on("restore-snapshot", |Data::<Value>(data), ack: AckSender| {
+        let fields = match extract_required_fields(&data, &["sketch_name", "snapshot_id"]) {
+            Ok(values) => values,
+            Err(error_msg) => {
+                let error_response = create_error_response(&error_msg, "restore-snapshot", vec![]);
+                ack.send(&error_response).ok();
+                return;
+            }
+        };
+        tokio::spawn(async move {
+            let (name, snapshot_id) = (fields[0].clone(), fields[1].clone());
+            let response = match run_blocking(move || restore_snapshot(&name, &snapshot_id)).await {
+                Ok(snapshot) =>
+                    create_success_response(
+                        format!("Restored snapshot {}", snapshot.id),
+                        "restore-snapshot",
+                        fields,
+                        Some(serde_json::to_value(snapshot).unwrap_or(Value::Null))
+                    ),
+                Err(error) => create_path_error_response(&error, "restore-snapshot", fields),
+            };
+            ack.send(&response).ok();
+        });
+    });
+    //lists files inisde a sketch
+    socket.on("list-sketch-files", |Data::<Value>(data), ack: AckSender| {
+        let sketch_name = match extract_string_field(&data, "sketch_name") {
+            Some(name) => name,
+            None => {
+                let error_response = create_error_response(
+                    "Missing sketch name",
+                    "list-sketch-files",
+                    vec![]
+                );
                 ack.send(&error_response).ok();
                 return;
             }
         };
-        sketch_path.push(&sketch_name);
-        if !sketch_path.exists() {
-            let error_response = create_error_response(
-                &format!("Sketch {} does not exist", sketch_name),
-                "list-sketch-files",
-                vec![]
-            );
-            ack.send(&error_response).ok();
-            return;
-        }
         tokio::spawn(async move {
-            match list_directory_recursive(&sketch_path.to_string_lossy()) {
+            match list_sketch_files(&sketch_name) {
                 Ok(file_list) => {
                     let response = CommandResponse {
                         success: true,
@@ -974,15 +1830,17 @@ fn register_arduino_handlers(socket: &SocketRef, port_address: Arc<Mutex<Option<
                         files: Some(file_list),
                         error: None,
                         command: "list-sketch-files".to_string(),
-                        args: vec![sketch_path.to_string_lossy().to_string()],
+                        args: vec![sketch_name],
+                        job_id: None,
+                        error_code: None,
                     };
                     ack.send(&response).ok();
                 }
-                Err(e) => {
-                    let error_response = create_error_response(
-                        &format!("Error listing sketch files: {}", e),
+                Err(error) => {
+                    let error_response = create_path_error_response(
+                        &error,
                         "list-sketch-files",
-                        vec![]
+                        vec![sketch_name]
                     );
                     ack.send(&error_response).ok();
                 }
@@ -990,7 +1848,7 @@ fn register_arduino_handlers(socket: &SocketRef, port_address: Arc<Mutex<Option<
         });
     });
     // Compile a sketch
-    socket.on("compile-sketch", |Data::<Value>(data), ack: AckSender| {
+    socket.on("compile-sketch", |socket: SocketRef, Data::<Value>(data), ack: AckSender| {
         let sketch_name = match extract_string_field(&data, "sketch_name") {
             Some(name) => name,
             None => {
@@ -1003,275 +1861,1266 @@ fn register_arduino_handlers(socket: &SocketRef, port_address: Arc<Mutex<Option<
                 return;
             }
         };
-        let mut sketch_path = match get_sketch_directory() {
-            Ok(dir) => dir,
-            Err(error_msg) => {
-                let error_response = create_error_response(&error_msg, "compile", vec![]);
+        let sketch_path = match resolve_sketch_file(&sketch_name, &format!("{}.ino", sketch_name)) {
+            Ok(path) => path,
+            Err(error) => {
+                let error_response = create_path_error_response(&error, "compile", vec![]);
                 ack.send(&error_response).ok();
                 return;
             }
         };
-        sketch_path.push(&sketch_name);
-        sketch_path.push(format!("{}.ino", sketch_name));
-
-        let mut args = vec![];
 
-        // Add FQBN if provided
-        if let Some(fqbn) = extract_string_field(&data, "fqbn") {
-            args.push("--fqbn".to_string());
-            args.push(fqbn);
+        let options = match data.get("options") {
+            Some(options) =>
+                match serde_json::from_value::<CompileOptions>(options.clone()) {
+                    Ok(options) => options,
+                    Err(e) => {
+                        let error_response = create_error_response(
+                            &format!("Invalid compile options: {}", e),
+                            "compile",
+                            vec![]
+                        );
+                        ack.send(&error_response).ok();
+                        return;
+                    }
+                }
+            None => CompileOptions::default(),
+        };
+        // Board options may be given next to the fqbn or in the compile options
+        let mut board_options = options.board_options.clone();
+        match extract_board_options(&data) {
+            Ok(selected) => board_options.extend(selected),
+            Err(error_msg) => {
+                let error_response = create_error_response(&error_msg, "compile", vec![]);
+                ack.send(&error_response).ok();
+                return;
+            }
         }
-
-        args.push(format!("sketches/{}", sketch_name));
-        args.extend(vec!["--log".to_string(), "--log-file".to_string(), "log.txt".to_string()]);
-
-        tokio::spawn(async move {
-            run_arduino_command_async("compile", args, ack).await;
+        // A sketch.yaml profile pins the core and library versions of the build
+        let profile = match extract_string_field(&data, "profile") {
+            Some(profile_name) =>
+                match get_profile(&sketch_name, &profile_name) {
+                    Ok(profile) => Some((profile_name, profile)),
+                    Err(error) => {
+                        let error_response = create_path_error_response(&error, "compile", vec![]);
+                        ack.send(&error_response).ok();
+                        return;
+                    }
+                }
+            None => None,
+        };
+        let requested_fqbn = extract_string_field(&data, "fqbn").or_else(|| {
+            // Board options are applied on top of the profile's board
+            profile
+                .as_ref()
+                .filter(|_| !board_options.is_empty())
+                .map(|(_, profile)| profile.fqbn.clone())
         });
-    });
-    // Upload a sketch
-    socket.on("upload-sketch", |Data::<Value>(data), ack: AckSender| {
-        let fields = match extract_required_fields(&data, &["sketch_name", "port", "fqbn"]) {
-            Ok(values) => values,
-            Err(error_msg) => {
-                let error_response = create_error_response(&error_msg, "upload", vec![]);
+        let fqbn = match (requested_fqbn, board_options.is_empty()) {
+            (Some(fqbn), _) =>
+                match build_fqbn(&fqbn, &board_options) {
+                    Ok(fqbn) => Some(fqbn),
+                    Err(error_msg) => {
+                        let error_response = create_error_response(&error_msg, "compile", vec![]);
+                        ack.send(&error_response).ok();
+                        return;
+                    }
+                }
+            (None, true) => None,
+            (None, false) => {
+                let error_response = create_error_response(
+                    "Board options need an fqbn",
+                    "compile",
+                    vec![]
+                );
                 ack.send(&error_response).ok();
                 return;
             }
         };
+        let build_path = get_build_path(&sketch_name);
+        let output_path = get_output_path(&sketch_name);
 
-        let mut sketch_path = match get_sketch_directory() {
-            Ok(dir) => dir,
+        let mut args = match compile_args(fqbn.as_deref(), &options, &build_path, &output_path) {
+            Ok(args) => args,
             Err(error_msg) => {
-                let error_response = create_error_response(&error_msg, "upload", vec![]);
+                let error_response = create_error_response(&error_msg, "compile", vec![]);
                 ack.send(&error_response).ok();
                 return;
             }
         };
-        sketch_path.push(&fields[0]);
-        sketch_path.push(format!("{}.ino", fields[0]));
-        let port = fields[1].clone();
-        let fqbn = fields[2].clone();
+        if let Some((profile_name, _)) = &profile {
+            args.push("--profile".to_string());
+            args.push(profile_name.clone());
+        }
+        args.push(sketch_path.to_string_lossy().to_string());
 
-        let args = vec![
-            "--port".to_string(),
-            port,
-            "--fqbn".to_string(),
-            fqbn,
-            sketch_path.to_string_lossy().to_string(),
-            "--log".to_string(),
-            "--log-file".to_string(),
-            "log.txt".to_string()
-        ];
+        // Record the board the build is for, which a profile may provide
+        let profile_name = profile.as_ref().map(|(profile_name, _)| profile_name.clone());
+        let fqbn = fqbn.or(profile.map(|(_, profile)| profile.fqbn));
+        let finish_sketch_name = sketch_name.clone();
+        let build_properties = options.build_properties.clone();
+        let request = JobRequest {
+            job_id: extract_job_id(&data),
+            kind: "compile-sketch".to_string(),
+            sketch: Some(sketch_name),
+            port: None,
+            command: ArduinoCommand {
+                command: "compile".to_string(),
+                args,
+            },
+            cleanup: vec![JobCleanup::RemoveDir(build_path)],
+            check: None,
+            prepare: Some(Box::new(move || reset_dir(&output_path))),
+            finish: Some(
+                Box::new(move |response| {
+                    let response = finish_compile(
+                        &finish_sketch_name,
+                        fqbn,
+                        profile_name,
+                        build_properties,
+                        response
+                    );
+                    if response.success {
+                        tokio::task::spawn_blocking(move || {
+                            if let Err(e) = take_snapshot(&finish_sketch_name, "compiled") {
+                                error!("Failed to snapshot {}: {}", finish_sketch_name, e);
+                            }
+                        });
+                    }
+                    response
+                })
+            ),
+        };
 
         tokio::spawn(async move {
-            run_arduino_command_async("upload", args, ack).await;
+            run_arduino_job(socket, request, ack).await;
         });
     });
-    //enable or disable unsafe library installation
-    socket.on("enable-unsafe-install", |Data::<Value>(data), ack: AckSender| {
-        let enable = match data.get("enable").and_then(|v| v.as_bool()) {
-            Some(value) => value,
+    // Check the in-editor content of a sketch for syntax errors, for live linting
+    socket.on("check-sketch", |Data::<Value>(data), ack: AckSender| {
+        let sketch_name = match extract_string_field(&data, "sketch_name") {
+            Some(name) => name,
             None => {
                 let error_response = create_error_response(
-                    "Missing enable flag",
-                    "enable-unsafe-install",
+                    "Missing sketch name",
+                    "check-sketch",
                     vec![]
                 );
                 ack.send(&error_response).ok();
                 return;
             }
         };
-        enable_unsafe_install(enable);
-        let response = CommandResponse {
-            success: true,
-            output: format!("Unsafe install is now {}", if enable {
-                "enabled"
-            } else {
-                "disabled"
-            }),
-            output_json: None,
-            files: None,
-            error: None,
-            command: "enable-unsafe-install".to_string(),
-            args: vec![enable.to_string()],
-        };
-        ack.send(&response).ok();
-    });
-    // libary commands
-    socket.on("list-libraries", |ack: AckSender| {
-        tokio::spawn(async move {
-            run_arduino_command_async(
-                "lib",
-                vec!["list".to_string(), "--format".to_string(), "json".to_string()],
-                ack
-            ).await;
+        if let Err(error) = existing_sketch_dir(&sketch_name) {
+            let error_response = create_path_error_response(
+                &error,
+                "check-sketch",
+                vec![sketch_name]
+            );
+            ack.send(&error_response).ok();
+            return;
+        }
+        let fqbn = extract_string_field(&data, "fqbn").or_else(|| {
+            read_sketch_yaml(&sketch_name)
+                .ok()
+                .and_then(|sketch_yaml| sketch_yaml.default_fqbn)
         });
-    });
-    // serach for a library
-    socket.on("search-library", |Data::<Value>(data), ack: AckSender| {
-        let library_name = match extract_string_field(&data, "library_name") {
-            Some(name) => name,
-            None => {
+        // Unsaved editor content by file name, e.g. {"MySketch.ino": "void setup() {..."}
+        let files = match
+            fqbn
+                .clone()
+                .ok_or_else(|| "Missing fqbn".to_string())
+                .and_then(|_| {
+                    serde_json
+                        ::from_value::<BTreeMap<String, String>>(
+                            data.get("files").cloned().unwrap_or(serde_json::json!({}))
+                        )
+                        .map_err(|e| format!("Invalid files: {}", e))
+                })
+                .and_then(|files| {
+                    match files.keys().find(|file_name| !is_plain_name(file_name)) {
+                        Some(file_name) => Err(format!("Invalid file name {}", file_name)),
+                        None => Ok(files),
+                    }
+                })
+        {
+            Ok(files) => files,
+            Err(error_msg) => {
                 let error_response = create_error_response(
-                    "Missing library name",
-                    "search-library",
-                    vec![]
+                    &error_msg,
+                    "check-sketch",
+                    vec![sketch_name]
                 );
                 ack.send(&error_response).ok();
                 return;
             }
         };
+        let fqbn = fqbn.unwrap_or_default();
+        let debounce = data
+            .get("debounce_ms")
+            .and_then(|v| v.as_u64())
+            .map(std::time::Duration::from_millis)
+            .unwrap_or(DEFAULT_DEBOUNCE);
+
         tokio::spawn(async move {
-            let args = vec![
-                "search".to_string(),
-                library_name,
-                "name".to_string(),
-                "--format".to_string(),
-                "json".to_string()
-            ];
-            run_arduino_command_async("lib", args, ack).await;
+            let response = match check_sketch(&sketch_name, &fqbn, files, debounce).await {
+                Ok(CheckResult::Checked(diagnostics)) => {
+                    let errors = diagnostics
+                        .iter()
+                        .filter(|diagnostic| diagnostic.severity == "error")
+                        .count();
+                    create_success_response(
+                        format!("{} errors", errors),
+                        "check-sketch",
+                        vec![sketch_name],
+                        Some(serde_json::json!({ "superseded": false, "diagnostics": diagnostics }))
+                    )
+                }
+                Ok(CheckResult::Superseded) =>
+                    CommandResponse {
+                        output_json: Some(serde_json::json!({ "superseded": true })),
+                        ..create_error_response(
+                            "Superseded by a newer check",
+                            "check-sketch",
+                            vec![sketch_name]
+                        )
+                    },
+                Err(error_msg) =>
+                    create_error_response(&error_msg, "check-sketch", vec![sketch_name]),
+            };
+            ack.send(&response).ok();
         });
     });
-    socket.on("install-library", |Data::<Value>(data), ack: AckSender| {
-        let library_name = match extract_string_field(&data, "library_name") {
+    // Generate the compile_commands.json of a sketch for editor code intelligence
+    socket.on("compilation-database", |socket: SocketRef, Data::<Value>(data), ack: AckSender| {
+        let sketch_name = match extract_string_field(&data, "sketch_name") {
             Some(name) => name,
             None => {
                 let error_response = create_error_response(
-                    "Missing library name",
-                    "install-library",
+                    "Missing sketch name",
+                    "compilation-database",
                     vec![]
                 );
                 ack.send(&error_response).ok();
                 return;
             }
         };
-        tokio::spawn(async move {
-            let mut args = vec![
-                "install".to_string()
-                // library_name will be added below
-            ];
-            if library_name.ends_with(".zip") {
-                args.push("--zip-path".to_string());
-                let lib_path = get_sketch_directory()
-                    .unwrap_or_else(|_| PathBuf::from("."))
-                    .join("libraries")
-                    .join(&library_name);
-                info!(?lib_path, "Library path to install");
-                args.push(lib_path.to_string_lossy().to_string());
-            } else {
-                args.push(library_name);
+        let sketch_dir = match existing_sketch_dir(&sketch_name) {
+            Ok(sketch_dir) => sketch_dir,
+            Err(error) => {
+                let error_response = create_path_error_response(
+                    &error,
+                    "compilation-database",
+                    vec![sketch_name]
+                );
+                ack.send(&error_response).ok();
+                return;
             }
-            args.extend(vec!["--log".to_string(), "--log-file".to_string(), "log.txt".to_string()]);
-            run_arduino_command_async("lib", args, ack).await;
+        };
+        // Fall back to the board the sketch was last uploaded to
+        let fqbn = extract_string_field(&data, "fqbn").or_else(|| {
+            read_sketch_yaml(&sketch_name)
+                .ok()
+                .and_then(|sketch_yaml| sketch_yaml.default_fqbn)
         });
-    });
-    socket.on("uninstall-library", |Data::<Value>(data), ack: AckSender| {
-        let library_name = match extract_string_field(&data, "library_name") {
-            Some(name) => name,
-            None => {
+        let fqbn = match
+            fqbn
+                .ok_or_else(|| "Missing fqbn".to_string())
+                .and_then(|fqbn| {
+                    extract_board_options(&data).and_then(|options| build_fqbn(&fqbn, &options))
+                })
+        {
+            Ok(fqbn) => fqbn,
+            Err(error_msg) => {
                 let error_response = create_error_response(
-                    "Missing library name",
-                    "uninstall-library",
-                    vec![]
+                    &error_msg,
+                    "compilation-database",
+                    vec![sketch_name]
                 );
                 ack.send(&error_response).ok();
                 return;
             }
         };
+        let job_id = extract_job_id(&data);
+
         tokio::spawn(async move {
+            let build_path = get_compilation_database_path(&sketch_name);
+            let fingerprint = compilation_database_fingerprint(&sketch_dir, &fqbn).await;
+            let cached = cached_compilation_database(&build_path, &sketch_name, &fingerprint);
+            if let Some(database) = cached {
+                let response = CommandResponse {
+                    job_id: Some(job_id),
+                    ..create_success_response(
+                        "Compilation database is up to date".to_string(),
+                        "compilation-database",
+                        vec![sketch_name],
+                        Some(database)
+                    )
+                };
+                ack.send(&response).ok();
+                return;
+            }
+
+            let prepare_path = build_path.clone();
+            let finish_path = build_path.clone();
             let args = vec![
-                "uninstall".to_string(),
-                library_name,
-                "--log".to_string(),
-                "--log-file".to_string(),
-                "log.txt".to_string()
+                "--fqbn".to_string(),
+                fqbn,
+                "--only-compilation-database".to_string(),
+                "--build-path".to_string(),
+                build_path.to_string_lossy().to_string(),
+                sketch_dir.to_string_lossy().to_string()
             ];
-            run_arduino_command_async("lib", args, ack).await;
+            let finish_sketch_name = sketch_name.clone();
+            let request = JobRequest {
+                job_id,
+                kind: "compilation-database".to_string(),
+                sketch: Some(sketch_name),
+                port: None,
+                command: ArduinoCommand {
+                    command: "compile".to_string(),
+                    args,
+                },
+                cleanup: vec![JobCleanup::RemoveDir(build_path)],
+                check: None,
+                // A failed or cancelled run must not leave a stale database marked as current
+                prepare: Some(Box::new(move || clear_fingerprint(&prepare_path))),
+                finish: Some(
+                    Box::new(move |response| {
+                        if !response.success {
+                            return response;
+                        }
+                        match read_compilation_database(&finish_path, &finish_sketch_name, false) {
+                            Ok(database) => {
+                                store_fingerprint(&finish_path, &fingerprint);
+                                CommandResponse {
+                                    output_json: Some(database),
+                                    ..response
+                                }
+                            }
+                            Err(error_msg) =>
+                                CommandResponse {
+                                    success: false,
+                                    error: Some(error_msg),
+                                    ..response
+                                },
+                        }
+                    })
+                ),
+            };
+            run_arduino_job(socket, request, ack).await;
         });
     });
-}
-
-fn start_log_monitoring(socket: SocketRef) {
-    tokio::spawn(async move {
-        let log_file_path = "log.txt";
+    // Upload a sketch
+    socket.on("upload-sketch", |socket: SocketRef, Data::<Value>(data), ack: AckSender| {
+        let fields = match extract_required_fields(&data, &["sketch_name", "port"]) {
+            Ok(values) => values,
+            Err(error_msg) => {
+                let error_response = create_error_response(&error_msg, "upload", vec![]);
+                ack.send(&error_response).ok();
+                return;
+            }
+        };
 
-        // Create the log file if it doesn't exist
-        if !std::path::Path::new(log_file_path).exists() {
-            if let Err(e) = std::fs::write(log_file_path, "") {
-                error!(?e, "Failed to create log file");
+        let sketch_name = fields[0].clone();
+        let port = fields[1].clone();
+        let sketch_path = match resolve_sketch_file(&sketch_name, &format!("{}.ino", sketch_name)) {
+            Ok(path) => path,
+            Err(error) => {
+                let error_response = create_path_error_response(&error, "upload", vec![]);
+                ack.send(&error_response).ok();
                 return;
             }
+        };
+        let profile_name = extract_string_field(&data, "profile");
+        let mut requested_fqbn = extract_string_field(&data, "fqbn");
+        if let Some(profile_name) = &profile_name {
+            match get_profile(&sketch_name, profile_name) {
+                Ok(profile) => {
+                    requested_fqbn = requested_fqbn.or(Some(profile.fqbn));
+                }
+                Err(error) => {
+                    let error_response = create_path_error_response(&error, "upload", vec![]);
+                    ack.send(&error_response).ok();
+                    return;
+                }
+            }
         }
+        let board_options = match extract_board_options(&data) {
+            Ok(board_options) => board_options,
+            Err(error_msg) => {
+                let error_response = create_error_response(&error_msg, "upload", vec![]);
+                ack.send(&error_response).ok();
+                return;
+            }
+        };
+        // true flashes the last compile-sketch build, false always builds the sketch.
+        // By default the last build is flashed only if it is current for this board.
+        let use_last_build = data.get("use_last_build").and_then(|v| v.as_bool());
+        // Overwrite the board and port already saved in sketch.yaml after the upload
+        let overwrite_board = data
+            .get("remember_board")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+        let job_id = extract_job_id(&data);
 
-        info!("Started automatic log monitoring for file: {}", log_file_path);
-
-        // Start file monitoring
-        start_log_file_monitoring(socket, log_file_path.to_string()).await;
-    });
-}
-
-async fn start_log_file_monitoring(socket: SocketRef, log_file_path: String) {
-    use std::time::Duration;
-    use tokio::time::sleep;
-
-    let mut last_position = 0;
-    let path = Path::new(&log_file_path);
-
-    loop {
-        if path.exists() {
-            match fs::metadata(&path) {
-                Ok(metadata) => {
-                    let current_size = metadata.len();
-                    if current_size > last_position {
-                        // Read new content
-                        match fs::read_to_string(&path) {
-                            Ok(content) => {
-                                let new_content = if last_position > 0 {
-                                    content
-                                        .chars()
-                                        .skip(last_position as usize)
-                                        .collect::<String>()
-                                } else {
-                                    content
-                                };
+        tokio::spawn(async move {
+            let fqbn = match
+                resolve_upload_fqbn(requested_fqbn, &port, Some(&sketch_name), "upload").await
+            {
+                Ok(fqbn) => fqbn,
+                Err(error_response) => {
+                    ack.send(&error_response).ok();
+                    return;
+                }
+            };
+            let fqbn = match build_fqbn(&fqbn, &board_options) {
+                Ok(fqbn) => fqbn,
+                Err(error_msg) => {
+                    let error_response = create_error_response(&error_msg, "upload", vec![]);
+                    ack.send(&error_response).ok();
+                    return;
+                }
+            };
 
-                                if !new_content.is_empty() {
-                                    // Try to parse as JSON lines
-                                    let lines: Vec<&str> = new_content.lines().collect();
-                                    for line in lines {
-                                        if !line.trim().is_empty() {
-                                            let log_data = match
-                                                serde_json::from_str::<Value>(line)
-                                            {
-                                                Ok(json) => json,
-                                                Err(_) =>
-                                                    serde_json::json!({
-                                                    "message": line,
-                                                    "timestamp": chrono::Utc::now().to_rfc3339()
-                                                }),
-                                            };
+            let use_last_build = match use_last_build {
+                Some(true) => {
+                    let built = read_build_info(&sketch_name).is_some_and(|info| info.success);
+                    if !built || !get_output_path(&sketch_name).is_dir() {
+                        let error_response = create_error_response(
+                            &format!("Sketch {} has no successful build to upload", sketch_name),
+                            "upload",
+                            vec![]
+                        );
+                        ack.send(&error_response).ok();
+                        return;
+                    }
+                    true
+                }
+                Some(false) => false,
+                None => is_build_current(&sketch_name, &fqbn, profile_name.as_deref()),
+            };
 
-                                            socket.emit("logs", &log_data).ok();
-                                        }
-                                    }
-                                }
-                                last_position = current_size;
-                            }
-                            Err(e) => {
-                                info!("Error reading log file: {}", e);
+            let mut args = vec![
+                "--port".to_string(),
+                port.clone(),
+                "--fqbn".to_string(),
+                fqbn.clone(),
+                sketch_path.to_string_lossy().to_string()
+            ];
+            if let Some(profile_name) = profile_name {
+                args.push("--profile".to_string());
+                args.push(profile_name);
+            }
+            if use_last_build {
+                args.push("--input-dir".to_string());
+                args.push(get_output_path(&sketch_name).to_string_lossy().to_string());
+            }
+            let finish_sketch_name = sketch_name.clone();
+            let finish_port = port.clone();
+            let request = JobRequest {
+                job_id,
+                kind: "upload-sketch".to_string(),
+                sketch: Some(sketch_name),
+                port: Some(port),
+                command: ArduinoCommand {
+                    command: "upload".to_string(),
+                    args,
+                },
+                cleanup: vec![],
+                check: None,
+                prepare: None,
+                finish: Some(
+                    Box::new(move |response| {
+                        // Remember the board so the next upload does not need an fqbn
+                        if response.success {
+                            if
+                                let Err(e) = remember_board(
+                                    &finish_sketch_name,
+                                    &fqbn,
+                                    &finish_port,
+                                    overwrite_board
+                                )
+                            {
+                                error!("Failed to remember board: {}", e);
                             }
+                            tokio::task::spawn_blocking(move || {
+                                if let Err(e) = take_snapshot(&finish_sketch_name, "uploaded") {
+                                    error!("Failed to snapshot {}: {}", finish_sketch_name, e);
+                                }
+                            });
                         }
-                    }
+                        response
+                    })
+                ),
+            };
+            run_arduino_job(socket, request, ack).await;
+        });
+    });
+    // List the sketch.yaml profiles of a sketch
+    socket.on("list-profiles", |Data::<Value>(data), ack: AckSender| {
+        let sketch_name = match extract_string_field(&data, "sketch_name") {
+            Some(name) => name,
+            None => {
+                let error_response = create_error_response(
+                    "Missing sketch name",
+                    "list-profiles",
+                    vec![]
+                );
+                ack.send(&error_response).ok();
+                return;
+            }
+        };
+        let response = match read_sketch_yaml(&sketch_name) {
+            Ok(sketch_yaml) =>
+                create_success_response(
+                    format!("{} profiles", sketch_yaml.profiles.len()),
+                    "list-profiles",
+                    vec![sketch_name],
+                    Some(
+                        serde_json::json!({
+                            "default_profile": sketch_yaml.default_profile,
+                            "profiles": sketch_yaml.profiles,
+                        })
+                    )
+                ),
+            Err(error) => create_path_error_response(&error, "list-profiles", vec![sketch_name]),
+        };
+        ack.send(&response).ok();
+    });
+    // Create or replace a profile, e.g.
+    // {sketch_name, profile_name, profile: {fqbn, platforms, libraries}, default: true}
+    socket.on("set-profile", |Data::<Value>(data), ack: AckSender| {
+        let fields = match extract_required_fields(&data, &["sketch_name", "profile_name"]) {
+            Ok(values) => values,
+            Err(error_msg) => {
+                let error_response = create_error_response(&error_msg, "set-profile", vec![]);
+                ack.send(&error_response).ok();
+                return;
+            }
+        };
+        let profile = match
+            data
+                .get("profile")
+                .cloned()
+                .ok_or_else(|| "Missing profile".to_string())
+                .and_then(|profile| {
+                    serde_json
+                        ::from_value::<SketchProfile>(profile)
+                        .map_err(|e| format!("Invalid profile: {}", e))
+                })
+        {
+            Ok(profile) => profile,
+            Err(error_msg) => {
+                let error_response = create_error_response(&error_msg, "set-profile", fields);
+                ack.send(&error_response).ok();
+                return;
+            }
+        };
+        let make_default = data
+            .get("default")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+        let response = match set_profile(&fields[0], &fields[1], profile, make_default) {
+            Ok(sketch_yaml) =>
+                create_success_response(
+                    format!("Profile {} saved", fields[1]),
+                    "set-profile",
+                    fields,
+                    Some(serde_json::to_value(sketch_yaml.profiles).unwrap_or(Value::Null))
+                ),
+            Err(error) => create_path_error_response(&error, "set-profile", fields),
+        };
+        ack.send(&response).ok();
+    });
+    // Delete a profile of a sketch
+    socket.on("delete-profile", |Data::<Value>(data), ack: AckSender| {
+        let fields = match extract_required_fields(&data, &["sketch_name", "profile_name"]) {
+            Ok(values) => values,
+            Err(error_msg) => {
+                let error_response = create_error_response(&error_msg, "delete-profile", vec![]);
+                ack.send(&error_response).ok();
+                return;
+            }
+        };
+        let response = match delete_profile(&fields[0], &fields[1]) {
+            Ok(_) =>
+                create_success_response(
+                    format!("Profile {} deleted", fields[1]),
+                    "delete-profile",
+                    fields,
+                    None
+                ),
+            Err(error) => create_path_error_response(&error, "delete-profile", fields),
+        };
+        ack.send(&response).ok();
+    });
+    // Select the profile used when compile/upload get none, or clear it with a null profile_name
+    socket.on("set-default-profile", |Data::<Value>(data), ack: AckSender| {
+        let sketch_name = match extract_string_field(&data, "sketch_name") {
+            Some(name) => name,
+            None => {
+                let error_response = create_error_response(
+                    "Missing sketch name",
+                    "set-default-profile",
+                    vec![]
+                );
+                ack.send(&error_response).ok();
+                return;
+            }
+        };
+        let profile_name = extract_string_field(&data, "profile_name");
+        let response = match set_default_profile(&sketch_name, profile_name.as_deref()) {
+            Ok(sketch_yaml) =>
+                create_success_response(
+                    format!(
+                        "Default profile: {}",
+                        sketch_yaml.default_profile.as_deref().unwrap_or("none")
+                    ),
+                    "set-default-profile",
+                    vec![sketch_name],
+                    Some(serde_json::json!({ "default_profile": sketch_yaml.default_profile }))
+                ),
+            Err(error) =>
+                create_path_error_response(&error, "set-default-profile", vec![sketch_name]),
+        };
+        ack.send(&response).ok();
+    });
+    // Get the menu options, programmers and required tools of a board
+    socket.on("board-details", |Data::<Value>(data), ack: AckSender| {
+        let fqbn = match extract_string_field(&data, "fqbn") {
+            Some(fqbn) => fqbn,
+            None => {
+                let error_response = create_error_response("Missing fqbn", "board-details", vec![]);
+                ack.send(&error_response).ok();
+                return;
+            }
+        };
+        tokio::spawn(async move {
+            let response = match get_board_details(&fqbn).await {
+                Ok(details) =>
+                    create_success_response(
+                        details.name.clone(),
+                        "board-details",
+                        vec![fqbn],
+                        Some(serde_json::to_value(details).unwrap_or(Value::Null))
+                    ),
+                Err(error_msg) => create_error_response(&error_msg, "board-details", vec![fqbn]),
+            };
+            ack.send(&response).ok();
+        });
+    });
+    // Detect which board is connected to a port
+    socket.on("detect-board", |Data::<Value>(data), ack: AckSender| {
+        let port = match extract_string_field(&data, "port") {
+            Some(port) => port,
+            None => {
+                let error_response = create_error_response("Missing port", "detect-board", vec![]);
+                ack.send(&error_response).ok();
+                return;
+            }
+        };
+        let sketch_name = extract_string_field(&data, "sketch_name");
+        tokio::spawn(async move {
+            let default_fqbn = sketch_name
+                .and_then(|name| read_sketch_yaml(&name).ok())
+                .and_then(|sketch_yaml| sketch_yaml.default_fqbn);
+            // Several matching boards are not an error, the UI lets the user pick one
+            let response = match resolve_fqbn(&port, default_fqbn.as_deref()).await {
+                FqbnResolution::Resolved(fqbn) =>
+                    create_success_response(
+                        fqbn.clone(),
+                        "detect-board",
+                        vec![port],
+                        Some(
+                            serde_json::json!({
+                                "fqbn": fqbn,
+                                "ambiguous": false,
+                                "candidates": []
+                            })
+                        )
+                    ),
+                FqbnResolution::Ambiguous(candidates) =>
+                    create_success_response(
+                        format!("Several boards match port {}", port),
+                        "detect-board",
+                        vec![port],
+                        Some(
+                            serde_json::json!({
+                                "fqbn": null,
+                                "ambiguous": true,
+                                "candidates": candidates
+                            })
+                        )
+                    ),
+                FqbnResolution::NotFound =>
+                    create_error_response(
+                        &format!("Could not detect the board on port {}", port),
+                        "detect-board",
+                        vec![port]
+                    ),
+            };
+            ack.send(&response).ok();
+        });
+    });
+    // List the USB ids used to recognise Plode boards
+    socket.on("list-board-ids", |ack: AckSender| {
+        let board_ids = read_board_ids();
+        let response = create_success_response(
+            format!("{} board ids", board_ids.len()),
+            "list-board-ids",
+            vec![],
+            Some(serde_json::to_value(board_ids).unwrap_or(Value::Null))
+        );
+        ack.send(&response).ok();
+    });
+    // Add a USB id to recognise a board by, e.g. {vid: "0xB1B0", pid: "0x0001", fqbn, name}
+    socket.on("add-board-id", |Data::<Value>(data), ack: AckSender| {
+        let board_id = match serde_json::from_value::<BoardId>(data) {
+            Ok(board_id) => board_id,
+            Err(e) => {
+                let error_response = create_error_response(
+                    &format!("Invalid board id: {}", e),
+                    "add-board-id",
+                    vec![]
+                );
+                ack.send(&error_response).ok();
+                return;
+            }
+        };
+        let response = match add_board_id(board_id) {
+            Ok(board_ids) =>
+                create_success_response(
+                    "Board id added".to_string(),
+                    "add-board-id",
+                    vec![],
+                    Some(serde_json::to_value(board_ids).unwrap_or(Value::Null))
+                ),
+            Err(error_msg) => create_error_response(&error_msg, "add-board-id", vec![]),
+        };
+        ack.send(&response).ok();
+    });
+    // Upload a previously built binary without compiling, either the stored build
+    // of a sketch (`sketch_name`) or a binary uploaded over HTTP (`binary`)
+    socket.on("upload-binary", |socket: SocketRef, Data::<Value>(data), ack: AckSender| {
+        let port = match extract_string_field(&data, "port") {
+            Some(port) => port,
+            None => {
+                let error_response = create_error_response("Missing port", "upload-binary", vec![]);
+                ack.send(&error_response).ok();
+                return;
+            }
+        };
+        let sketch_name = extract_string_field(&data, "sketch_name");
+        let binary = extract_string_field(&data, "binary");
+        let mut fqbn = extract_string_field(&data, "fqbn");
+
+        let input_args = match (&sketch_name, &binary) {
+            (Some(sketch_name), None) => {
+                // The name also names the build directory, so it must be a sketch
+                if let Err(error) = existing_sketch_dir(sketch_name) {
+                    let error_response = create_path_error_response(
+                        &error,
+                        "upload-binary",
+                        vec![sketch_name.clone()]
+                    );
+                    ack.send(&error_response).ok();
+                    return;
                 }
-                Err(e) => {
-                    info!("Error getting file metadata: {}", e);
+                let build_info = read_build_info(sketch_name).filter(|info| info.success);
+                let Some(build_info) = build_info else {
+                    let error_response = create_error_response(
+                        &format!("Sketch {} has no successful build to upload", sketch_name),
+                        "upload-binary",
+                        vec![]
+                    );
+                    ack.send(&error_response).ok();
+                    return;
+                };
+                // Flash the board the binaries were built for unless told otherwise
+                if fqbn.is_none() {
+                    fqbn = build_info.fqbn;
                 }
+                vec![
+                    "--input-dir".to_string(),
+                    get_output_path(sketch_name).to_string_lossy().to_string()
+                ]
             }
-        } else {
-            info!("Log file does not exist: {}", log_file_path);
-        }
+            (None, Some(binary)) => {
+                let binary_path = get_binaries_directory().join(binary);
+                if !is_plain_name(binary) || !binary_path.is_file() {
+                    let error_response = create_error_response(
+                        &format!("Binary {} not found", binary),
+                        "upload-binary",
+                        vec![]
+                    );
+                    ack.send(&error_response).ok();
+                    return;
+                }
+                vec!["--input-file".to_string(), binary_path.to_string_lossy().to_string()]
+            }
+            _ => {
+                let error_response = create_error_response(
+                    "Provide either sketch_name or binary",
+                    "upload-binary",
+                    vec![]
+                );
+                ack.send(&error_response).ok();
+                return;
+            }
+        };
+        let job_id = extract_job_id(&data);
 
-        sleep(Duration::from_millis(100)).await;
-    }
+        tokio::spawn(async move {
+            let fqbn = match
+                resolve_upload_fqbn(fqbn, &port, sketch_name.as_deref(), "upload-binary").await
+            {
+                Ok(fqbn) => fqbn,
+                Err(error_response) => {
+                    ack.send(&error_response).ok();
+                    return;
+                }
+            };
+
+            let mut args = vec!["--port".to_string(), port.clone(), "--fqbn".to_string(), fqbn];
+            args.extend(input_args);
+            let request = JobRequest {
+                job_id,
+                kind: "upload-binary".to_string(),
+                sketch: sketch_name,
+                port: Some(port),
+                command: ArduinoCommand {
+                    command: "upload".to_string(),
+                    args,
+                },
+                cleanup: vec![],
+                check: None,
+                prepare: None,
+                finish: None,
+            };
+            run_arduino_job(socket, request, ack).await;
+        });
+    });
+    //enable or disable unsafe library installation
+    socket.on("enable-unsafe-install", |Data::<Value>(data), ack: AckSender| {
+        let enable = match data.get("enable").and_then(|v| v.as_bool()) {
+            Some(value) => value,
+            None => {
+                let error_response = create_error_response(
+                    "Missing enable flag",
+                    "enable-unsafe-install",
+                    vec![]
+                );
+                ack.send(&error_response).ok();
+                return;
+            }
+        };
+        tokio::spawn(async move {
+            let response = match
+                set_setting("library.enable_unsafe_install", Value::Bool(enable)).await
+            {
+                Ok(()) =>
+                    CommandResponse {
+                        success: true,
+                        output: format!("Unsafe install is now {}", if enable {
+                            "enabled"
+                        } else {
+                            "disabled"
+                        }),
+                        output_json: None,
+                        files: None,
+                        error: None,
+                        command: "enable-unsafe-install".to_string(),
+                        args: vec![enable.to_string()],
+                        job_id: None,
+                        error_code: None,
+                    },
+                Err(error_msg) =>
+                    create_error_response(
+                        &error_msg,
+                        "enable-unsafe-install",
+                        vec![enable.to_string()]
+                    ),
+            };
+            ack.send(&response).ok();
+        });
+    });
+    // Find the Arduino IDE sketchbook and what importing it would bring in
+    socket.on("find-arduino-ide", |ack: AckSender| {
+        let description = describe_ide_import();
+        let response = create_success_response(
+            match description.get("sketchbook").and_then(|v| v.as_str()) {
+                Some(sketchbook) => format!("Found sketchbook {}", sketchbook),
+                None => "No Arduino IDE sketchbook found".to_string(),
+            },
+            "find-arduino-ide",
+            vec![],
+            Some(description)
+        );
+        ack.send(&response).ok();
+    });
+    // Import the sketches and libraries of the Arduino IDE sketchbook, once
+    socket.on("import-arduino-ide", |Data::<Value>(data), ack: AckSender| {
+        let flag = |field: &str, default: bool| {
+            data.get(field)
+                .and_then(|v| v.as_bool())
+                .unwrap_or(default)
+        };
+        let (sketches, libraries, force) = (
+            flag("sketches", true),
+            flag("libraries", true),
+            flag("force", false),
+        );
+        tokio::spawn(async move {
+            // Copying a whole sketchbook takes a while, keep it off the async workers
+            let result = tokio::task
+                ::spawn_blocking(move || import_ide_sketchbook(sketches, libraries, force)).await
+                .unwrap_or_else(|e| Err(format!("Import failed: {}", e)));
+            let response = match result {
+                Ok(result) =>
+                    create_success_response(
+                        "Arduino IDE sketchbook imported".to_string(),
+                        "import-arduino-ide",
+                        vec![],
+                        Some(result)
+                    ),
+                Err(error_msg) => create_error_response(&error_msg, "import-arduino-ide", vec![]),
+            };
+            ack.send(&response).ok();
+        });
+    });
+    // Read an allowlisted arduino-cli setting, e.g. {key: "network.proxy"}
+    socket.on("config-get", |Data::<Value>(data), ack: AckSender| {
+        let key = match extract_string_field(&data, "key") {
+            Some(key) => key,
+            None => {
+                let error_response = create_error_response("Missing key", "config-get", vec![]);
+                ack.send(&error_response).ok();
+                return;
+            }
+        };
+        tokio::spawn(async move {
+            let response = match get_setting(&key).await {
+                Ok(value) =>
+                    create_success_response(
+                        value.to_string(),
+                        "config-get",
+                        vec![key.clone()],
+                        Some(serde_json::json!({ "key": key, "value": value }))
+                    ),
+                Err(error_msg) => create_error_response(&error_msg, "config-get", vec![key]),
+            };
+            ack.send(&response).ok();
+        });
+    });
+    // Change an allowlisted arduino-cli setting, e.g. {key: "locale", value: "de"}.
+    // The value is kept and applied again when the agent starts.
+    socket.on("config-set", |Data::<Value>(data), ack: AckSender| {
+        let key = match extract_string_field(&data, "key") {
+            Some(key) => key,
+            None => {
+                let error_response = create_error_response("Missing key", "config-set", vec![]);
+                ack.send(&error_response).ok();
+                return;
+            }
+        };
+        let value = match data.get("value") {
+            Some(value) => value.clone(),
+            None => {
+                let error_response = create_error_response(
+                    "Missing value",
+                    "config-set",
+                    vec![key]
+                );
+                ack.send(&error_response).ok();
+                return;
+            }
+        };
+        tokio::spawn(async move {
+            let response = match set_setting(&key, value.clone()).await {
+                Ok(()) =>
+                    create_success_response(
+                        format!("{} set", key),
+                        "config-set",
+                        vec![key.clone()],
+                        Some(serde_json::json!({ "key": key, "value": value }))
+                    ),
+                Err(error_msg) => create_error_response(&error_msg, "config-set", vec![key]),
+            };
+            ack.send(&response).ok();
+        });
+    });
+    // Dump every allowlisted arduino-cli setting
+    socket.on("config-dump", |ack: AckSender| {
+        tokio::spawn(async move {
+            let response = match dump_settings().await {
+                Ok(settings) =>
+                    create_success_response(
+                        format!("{} settings", settings.len()),
+                        "config-dump",
+                        vec![],
+                        Some(serde_json::to_value(settings).unwrap_or(Value::Null))
+                    ),
+                Err(error_msg) => create_error_response(&error_msg, "config-dump", vec![]),
+            };
+            ack.send(&response).ok();
+        });
+    });
+    // Undo the changes to one setting ({key}) or to all settings ({})
+    socket.on("config-reset", |Data::<Value>(data), ack: AckSender| {
+        let key = extract_string_field(&data, "key");
+        tokio::spawn(async move {
+            let args = key.iter().cloned().collect::<Vec<_>>();
+            let response = match reset_settings(key.as_deref()).await {
+                Ok(keys) =>
+                    create_success_response(
+                        format!("{} settings reset", keys.len()),
+                        "config-reset",
+                        args,
+                        Some(serde_json::json!(keys))
+                    ),
+                Err(error_msg) => create_error_response(&error_msg, "config-reset", args),
+            };
+            ack.send(&response).ok();
+        });
+    });
+    // libary commands
+    socket.on("list-libraries", |ack: AckSender| {
+        tokio::spawn(async move {
+            run_arduino_command_async(
+                "lib",
+                vec!["list".to_string(), "--format".to_string(), "json".to_string()],
+                ack
+            ).await;
+        });
+    });
+    // serach for a library
+    socket.on("search-library", |Data::<Value>(data), ack: AckSender| {
+        let library_name = match extract_string_field(&data, "library_name") {
+            Some(name) => name,
+            None => {
+                let error_response = create_error_response(
+                    "Missing library name",
+                    "search-library",
+                    vec![]
+                );
+                ack.send(&error_response).ok();
+                return;
+            }
+        };
+        tokio::spawn(async move {
+            let args = vec![
+                "search".to_string(),
+                library_name,
+                "name".to_string(),
+                "--format".to_string(),
+                "json".to_string()
+            ];
+            run_arduino_command_async("lib", args, ack).await;
+        });
+    });
+    socket.on("install-library", |socket: SocketRef, Data::<Value>(data), ack: AckSender| {
+        let library_name = match extract_string_field(&data, "library_name") {
+            Some(name) => name,
+            None => {
+                let error_response = create_error_response(
+                    "Missing library name",
+                    "install-library",
+                    vec![]
+                );
+                ack.send(&error_response).ok();
+                return;
+            }
+        };
+        // Pin an exact release, e.g. {library_name: "Servo", version: "1.2.1"}
+        let library_name = match extract_string_field(&data, "version") {
+            Some(_) if library_name.contains('@') || library_name.ends_with(".zip") => {
+                let error_response = create_error_response(
+                    "A version cannot be combined with a versioned or ZIP library",
+                    "install-library",
+                    vec![library_name]
+                );
+                ack.send(&error_response).ok();
+                return;
+            }
+            Some(version) => format!("{}@{}", library_name, version),
+            None => library_name,
+        };
+        let no_deps = data
+            .get("no_deps")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+        let job_id = extract_job_id(&data);
+        tokio::spawn(async move {
+            let mut args = vec![
+                "install".to_string()
+                // library_name will be added below
+            ];
+            if no_deps {
+                args.push("--no-deps".to_string());
+            }
+            let mut check: Option<JobCheck> = None;
+            if library_name.ends_with(".zip") {
+                args.push("--zip-path".to_string());
+                let lib_path = get_sketch_directory()
+                    .unwrap_or_else(|_| PathBuf::from("."))
+                    .join("libraries")
+                    .join(&library_name);
+                info!(?lib_path, "Library path to install");
+                args.push(lib_path.to_string_lossy().to_string());
+            } else {
+                // Only roll back a cancelled install if the library was not there before
+                let check_library = library_name.clone();
+                check = Some(
+                    Box::new(move || {
+                        Box::pin(async move {
+                            if is_library_installed(&check_library).await {
+                                return vec![];
+                            }
+                            let library_id = check_library
+                                .split('@')
+                                .next()
+                                .unwrap_or(&check_library)
+                                .to_string();
+                            vec![
+                                JobCleanup::Command(ArduinoCommand {
+                                    command: "lib".to_string(),
+                                    args: vec!["uninstall".to_string(), library_id],
+                                })
+                            ]
+                        })
+                    })
+                );
+                args.push(library_name);
+            }
+            let request = JobRequest {
+                job_id,
+                kind: "install-library".to_string(),
+                sketch: None,
+                port: None,
+                command: ArduinoCommand {
+                    command: "lib".to_string(),
+                    args,
+                },
+                cleanup: vec![],
+                check,
+                prepare: None,
+                finish: None,
+            };
+            run_arduino_job(socket, request, ack).await;
+        });
+    });
+    // List every library installing a library would install or change, before installing it
+    socket.on("library-deps", |Data::<Value>(data), ack: AckSender| {
+        let library_name = match extract_string_field(&data, "library_name") {
+            Some(name) => name,
+            None => {
+                let error_response = create_error_response(
+                    "Missing library name",
+                    "library-deps",
+                    vec![]
+                );
+                ack.send(&error_response).ok();
+                return;
+            }
+        };
+        let library = match extract_string_field(&data, "version") {
+            Some(version) => format!("{}@{}", library_name, version),
+            None => library_name,
+        };
+        tokio::spawn(async move {
+            let response = match resolve_dependencies(&library).await {
+                Ok(dependencies) => {
+                    let changes = dependencies
+                        .iter()
+                        .filter(|dependency| dependency.action != "none")
+                        .count();
+                    create_success_response(
+                        format!("{} libraries would be installed or changed", changes),
+                        "library-deps",
+                        vec![library],
+                        Some(serde_json::to_value(dependencies).unwrap_or(Value::Null))
+                    )
+                }
+                Err(error_msg) => create_error_response(&error_msg, "library-deps", vec![library]),
+            };
+            ack.send(&response).ok();
+        });
+    });
+    // List the installed libraries that have newer releases
+    socket.on("list-outdated-libraries", |ack: AckSender| {
+        tokio::spawn(async move {
+            let response = match list_outdated_libraries().await {
+                Ok(libraries) =>
+                    create_success_response(
+                        format!("{} outdated libraries", libraries.len()),
+                        "list-outdated-libraries",
+                        vec![],
+                        Some(serde_json::to_value(libraries).unwrap_or(Value::Null))
+                    ),
+                Err(error_msg) =>
+                    create_error_response(&error_msg, "list-outdated-libraries", vec![]),
+            };
+            ack.send(&response).ok();
+        });
+    });
+    // Upgrade a library, or every installed library when no library_name is given
+    socket.on("upgrade-library", |socket: SocketRef, Data::<Value>(data), ack: AckSender| {
+        let mut args = vec!["upgrade".to_string()];
+        if let Some(library_name) = extract_string_field(&data, "library_name") {
+            args.push(library_name);
+        }
+        let request = JobRequest {
+            job_id: extract_job_id(&data),
+            kind: "upgrade-library".to_string(),
+            sketch: None,
+            port: None,
+            command: ArduinoCommand {
+                command: "lib".to_string(),
+                args,
+            },
+            cleanup: vec![],
+            check: None,
+            prepare: None,
+            finish: None,
+        };
+        tokio::spawn(async move {
+            run_arduino_job(socket, request, ack).await;
+        });
+    });
+    socket.on("uninstall-library", |socket: SocketRef, Data::<Value>(data), ack: AckSender| {
+        let library_name = match extract_string_field(&data, "library_name") {
+            Some(name) => name,
+            None => {
+                let error_response = create_error_response(
+                    "Missing library name",
+                    "uninstall-library",
+                    vec![]
+                );
+                ack.send(&error_response).ok();
+                return;
+            }
+        };
+        let job_id = extract_job_id(&data);
+        tokio::spawn(async move {
+            let args = vec![
+                "uninstall".to_string(),
+                library_name
+            ];
+            let request = JobRequest {
+                job_id,
+                kind: "uninstall-library".to_string(),
+                sketch: None,
+                port: None,
+                command: ArduinoCommand {
+                    command: "lib".to_string(),
+                    args,
+                },
+                cleanup: vec![],
+                check: None,
+                prepare: None,
+                finish: None,
+            };
+            run_arduino_job(socket, request, ack).await;
+        });
+    });
 }
@@ -0,0 +1,294 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{ Component, Path, PathBuf };
+use include_dir::{ include_dir, Dir };
+use tracing::{ info, error };
+use crate::models::SketchTemplate;
+use crate::builds::is_plain_name;
+use crate::environment::get_agent_home;
+use crate::sketch_paths::{
+    resolve_sketch_dir,
+    PathError,
+    ALREADY_EXISTS,
+    INVALID_TEMPLATE,
+    IO_ERROR,
+    NOT_FOUND,
+};
+use crate::sketch_yaml::{ read_sketch_yaml, write_sketch_yaml };
+
+// Templates that ship with the agent, one folder each
+static BUILTIN_TEMPLATES: Dir<'_> = include_dir!("$CARGO_MANIFEST_DIR/templates");
+
+const TEMPLATE_FILE: &str = "template.json";
+const DEFAULT_MAIN_FILE: &str = "sketch.ino";
+
+// User templates, laid out like the built-in ones. A user template replaces
+// the built-in template with the same folder name.
+pub fn get_user_templates_directory() -> PathBuf {
+    get_agent_home().join("templates")
+}
+
+// A template with the contents of its files, keyed by their path in the template
+struct LoadedTemplate {
+    template: SketchTemplate,
+    files: BTreeMap<String, Vec<u8>>,
+}
+
+fn io_error(message: String) -> PathError {
+    PathError::new(IO_ERROR, message)
+}
+
+fn invalid_template(message: String) -> PathError {
+    PathError::new(INVALID_TEMPLATE, message)
+}
+
+// Path of a file in a template with `/` separators, e.g. "src/motors.h"
+fn template_path(path: &Path) -> String {
+    path.components()
+        .map(|part| part.as_os_str().to_string_lossy().to_string())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn collect_builtin_files(dir: &Dir<'_>, root: &Path, files: &mut BTreeMap<String, Vec<u8>>) {
+    for file in dir.files() {
+        if let Ok(relative) = file.path().strip_prefix(root) {
+            files.insert(template_path(relative), file.contents().to_vec());
+        }
+    }
+    for sub_dir in dir.dirs() {
+        collect_builtin_files(sub_dir, root, files);
+    }
+}
+
+// Symlinks are skipped so a template cannot pull in files from elsewhere
+fn collect_user_files(
+    dir: &Path,
+    root: &Path,
+    files: &mut BTreeMap<String, Vec<u8>>
+) -> Result<(), String> {
+    let entries = fs
+        ::read_dir(dir)
+        .map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?;
+    for entry in entries.flatten() {
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        let path = entry.path();
+        if file_type.is_dir() {
+            collect_user_files(&path, root, files)?;
+        } else if file_type.is_file() {
+            let content = fs
+                ::read(&path)
+                .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+            if let Ok(relative) = path.strip_prefix(root) {
+                files.insert(template_path(relative), content);
+            }
+        }
+    }
+    Ok(())
+}
+
+// Check a template's description against its files
+fn parse_template(
+    id: &str,
+    files: BTreeMap<String, Vec<u8>>,
+    builtin: bool
+) -> Result<LoadedTemplate, String> {
+    let description = files
+        .get(TEMPLATE_FILE)
+        .ok_or_else(|| format!("Template {} has no {}", id, TEMPLATE_FILE))?;
+    let mut template: SketchTemplate = serde_json
+        ::from_slice(description)
+        .map_err(|e| format!("Invalid {} of template {}: {}", TEMPLATE_FILE, id, e))?;
+    let main = template.main.as_deref().unwrap_or(DEFAULT_MAIN_FILE);
+    if !files.contains_key(main) {
+        return Err(format!("Template {} has no main file {}", id, main));
+    }
+    template.id = id.to_string();
+    template.builtin = builtin;
+    template.files = files
+        .keys()
+        .filter(|path| path.as_str() != TEMPLATE_FILE)
+        .cloned()
+        .collect();
+    Ok(LoadedTemplate { template, files })
+}
+
+fn load_builtin_template(id: &str) -> Option<Result<LoadedTemplate, String>> {
+    let dir = BUILTIN_TEMPLATES.get_dir(id)?;
+    let mut files = BTreeMap::new();
+    collect_builtin_files(dir, dir.path(), &mut files);
+    Some(parse_template(id, files, true))
+}
+
+fn load_user_template(id: &str) -> Option<Result<LoadedTemplate, String>> {
+    let dir = get_user_templates_directory().join(id);
+    if !dir.is_dir() {
+        return None;
+    }
+    let mut files = BTreeMap::new();
+    Some(
+        collect_user_files(&dir, &dir, &mut files).and_then(|_| parse_template(id, files, false))
+    )
+}
+
+// The built-in and user templates, sorted by id. Broken user templates are left out.
+pub fn list_templates() -> Vec<SketchTemplate> {
+    let mut templates = BTreeMap::new();
+    for dir in BUILTIN_TEMPLATES.dirs() {
+        let id = dir.path().to_string_lossy().to_string();
+        match load_builtin_template(&id) {
+            Some(Ok(loaded)) => {
+                templates.insert(id, loaded.template);
+            }
+            Some(Err(error_msg)) => error!("{}", error_msg),
+            None => {}
+        }
+    }
+    if let Ok(entries) = fs::read_dir(get_user_templates_directory()) {
+        for entry in entries.flatten() {
+            let id = entry.file_name().to_string_lossy().to_string();
+            if !is_plain_name(&id) {
+                continue;
+            }
+            match load_user_template(&id) {
+                Some(Ok(loaded)) => {
+                    templates.insert(id, loaded.template);
+                }
+                Some(Err(error_msg)) => error!("Skipping user template: {}", error_msg),
+                None => {}
+            }
+        }
+    }
+    templates.into_values().collect()
+}
+
+fn find_template(id: &str) -> Result<LoadedTemplate, PathError> {
+    if !is_plain_name(id) {
+        return Err(invalid_template(format!("Invalid template name {}", id)));
+    }
+    load_user_template(id)
+        .or_else(|| load_builtin_template(id))
+        .ok_or_else(|| PathError::new(NOT_FOUND, format!("Template {} does not exist", id)))?
+        .map_err(invalid_template)
+}
+
+// Replace every {{name}} with its value
+fn fill_placeholders(text: &str, values: &BTreeMap<String, String>) -> String {
+    values.iter().fold(text.to_string(), |text, (name, value)| {
+        text.replace(&format!("{{{{{}}}}}", name), value)
+    })
+}
+
+// A file name after filling in its placeholders, which must stay inside the sketch
+fn sketch_relative_path(path: &str) -> Result<PathBuf, PathError> {
+    let relative = Path::new(path);
+    let plain = relative.components().all(|part| matches!(part, Component::Normal(_)));
+    if path.is_empty() || !plain {
+        return Err(invalid_template(format!("Template file name {:?} is not allowed", path)));
+    }
+    Ok(relative.to_path_buf())
+}
+
+fn write_template_files(
+    staging: &Path,
+    files: &[(PathBuf, Vec<u8>)]
+) -> Result<(), PathError> {
+    for (relative, content) in files {
+        let path = staging.join(relative);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| {
+                io_error(format!("Failed to create {}: {}", parent.display(), e))
+            })?;
+        }
+        fs::write(&path, content).map_err(|e| {
+            io_error(format!("Failed to write {}: {}", path.display(), e))
+        })?;
+    }
+    Ok(())
+}
+
+// Create a sketch from a template. Besides the template's own placeholders,
+// {{sketch_name}}, {{fqbn}} and {{date}} are filled in. The template's default
+// board, or `fqbn` when given, becomes the sketch's default_fqbn.
+pub fn create_sketch_from_template(
+    sketch_name: &str,
+    template_id: &str,
+    fqbn: Option<&str>,
+    values: &BTreeMap<String, String>
+) -> Result<(PathBuf, Vec<String>), PathError> {
+    let LoadedTemplate { template, files } = find_template(template_id)?;
+    let target = resolve_sketch_dir(sketch_name)?;
+    if fs::symlink_metadata(&target).is_ok() {
+        return Err(
+            PathError::new(ALREADY_EXISTS, format!("Sketch {} already exists", sketch_name))
+        );
+    }
+
+    let fqbn = fqbn.map(|fqbn| fqbn.to_string()).or(template.default_fqbn.clone());
+    let mut placeholders = values.clone();
+    for (name, placeholder) in &template.placeholders {
+        if placeholders.contains_key(name) {
+            continue;
+        }
+        let Some(default) = &placeholder.default else {
+            return Err(
+                invalid_template(format!("Template {} needs a value for {}", template_id, name))
+            );
+        };
+        placeholders.insert(name.clone(), default.clone());
+    }
+    placeholders.insert("sketch_name".to_string(), sketch_name.to_string());
+    placeholders.insert("fqbn".to_string(), fqbn.clone().unwrap_or_default());
+    placeholders.insert("date".to_string(), chrono::Local::now().format("%Y-%m-%d").to_string());
+
+    let main = template.main.as_deref().unwrap_or(DEFAULT_MAIN_FILE);
+    let mut sketch_files = Vec::new();
+    for (path, content) in files {
+        if path == TEMPLATE_FILE {
+            continue;
+        }
+        let relative = if path == main {
+            PathBuf::from(format!("{}.ino", sketch_name))
+        } else {
+            sketch_relative_path(&fill_placeholders(&path, &placeholders))?
+        };
+        // Binary files such as images are copied as they are
+        let content = match String::from_utf8(content) {
+            Ok(text) => fill_placeholders(&text, &placeholders).into_bytes(),
+            Err(error) => error.into_bytes(),
+        };
+        sketch_files.push((relative, content));
+    }
+
+    // Write next to the sketchbook first so a failed write leaves no half sketch behind
+    let staging = target.with_file_name(format!(".{}.creating", sketch_name));
+    let _ = fs::remove_dir_all(&staging);
+    write_template_files(&staging, &sketch_files)
+        .and_then(|_| {
+            fs::rename(&staging, &target).map_err(|e| {
+                io_error(format!("Failed to create sketch {}: {}", sketch_name, e))
+            })
+        })
+        .inspect_err(|_| {
+            let _ = fs::remove_dir_all(&staging);
+        })?;
+
+    // A sketch.yaml shipped with the template keeps its own default board
+    if let Some(fqbn) = fqbn {
+        let mut sketch_yaml = read_sketch_yaml(sketch_name)?;
+        if sketch_yaml.default_fqbn.is_none() {
+            sketch_yaml.default_fqbn = Some(fqbn);
+            write_sketch_yaml(sketch_name, &sketch_yaml)?;
+        }
+    }
+    info!("Created sketch {} from template {}", sketch_name, template_id);
+    Ok((
+        target,
+        sketch_files
+            .iter()
+            .map(|(relative, _)| template_path(relative))
+            .collect(),
+    ))
+}
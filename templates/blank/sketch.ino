@@ -0,0 +1,7 @@
+// {{sketch_name}}, created {{date}}
+
+void setup() {
+}
+
+void loop() {
+}
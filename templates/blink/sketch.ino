@@ -0,0 +1,14 @@
+// {{sketch_name}}, created {{date}}
+
+const int LED_PIN = {{led_pin}};
+
+void setup() {
+  pinMode(LED_PIN, OUTPUT);
+}
+
+void loop() {
+  digitalWrite(LED_PIN, HIGH);
+  delay(500);
+  digitalWrite(LED_PIN, LOW);
+  delay(500);
+}
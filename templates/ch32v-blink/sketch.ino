@@ -0,0 +1,12 @@
+// {{sketch_name}}, created {{date}} for {{fqbn}}
+
+void setup() {
+  pinMode({{led_pin}}, OUTPUT);
+}
+
+void loop() {
+  digitalWrite({{led_pin}}, HIGH);
+  delay(500);
+  digitalWrite({{led_pin}}, LOW);
+  delay(500);
+}
@@ -0,0 +1,27 @@
+#pragma once
+
+#include <Arduino.h>
+
+const int LEFT_FORWARD = {{left_forward_pin}};
+const int LEFT_BACKWARD = {{left_backward_pin}};
+const int RIGHT_FORWARD = {{right_forward_pin}};
+const int RIGHT_BACKWARD = {{right_backward_pin}};
+
+inline void setupMotors() {
+  pinMode(LEFT_FORWARD, OUTPUT);
+  pinMode(LEFT_BACKWARD, OUTPUT);
+  pinMode(RIGHT_FORWARD, OUTPUT);
+  pinMode(RIGHT_BACKWARD, OUTPUT);
+}
+
+// Run one motor at a speed from -255 (full backward) to 255 (full forward)
+inline void runMotor(int forwardPin, int backwardPin, int speed) {
+  speed = constrain(speed, -255, 255);
+  analogWrite(forwardPin, speed > 0 ? speed : 0);
+  analogWrite(backwardPin, speed < 0 ? -speed : 0);
+}
+
+inline void drive(int left, int right) {
+  runMotor(LEFT_FORWARD, LEFT_BACKWARD, left);
+  runMotor(RIGHT_FORWARD, RIGHT_BACKWARD, right);
+}
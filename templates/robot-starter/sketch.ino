@@ -0,0 +1,16 @@
+// {{sketch_name}}, created {{date}}
+
+#include "motors.h"
+
+void setup() {
+  setupMotors();
+}
+
+void loop() {
+  drive(200, 200);
+  delay(1000);
+  drive(200, -200);
+  delay(500);
+  drive(0, 0);
+  delay(1000);
+}
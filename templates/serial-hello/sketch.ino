@@ -0,0 +1,10 @@
+// {{sketch_name}}, created {{date}}
+
+void setup() {
+  Serial.begin({{baud_rate}});
+}
+
+void loop() {
+  Serial.println("Hello from {{sketch_name}}");
+  delay(1000);
+}